@@ -99,6 +99,53 @@ fn build_canister_ids(out_dir: &str) -> Result<()> {
     Ok(())
 }
 
+/// Make the agent bindings generic over `CanisterTransport`
+/// so they can be driven by something other than `ic_agent::Agent`
+fn make_transport_generic(service_name: &str, bindings: String) -> String {
+    let agent_struct =
+        format!("pub struct {service_name}<'a>(pub Principal, pub &'a ic_agent::Agent);");
+    let agent_impl = format!("impl<'a> {service_name}<'a> {{");
+    assert!(
+        bindings.contains(&agent_struct) && bindings.contains(&agent_impl),
+        "unexpected agent bindings for {service_name}"
+    );
+
+    let bindings = bindings
+        .replace(
+            &agent_struct,
+            &format!(
+                "pub struct {service_name}<'a, T = ic_agent::Agent>(pub Principal, pub &'a T);"
+            ),
+        )
+        .replace(
+            &agent_impl,
+            &format!("impl<'a, T: crate::CanisterTransport> {service_name}<'a, T> {{"),
+        )
+        .replace("self.1.query(&self.0, ", "self.1.query_raw(&self.0, ")
+        .replace("self.1.update(&self.0, ", "self.1.update_raw(&self.0, ")
+        .replace("\").with_arg(args).call().await", "\", args).await")
+        .replace(
+            "\").with_arg(args).call_and_wait().await",
+            "\", args).await",
+        );
+
+    // a change in the candid generator's output must not silently leave
+    // calls going through `ic_agent::Agent`
+    for leftover in [
+        "ic_agent::Agent);",
+        "self.1.query(&self.0, ",
+        "self.1.update(&self.0, ",
+        ".with_arg(args).call",
+    ] {
+        assert!(
+            !bindings.contains(leftover),
+            "`{leftover}` left in transport generic bindings for {service_name}"
+        );
+    }
+
+    bindings
+}
+
 fn build_did_intfs(out_dir: &str) -> Result<()> {
     println!("cargo:rerurn-if-changed=./did/*");
 
@@ -126,7 +173,7 @@ fn build_did_intfs(out_dir: &str) -> Result<()> {
 
         // compile bindings from did
         let service_name: String = file_name.to_case(Case::Pascal);
-        candid_config.set_service_name(service_name);
+        candid_config.set_service_name(service_name.clone());
         let (type_env, actor) = candid_parser::check_file(&didpath).unwrap_or_else(|e| {
             panic!(
                 "invalid did file: {}, err: {e}",
@@ -134,6 +181,7 @@ fn build_did_intfs(out_dir: &str) -> Result<()> {
             )
         });
         let bindings = candid_parser::bindings::rust::compile(&candid_config, &type_env, &actor);
+        let bindings = make_transport_generic(&service_name, bindings);
 
         // write bindings to $OUT_DIR/did/<did file>.rs
         let mut binding_file = did_dir.clone();
@@ -1,5 +1,7 @@
 //! Auto generated bindings for canisters
 #[allow(clippy::all)]
 mod generated;
+pub mod transport;
 
 pub use generated::*;
+pub use transport::CanisterTransport;
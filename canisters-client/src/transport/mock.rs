@@ -0,0 +1,296 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use candid::{utils::ArgumentEncoder, CandidType, Principal};
+use ic_agent::AgentError;
+use serde::Deserialize;
+
+use super::CanisterTransport;
+
+type MockResult = Box<dyn FnOnce() -> Result<Vec<u8>, AgentError> + Send>;
+
+enum MockReply {
+    /// Answer to the call request itself
+    Now(MockResult),
+    /// The call is accepted, each poll of its status consumes the next result
+    Accepted(VecDeque<MockResult>),
+}
+
+/// Replies queued per (canister, method)
+type MockScript = HashMap<(Principal, String), VecDeque<MockReply>>;
+
+/// Id of an update accepted by [`MockTransport::submit_update`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MockRequestId(u64);
+
+/// Outcome of [`MockTransport::submit_update`]
+#[derive(Debug)]
+pub enum MockSubmission {
+    Replied(Vec<u8>),
+    Accepted(MockRequestId),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallKind {
+    Query,
+    Update,
+}
+
+/// A call received by [`MockTransport`]
+#[derive(Clone, Debug)]
+pub struct MockCall {
+    pub kind: CallKind,
+    pub canister: Principal,
+    pub method: String,
+    pub args: Vec<u8>,
+}
+
+impl MockCall {
+    /// Decode the (single) candid argument of this call
+    pub fn decode_arg<'a, T: Deserialize<'a> + CandidType>(&'a self) -> candid::Result<T> {
+        candid::decode_one(&self.args)
+    }
+}
+
+/// In-memory transport that replies with scripted candid values
+///
+/// Replies are queued per (canister, method) and consumed in order,
+/// calls without a queued reply fail with [`AgentError::MessageError`].
+/// Clones share the same script and call log
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    replies: Arc<Mutex<MockScript>>,
+    calls: Arc<Mutex<Vec<MockCall>>>,
+    accepted: Arc<Mutex<HashMap<MockRequestId, VecDeque<MockResult>>>>,
+    next_request_id: Arc<Mutex<u64>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push_reply(&self, canister: Principal, method: &str, reply: MockReply) {
+        self.replies
+            .lock()
+            .unwrap()
+            .entry((canister, method.to_string()))
+            .or_default()
+            .push_back(reply);
+    }
+
+    /// Queue `reply` for the next call to `method` on `canister`
+    pub fn reply_with(&self, canister: Principal, method: &str, reply: impl CandidType) {
+        let bytes = candid::encode_one(reply).expect("mock reply should serialize");
+        self.push_reply(
+            canister,
+            method,
+            MockReply::Now(Box::new(move || Ok(bytes))),
+        );
+    }
+
+    /// Queue a reply with multiple return values
+    pub fn reply_with_args(&self, canister: Principal, method: &str, reply: impl ArgumentEncoder) {
+        let bytes = candid::encode_args(reply).expect("mock reply should serialize");
+        self.push_reply(
+            canister,
+            method,
+            MockReply::Now(Box::new(move || Ok(bytes))),
+        );
+    }
+
+    /// Queue a failure for the next call to `method` on `canister`
+    pub fn fail_with(&self, canister: Principal, method: &str, err: AgentError) {
+        self.push_reply(canister, method, MockReply::Now(Box::new(move || Err(err))));
+    }
+
+    /// Accept the next update to `method` on `canister`, the first poll of its
+    /// status fails with `err` and the next one returns `reply`
+    pub fn fail_after_accept(
+        &self,
+        canister: Principal,
+        method: &str,
+        err: AgentError,
+        reply: impl CandidType,
+    ) {
+        let bytes = candid::encode_one(reply).expect("mock reply should serialize");
+        let polls: VecDeque<MockResult> = VecDeque::from([
+            Box::new(move || Err(err)) as MockResult,
+            Box::new(move || Ok(bytes)),
+        ]);
+        self.push_reply(canister, method, MockReply::Accepted(polls));
+    }
+
+    /// All calls received so far, in order
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Calls received for `method` on `canister`
+    pub fn calls_to(&self, canister: Principal, method: &str) -> Vec<MockCall> {
+        self.calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|call| call.canister == canister && call.method == method)
+            .cloned()
+            .collect()
+    }
+
+    fn next_reply(
+        &self,
+        kind: CallKind,
+        canister: &Principal,
+        method: &str,
+        args: Vec<u8>,
+    ) -> Option<MockReply> {
+        self.calls.lock().unwrap().push(MockCall {
+            kind,
+            canister: *canister,
+            method: method.to_string(),
+            args,
+        });
+
+        self.replies
+            .lock()
+            .unwrap()
+            .get_mut(&(*canister, method.to_string()))
+            .and_then(VecDeque::pop_front)
+    }
+
+    /// Send an update without waiting for it to be executed
+    pub fn submit_update(
+        &self,
+        canister: &Principal,
+        method: &str,
+        args: Vec<u8>,
+    ) -> Result<MockSubmission, AgentError> {
+        match self.next_reply(CallKind::Update, canister, method, args) {
+            Some(MockReply::Now(reply)) => reply().map(MockSubmission::Replied),
+            Some(MockReply::Accepted(polls)) => {
+                let mut next = self.next_request_id.lock().unwrap();
+                let id = MockRequestId(*next);
+                *next += 1;
+                self.accepted.lock().unwrap().insert(id, polls);
+                Ok(MockSubmission::Accepted(id))
+            }
+            None => Err(no_reply(canister, method)),
+        }
+    }
+
+    /// Poll the status of an update accepted by [`Self::submit_update`]
+    pub fn poll_update(&self, id: MockRequestId) -> Result<Vec<u8>, AgentError> {
+        let poll = self
+            .accepted
+            .lock()
+            .unwrap()
+            .get_mut(&id)
+            .and_then(VecDeque::pop_front);
+        match poll {
+            Some(poll) => poll(),
+            None => Err(AgentError::MessageError(format!(
+                "no mock status for request {id:?}"
+            ))),
+        }
+    }
+}
+
+fn no_reply(canister: &Principal, method: &str) -> AgentError {
+    AgentError::MessageError(format!("no mock reply for {method} on {canister}"))
+}
+
+impl CanisterTransport for MockTransport {
+    async fn query_raw(
+        &self,
+        canister: &Principal,
+        method: &str,
+        args: Vec<u8>,
+    ) -> Result<Vec<u8>, AgentError> {
+        match self.next_reply(CallKind::Query, canister, method, args) {
+            Some(MockReply::Now(reply)) => reply(),
+            Some(MockReply::Accepted(_)) => Err(AgentError::MessageError(format!(
+                "queries can't be accepted, {method} on {canister}"
+            ))),
+            None => Err(no_reply(canister, method)),
+        }
+    }
+
+    async fn update_raw(
+        &self,
+        canister: &Principal,
+        method: &str,
+        args: Vec<u8>,
+    ) -> Result<Vec<u8>, AgentError> {
+        match self.submit_update(canister, method, args)? {
+            MockSubmission::Replied(reply) => Ok(reply),
+            MockSubmission::Accepted(id) => self.poll_update(id),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sns-ledger"))]
+mod tests {
+    use candid::Nat;
+
+    use super::*;
+    use crate::sns_ledger::{Account, SnsLedger, TransferArg, TransferResult};
+
+    fn account(owner: Principal) -> Account {
+        Account {
+            owner,
+            subaccount: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generated_query_uses_transport() {
+        let transport = MockTransport::new();
+        let ledger = Principal::from_slice(&[1]);
+        let owner = Principal::from_slice(&[2]);
+        transport.reply_with(ledger, "icrc1_balance_of", Nat::from(42u64));
+
+        let balance = SnsLedger(ledger, &transport)
+            .icrc_1_balance_of(account(owner))
+            .await
+            .unwrap();
+
+        assert_eq!(balance, Nat::from(42u64));
+        let calls = transport.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].kind, CallKind::Query);
+        assert_eq!(calls[0].method, "icrc1_balance_of");
+        assert_eq!(calls[0].decode_arg::<Account>().unwrap(), account(owner));
+    }
+
+    #[tokio::test]
+    async fn test_generated_update_uses_transport() {
+        let transport = MockTransport::new();
+        let ledger = Principal::from_slice(&[1]);
+        let to = Principal::from_slice(&[2]);
+        transport.reply_with(
+            ledger,
+            "icrc1_transfer",
+            TransferResult::Ok(Nat::from(3u64)),
+        );
+
+        let res = SnsLedger(ledger, &transport)
+            .icrc_1_transfer(TransferArg {
+                to: account(to),
+                fee: None,
+                memo: None,
+                from_subaccount: None,
+                created_at_time: None,
+                amount: Nat::from(100u64),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(res, TransferResult::Ok(Nat::from(3u64)));
+        let calls = transport.calls_to(ledger, "icrc1_transfer");
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].kind, CallKind::Update);
+        assert_eq!(calls[0].decode_arg::<TransferArg>().unwrap().amount, 100u64);
+    }
+}
@@ -0,0 +1,50 @@
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
+use std::future::Future;
+
+use candid::Principal;
+use ic_agent::{Agent, AgentError};
+
+/// Raw transport used by the generated canister bindings
+///
+/// Arguments and replies are candid encoded, the bindings take care of
+/// encoding and decoding
+pub trait CanisterTransport {
+    fn query_raw(
+        &self,
+        canister: &Principal,
+        method: &str,
+        args: Vec<u8>,
+    ) -> impl Future<Output = Result<Vec<u8>, AgentError>>;
+
+    fn update_raw(
+        &self,
+        canister: &Principal,
+        method: &str,
+        args: Vec<u8>,
+    ) -> impl Future<Output = Result<Vec<u8>, AgentError>>;
+}
+
+impl CanisterTransport for Agent {
+    async fn query_raw(
+        &self,
+        canister: &Principal,
+        method: &str,
+        args: Vec<u8>,
+    ) -> Result<Vec<u8>, AgentError> {
+        self.query(canister, method).with_arg(args).call().await
+    }
+
+    async fn update_raw(
+        &self,
+        canister: &Principal,
+        method: &str,
+        args: Vec<u8>,
+    ) -> Result<Vec<u8>, AgentError> {
+        self.update(canister, method)
+            .with_arg(args)
+            .call_and_wait()
+            .await
+    }
+}
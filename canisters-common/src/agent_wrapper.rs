@@ -1,37 +1,248 @@
 use std::sync::Arc;
 
 use candid::Principal;
-use ic_agent::{agent::AgentBuilder, Agent, Identity};
+#[cfg(any(test, feature = "mock"))]
+use canisters_client::transport::mock::{MockRequestId, MockSubmission, MockTransport};
+use canisters_client::CanisterTransport;
+use ic_agent::{
+    agent::{AgentBuilder, CallResponse},
+    Agent, AgentError, Identity, RequestId,
+};
 
-use crate::consts::AGENT_URL;
+use crate::{error::agent_error_kind, CanistersConfig, ErrorKind, RetryPolicy};
 
 #[derive(Clone)]
-pub struct AgentWrapper(Agent);
+pub struct AgentWrapper {
+    agent: Agent,
+    /// Canister calls go here instead of `agent` when set
+    #[cfg(any(test, feature = "mock"))]
+    mock: Option<MockTransport>,
+    fetch_root_key: bool,
+    retry: RetryPolicy,
+}
 
 impl AgentWrapper {
-    pub fn build(builder_func: impl FnOnce(AgentBuilder) -> AgentBuilder) -> Self {
-        let mut builder = Agent::builder().with_url(AGENT_URL);
+    pub fn build(
+        config: &CanistersConfig,
+        builder_func: impl FnOnce(AgentBuilder) -> AgentBuilder,
+    ) -> Self {
+        let mut builder = Agent::builder().with_url(config.agent_url.clone());
         builder = builder_func(builder);
-        Self(builder.build().unwrap())
+        Self {
+            agent: builder.build().unwrap(),
+            #[cfg(any(test, feature = "mock"))]
+            mock: None,
+            fetch_root_key: config.fetch_root_key,
+            retry: config.retry.clone(),
+        }
+    }
+
+    /// Route all canister calls to `transport` instead of the network
+    ///
+    /// the agent is still built from `config` and holds the identity,
+    /// but never fetches the root key
+    #[cfg(any(test, feature = "mock"))]
+    pub fn mock(config: &CanistersConfig, transport: MockTransport) -> Self {
+        Self {
+            mock: Some(transport),
+            fetch_root_key: false,
+            ..Self::build(config, |b| b)
+        }
     }
 
+    /// Get the underlying agent, calls made on it directly
+    /// bypass the mock transport and the retry policy
     pub async fn get_agent(&self) -> &Agent {
-        let agent = &self.0;
-        #[cfg(feature = "local")]
-        {
-            agent
-                .fetch_root_key()
-                .await
-                .expect("AGENT: fetch_root_key failed");
+        self.ready().await.expect("AGENT: fetch_root_key failed")
+    }
+
+    /// The agent with the root key fetched if needed
+    async fn ready(&self) -> Result<&Agent, AgentError> {
+        if self.fetch_root_key {
+            self.agent.fetch_root_key().await?;
         }
-        agent
+        Ok(&self.agent)
     }
 
     pub fn set_arc_id(&mut self, id: Arc<impl Identity + 'static>) {
-        self.0.set_arc_identity(id);
+        self.agent.set_arc_identity(id);
     }
 
     pub fn principal(&self) -> Result<Principal, String> {
-        self.0.get_principal()
+        self.agent.get_principal()
+    }
+}
+
+impl AgentWrapper {
+    async fn query_once(
+        &self,
+        canister: &Principal,
+        method: &str,
+        args: Vec<u8>,
+    ) -> Result<Vec<u8>, AgentError> {
+        #[cfg(any(test, feature = "mock"))]
+        if let Some(transport) = &self.mock {
+            return transport.query_raw(canister, method, args).await;
+        }
+        self.ready().await?.query_raw(canister, method, args).await
+    }
+
+    /// Send an update, without waiting for it to be executed
+    async fn submit_update(
+        &self,
+        canister: &Principal,
+        method: &str,
+        args: Vec<u8>,
+    ) -> Result<Submitted, AgentError> {
+        #[cfg(any(test, feature = "mock"))]
+        if let Some(transport) = &self.mock {
+            return Ok(match transport.submit_update(canister, method, args)? {
+                MockSubmission::Replied(reply) => Submitted::Replied(reply),
+                MockSubmission::Accepted(request_id) => Submitted::Mock(request_id),
+            });
+        }
+        let res = self
+            .ready()
+            .await?
+            .update(canister, method)
+            .with_arg(args)
+            .call()
+            .await?;
+        Ok(match res {
+            CallResponse::Response(reply) => Submitted::Replied(reply),
+            CallResponse::Poll(request_id) => Submitted::Agent(request_id),
+        })
+    }
+
+    /// Wait for an accepted update, polling never submits the update again
+    async fn poll_update(
+        &self,
+        canister: &Principal,
+        submitted: &Submitted,
+    ) -> Result<Vec<u8>, AgentError> {
+        match submitted {
+            Submitted::Replied(reply) => Ok(reply.clone()),
+            Submitted::Agent(request_id) => self.ready().await?.wait(request_id, *canister).await,
+            #[cfg(any(test, feature = "mock"))]
+            Submitted::Mock(request_id) => self
+                .mock
+                .as_ref()
+                .expect("mock request ids come from the mock transport")
+                .poll_update(*request_id),
+        }
+    }
+}
+
+enum Submitted {
+    Replied(Vec<u8>),
+    Agent(RequestId),
+    #[cfg(any(test, feature = "mock"))]
+    Mock(MockRequestId),
+}
+
+/// The replica rejected the call request, the update was not executed
+///
+/// only holds for errors returned by the call request, a 429 while polling
+/// says nothing about the update
+fn is_rate_limited(e: &AgentError) -> bool {
+    matches!(e, AgentError::HttpError(payload) if payload.status == 429)
+}
+
+impl CanisterTransport for AgentWrapper {
+    async fn query_raw(
+        &self,
+        canister: &Principal,
+        method: &str,
+        args: Vec<u8>,
+    ) -> Result<Vec<u8>, AgentError> {
+        self.retry
+            .retry(
+                |e| agent_error_kind(e) == ErrorKind::Transient,
+                || self.query_once(canister, method, args.clone()),
+            )
+            .await
+    }
+
+    async fn update_raw(
+        &self,
+        canister: &Principal,
+        method: &str,
+        args: Vec<u8>,
+    ) -> Result<Vec<u8>, AgentError> {
+        let submitted = self
+            .retry
+            .retry(
+                |e| {
+                    is_rate_limited(e)
+                        || (self.retry.retry_updates && agent_error_kind(e) == ErrorKind::Transient)
+                },
+                || self.submit_update(canister, method, args.clone()),
+            )
+            .await?;
+
+        // polling is a read, retrying it can't execute the update twice
+        self.retry
+            .retry(
+                |e| agent_error_kind(e) == ErrorKind::Transient,
+                || self.poll_update(canister, &submitted),
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_agent::agent_error::HttpErrorPayload;
+    use web_time::Duration;
+
+    use super::*;
+
+    fn rate_limited() -> AgentError {
+        AgentError::HttpError(HttpErrorPayload {
+            status: 429,
+            content_type: None,
+            content: vec![],
+        })
+    }
+
+    fn mock_agent(transport: &MockTransport) -> AgentWrapper {
+        let config = CanistersConfig::default().with_retry_policy(RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            jitter: false,
+            ..Default::default()
+        });
+        AgentWrapper::mock(&config, transport.clone())
+    }
+
+    #[tokio::test]
+    async fn test_update_retried_when_rate_limited_on_submit() {
+        let transport = MockTransport::new();
+        let canister = Principal::anonymous();
+        transport.fail_with(canister, "vote", rate_limited());
+        transport.reply_with(canister, "vote", 7u64);
+
+        let reply = mock_agent(&transport)
+            .update_raw(&canister, "vote", candid::encode_one(1u64).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(candid::decode_one::<u64>(&reply).unwrap(), 7);
+        assert_eq!(transport.calls_to(canister, "vote").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_update_not_resubmitted_when_rate_limited_after_accept() {
+        let transport = MockTransport::new();
+        let canister = Principal::anonymous();
+        transport.fail_after_accept(canister, "vote", rate_limited(), 7u64);
+
+        let reply = mock_agent(&transport)
+            .update_raw(&canister, "vote", candid::encode_one(1u64).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(candid::decode_one::<u64>(&reply).unwrap(), 7);
+        assert_eq!(transport.calls_to(canister, "vote").len(), 1);
     }
 }
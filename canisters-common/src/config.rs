@@ -0,0 +1,187 @@
+use candid::Principal;
+use url::Url;
+use web_time::Duration;
+
+use crate::{
+    consts::{self, VIDEO_STREAM_BASE_URL},
+    RetryPolicy,
+};
+
+/// Endpoints used by [`crate::Canisters`] to reach the IC and Yral's off-chain services
+///
+/// The [`Default`] config targets the endpoints selected by the `local` feature,
+/// use [`CanistersConfig::remote`] or [`CanistersConfig::local`] to pick one at runtime
+#[derive(Clone, Debug)]
+pub struct CanistersConfig {
+    pub agent_url: String,
+    pub canister_ids: CanisterIds,
+    /// Fetch the root key before talking to the replica
+    /// must only be enabled for local replicas
+    pub fetch_root_key: bool,
+    pub metadata_api_base: Url,
+    pub pump_and_dump_worker_url: Url,
+    pub hon_worker_url: Url,
+    pub video_stream_base: Url,
+    /// Retry policy for canister calls and idempotent worker requests
+    pub retry: RetryPolicy,
+    /// Settings for the HTTP client shared by all worker requests
+    pub http: HttpConfig,
+}
+
+/// Ids of Yral's backend canisters on the network at [`CanistersConfig::agent_url`]
+#[derive(Clone, Debug)]
+pub struct CanisterIds {
+    pub platform_orchestrator: Principal,
+    pub post_cache: Principal,
+    /// User index used for every subnet,
+    /// `None` to ask the platform orchestrator for the subnet indexes
+    pub user_index: Option<Principal>,
+}
+
+impl CanisterIds {
+    /// Canisters deployed on mainnet
+    pub fn ic() -> Self {
+        use canisters_client::ic::{PLATFORM_ORCHESTRATOR_ID, POST_CACHE_ID};
+        Self {
+            platform_orchestrator: PLATFORM_ORCHESTRATOR_ID,
+            post_cache: POST_CACHE_ID,
+            user_index: None,
+        }
+    }
+
+    /// Canisters deployed on a local replica
+    pub fn local() -> Self {
+        use canisters_client::local::{PLATFORM_ORCHESTRATOR_ID, POST_CACHE_ID, USER_INDEX_ID};
+        Self {
+            platform_orchestrator: PLATFORM_ORCHESTRATOR_ID,
+            post_cache: POST_CACHE_ID,
+            user_index: Some(USER_INDEX_ID),
+        }
+    }
+}
+
+/// Settings for the HTTP client used to reach Yral's workers
+///
+/// timeouts and proxy only apply outside the browser (wasm32)
+#[derive(Clone, Debug, Default)]
+pub struct HttpConfig {
+    pub timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    pub user_agent: Option<String>,
+    pub proxy: Option<Url>,
+}
+
+impl HttpConfig {
+    pub fn build_client(&self) -> reqwest::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent.clone());
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            if let Some(timeout) = self.timeout {
+                builder = builder.timeout(timeout);
+            }
+            if let Some(connect_timeout) = self.connect_timeout {
+                builder = builder.connect_timeout(connect_timeout);
+            }
+            if let Some(proxy) = &self.proxy {
+                builder = builder.proxy(reqwest::Proxy::all(proxy.clone())?);
+            }
+        }
+        builder.build()
+    }
+}
+
+impl Default for CanistersConfig {
+    fn default() -> Self {
+        #[cfg(feature = "local")]
+        {
+            Self::local()
+        }
+        #[cfg(not(feature = "local"))]
+        {
+            Self::remote()
+        }
+    }
+}
+
+impl CanistersConfig {
+    /// Config for mainnet (ic0.app) and production workers
+    pub fn remote() -> Self {
+        Self {
+            agent_url: consts::remote::AGENT_URL.to_string(),
+            canister_ids: CanisterIds::ic(),
+            fetch_root_key: false,
+            metadata_api_base: consts::remote::METADATA_API_BASE.clone(),
+            pump_and_dump_worker_url: consts::remote::PUMP_AND_DUMP_WORKER_URL.clone(),
+            hon_worker_url: hon_worker_common::WORKER_URL.parse().unwrap(),
+            video_stream_base: VIDEO_STREAM_BASE_URL.parse().unwrap(),
+            retry: RetryPolicy::default(),
+            http: HttpConfig::default(),
+        }
+    }
+
+    /// Config for a local replica, metadata service and pump-n-dump worker
+    ///
+    /// the hot-or-not worker has no local default and stays on production,
+    /// use [`Self::with_hon_worker_url`] to point it at a local one
+    pub fn local() -> Self {
+        Self {
+            agent_url: consts::local::AGENT_URL.to_string(),
+            canister_ids: CanisterIds::local(),
+            fetch_root_key: true,
+            metadata_api_base: consts::local::METADATA_API_BASE.clone(),
+            pump_and_dump_worker_url: consts::local::PUMP_AND_DUMP_WORKER_URL.clone(),
+            hon_worker_url: hon_worker_common::WORKER_URL.parse().unwrap(),
+            video_stream_base: VIDEO_STREAM_BASE_URL.parse().unwrap(),
+            retry: RetryPolicy::default(),
+            http: HttpConfig::default(),
+        }
+    }
+
+    pub fn with_agent_url(mut self, agent_url: impl Into<String>) -> Self {
+        self.agent_url = agent_url.into();
+        self
+    }
+
+    pub fn with_canister_ids(mut self, canister_ids: CanisterIds) -> Self {
+        self.canister_ids = canister_ids;
+        self
+    }
+
+    pub fn with_fetch_root_key(mut self, fetch_root_key: bool) -> Self {
+        self.fetch_root_key = fetch_root_key;
+        self
+    }
+
+    pub fn with_metadata_api_base(mut self, metadata_api_base: Url) -> Self {
+        self.metadata_api_base = metadata_api_base;
+        self
+    }
+
+    pub fn with_pump_and_dump_worker_url(mut self, pump_and_dump_worker_url: Url) -> Self {
+        self.pump_and_dump_worker_url = pump_and_dump_worker_url;
+        self
+    }
+
+    pub fn with_hon_worker_url(mut self, hon_worker_url: Url) -> Self {
+        self.hon_worker_url = hon_worker_url;
+        self
+    }
+
+    pub fn with_video_stream_base(mut self, video_stream_base: Url) -> Self {
+        self.video_stream_base = video_stream_base;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_http_config(mut self, http: HttpConfig) -> Self {
+        self.http = http;
+        self
+    }
+}
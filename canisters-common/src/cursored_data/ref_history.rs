@@ -76,3 +76,134 @@ impl CursoredDataProvider for ReferralHistory {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use canisters_client::{
+        individual_user_template::{PaginationError, SystemTime},
+        transport::mock::MockTransport,
+    };
+    use ic_agent::{identity::Secp256k1Identity, Identity};
+    use k256::SecretKey;
+    use types::delegated_identity::DelegatedIdentityWire;
+
+    use super::*;
+    use crate::{utils::profile::ProfileDetails, CanistersConfig};
+
+    const METHOD: &str = "get_user_utility_token_transaction_history_with_pagination";
+
+    fn referral(secs: u64, referee: Principal, amount: u64) -> TokenEvent {
+        TokenEvent::Mint {
+            timestamp: SystemTime {
+                nanos_since_epoch: 0,
+                secs_since_epoch: secs,
+            },
+            details: MintEvent::Referral {
+                referrer_user_principal_id: Principal::anonymous(),
+                referee_user_principal_id: referee,
+            },
+            amount,
+        }
+    }
+
+    fn mock_history(transport: &MockTransport, user_canister: Principal) -> ReferralHistory {
+        let secret = SecretKey::from_slice(&[7; 32]).unwrap();
+        let id = DelegatedIdentityWire {
+            from_key: Secp256k1Identity::from_private_key(secret.clone())
+                .public_key()
+                .unwrap(),
+            to_secret: secret.to_jwk(),
+            delegation_chain: vec![],
+        };
+        let profile = ProfileDetails {
+            username: None,
+            lifetime_earnings: 0,
+            followers_cnt: 0,
+            following_cnt: 0,
+            profile_pic: None,
+            display_name: None,
+            principal: Principal::anonymous(),
+            hots: 0,
+            nots: 0,
+        };
+        let canisters = Canisters::mock_authenticated(
+            CanistersConfig::default(),
+            transport.clone(),
+            id,
+            user_canister,
+            profile,
+        )
+        .unwrap();
+        ReferralHistory(canisters)
+    }
+
+    #[tokio::test]
+    async fn test_referral_history_page() {
+        let user_canister = Principal::from_slice(&[1]);
+        let referee = Principal::from_slice(&[2]);
+        let transport = MockTransport::new();
+        transport.reply_with(
+            user_canister,
+            METHOD,
+            Result12::Ok(vec![(0, referral(10, referee, 500)), (1, TokenEvent::Burn)]),
+        );
+
+        let page = mock_history(&transport, user_canister)
+            .get_by_cursor(0, 10)
+            .await
+            .unwrap();
+
+        assert!(page.end);
+        assert_eq!(page.data.len(), 1);
+        assert_eq!(page.data[0].epoch_secs, 10);
+        assert_eq!(page.data[0].referee, referee);
+        assert_eq!(page.data[0].amount, 500);
+
+        let calls = transport.calls_to(user_canister, METHOD);
+        assert_eq!(calls.len(), 1);
+        let (from, end) = candid::decode_args::<(u64, u64)>(&calls[0].args).unwrap();
+        assert_eq!((from, end), (0, 10));
+    }
+
+    #[tokio::test]
+    async fn test_referral_history_full_page_not_end() {
+        let user_canister = Principal::from_slice(&[1]);
+        let referee = Principal::from_slice(&[2]);
+        let transport = MockTransport::new();
+        transport.reply_with(
+            user_canister,
+            METHOD,
+            Result12::Ok(vec![
+                (0, referral(10, referee, 500)),
+                (1, referral(11, referee, 500)),
+            ]),
+        );
+
+        let page = mock_history(&transport, user_canister)
+            .get_by_cursor(0, 2)
+            .await
+            .unwrap();
+
+        assert!(!page.end);
+        assert_eq!(page.data.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_referral_history_pagination_error() {
+        let user_canister = Principal::from_slice(&[1]);
+        let transport = MockTransport::new();
+        transport.reply_with(
+            user_canister,
+            METHOD,
+            Result12::Err(PaginationError::ReachedEndOfItemsList),
+        );
+
+        let page = mock_history(&transport, user_canister)
+            .get_by_cursor(0, 10)
+            .await
+            .unwrap();
+
+        assert!(page.end);
+        assert!(page.data.is_empty());
+    }
+}
@@ -2,10 +2,9 @@ use std::sync::Mutex;
 
 use candid::Principal;
 use canisters_client::individual_user_template::PlacedBetDetail;
-use hon_worker_common::{GameRes, PaginatedGamesReq, PaginatedGamesRes, WORKER_URL};
-use url::Url;
+use hon_worker_common::{GameRes, PaginatedGamesReq, PaginatedGamesRes};
 
-use crate::{utils::vote::VoteDetails, Canisters, Error};
+use crate::{error::hon_worker_error_kind, utils::vote::VoteDetails, Canisters, Error, ErrorKind};
 
 use super::{CursoredDataProvider, KeyedData, PageEntry};
 
@@ -105,6 +104,7 @@ impl KeyedData for GameRes {
 pub struct VotesWithSatsProvider {
     // Mutex because we need to track next internally without mut ref.
     next: Mutex<Option<String>>,
+    canisters: Canisters<false>,
     user_principal: Principal,
 }
 
@@ -117,6 +117,7 @@ impl Clone for VotesWithSatsProvider {
 
         Self {
             next: Mutex::new(next),
+            canisters: self.canisters.clone(),
             user_principal,
         }
     }
@@ -131,21 +132,23 @@ impl CursoredDataProvider for VotesWithSatsProvider {
         start: usize,
         end: usize,
     ) -> Result<PageEntry<Self::Data>, Self::Error> {
-        // TODO make lazy static.
-        // i tried earlier, but there was some wasm related error?
-        // give it another shot in isloation
-        let url: Url = WORKER_URL.parse().unwrap();
-        let path = format!("/games/{}", self.user_principal);
-        let url = url.join(&path).unwrap();
         let cursor = self.get_cursor();
         let req = PaginatedGamesReq {
             page_size: end - start,
             cursor,
         };
 
-        let client = reqwest::Client::new();
-        let PaginatedGamesRes { games, next }: PaginatedGamesRes =
-            client.post(url).json(&req).send().await?.json().await?;
+        let hon_worker = &self.canisters.hon_worker();
+        let req = &req;
+        let PaginatedGamesRes { games, next } = self
+            .canisters
+            .config()
+            .retry
+            .retry(
+                |e| hon_worker_error_kind(e) == ErrorKind::Transient,
+                || hon_worker.games_paginated(self.user_principal, req),
+            )
+            .await?;
 
         let end = next.is_none();
 
@@ -156,8 +159,9 @@ impl CursoredDataProvider for VotesWithSatsProvider {
 }
 
 impl VotesWithSatsProvider {
-    pub fn new(user_principal: Principal) -> Self {
+    pub fn new(canisters: Canisters<false>, user_principal: Principal) -> Self {
         Self {
+            canisters,
             user_principal,
             next: Mutex::new(None),
         }
@@ -1,6 +1,8 @@
 use std::{io, str::FromStr};
 
 use candid::Nat;
+use canisters_client::individual_user_template::BetOnCurrentlyViewingPostError;
+use ic_agent::{export::PrincipalError, AgentError};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -9,6 +11,8 @@ pub enum PndError {
     Parse(<Nat as FromStr>::Err),
     #[error("network error when accessing worker: {0}")]
     Network(#[from] reqwest::Error),
+    #[error("{0}")]
+    Worker(#[from] pump_n_dump_common::client::ClientError),
 }
 
 #[derive(Debug, Error)]
@@ -19,6 +23,85 @@ pub enum HonError {
     Backend(String),
 }
 
+/// Errors reported by yral canisters
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum CanisterError {
+    #[error("user does not have sufficient balance")]
+    InsufficientBalance,
+    #[error("user has already voted on this post")]
+    AlreadyVoted,
+    #[error("betting is closed for this post")]
+    BettingClosed,
+    #[error("user is not logged in")]
+    UserNotLoggedIn,
+    #[error("user principal is not set")]
+    UserPrincipalNotSet,
+    #[error("unauthorized")]
+    Unauthorized,
+    #[error("call to post creator's canister failed")]
+    PostCreatorCanisterCallFailed,
+    #[error("{0} not found")]
+    NotFound(String),
+    /// Text error from a canister method without a typed error (e.g `Result_`),
+    /// not classified further
+    #[error("{0}")]
+    Other(String),
+}
+
+impl CanisterError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::InsufficientBalance => ErrorKind::InsufficientFunds,
+            Self::AlreadyVoted => ErrorKind::Duplicate,
+            Self::BettingClosed => ErrorKind::Closed,
+            Self::UserNotLoggedIn | Self::UserPrincipalNotSet | Self::Unauthorized => {
+                ErrorKind::Unauthorized
+            }
+            Self::NotFound(_) => ErrorKind::NotFound,
+            // the bet may already be recorded in the user's canister,
+            // repeating it is not known to be safe
+            Self::PostCreatorCanisterCallFailed | Self::Other(_) => ErrorKind::Other,
+        }
+    }
+}
+
+impl From<BetOnCurrentlyViewingPostError> for CanisterError {
+    fn from(value: BetOnCurrentlyViewingPostError) -> Self {
+        match value {
+            BetOnCurrentlyViewingPostError::UserPrincipalNotSet => Self::UserPrincipalNotSet,
+            BetOnCurrentlyViewingPostError::InsufficientBalance => Self::InsufficientBalance,
+            BetOnCurrentlyViewingPostError::UserAlreadyParticipatedInThisPost => Self::AlreadyVoted,
+            BetOnCurrentlyViewingPostError::BettingClosed => Self::BettingClosed,
+            BetOnCurrentlyViewingPostError::Unauthorized => Self::Unauthorized,
+            BetOnCurrentlyViewingPostError::PostCreatorCanisterCallFailed => {
+                Self::PostCreatorCanisterCallFailed
+            }
+            BetOnCurrentlyViewingPostError::UserNotLoggedIn => Self::UserNotLoggedIn,
+        }
+    }
+}
+
+/// Coarse classification of [`Error`], stable across variants
+/// for use in UIs and retry logic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// Network, replica or service hiccup, the request may succeed if retried
+    Transient,
+    /// The user does not have enough funds
+    InsufficientFunds,
+    /// The action was already performed (e.g already voted)
+    Duplicate,
+    /// The action is no longer possible (e.g betting closed)
+    Closed,
+    /// The requested entity does not exist
+    NotFound,
+    /// The caller is not allowed to perform the action
+    Unauthorized,
+    /// Malformed data was sent or received
+    InvalidData,
+    Other,
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("{0}")]
@@ -28,7 +111,7 @@ pub enum Error {
     #[error("{0}")]
     Metadata(#[from] yral_metadata_client::Error),
     #[error("error from yral canister: {0}")]
-    YralCanister(String),
+    YralCanister(#[from] CanisterError),
     #[error("invalid identity: {0}")]
     Identity(#[from] k256::elliptic_curve::Error),
     #[error("identity error: {0}")]
@@ -46,9 +129,107 @@ pub enum Error {
     #[error("{0}")]
     Hon(#[from] HonError),
     #[error("{0}")]
+    HonWorker(#[from] hon_worker_common::ClientError),
+    #[error("{0}")]
     Url(#[from] url::ParseError),
+    #[error("invalid principal: {0}")]
+    Principal(#[from] PrincipalError),
     #[error("network error: {0}")]
     Network(#[from] reqwest::Error),
 }
 
+pub(crate) fn agent_error_kind(e: &AgentError) -> ErrorKind {
+    match e {
+        AgentError::TransportError(_) | AgentError::TimeoutWaitingForResponse() => {
+            ErrorKind::Transient
+        }
+        AgentError::HttpError(payload) => http_status_kind(payload.status),
+        _ => ErrorKind::Other,
+    }
+}
+
+fn http_status_kind(status: u16) -> ErrorKind {
+    match status {
+        401 | 403 => ErrorKind::Unauthorized,
+        404 => ErrorKind::NotFound,
+        408 | 429 | 500..=599 => ErrorKind::Transient,
+        _ => ErrorKind::Other,
+    }
+}
+
+pub(crate) fn hon_worker_error_kind(e: &hon_worker_common::ClientError) -> ErrorKind {
+    use hon_worker_common::{ClientError, WorkerError};
+
+    match e {
+        ClientError::Network(e) => reqwest_error_kind(e),
+        ClientError::Url(_) => ErrorKind::InvalidData,
+        ClientError::Signing(_) | ClientError::Identity(_) => ErrorKind::Unauthorized,
+        ClientError::Status { status, .. } => http_status_kind(*status),
+        ClientError::Worker(e) => match e {
+            WorkerError::InvalidSignature => ErrorKind::Unauthorized,
+            WorkerError::AlreadyVotedOnPost => ErrorKind::Duplicate,
+            WorkerError::PostNotFound => ErrorKind::NotFound,
+            WorkerError::InsufficientFunds => ErrorKind::InsufficientFunds,
+            WorkerError::TreasuryOutOfFunds | WorkerError::TreasuryLimitReached => {
+                ErrorKind::Closed
+            }
+            WorkerError::Internal(_) => ErrorKind::Other,
+        },
+    }
+}
+
+pub(crate) fn pnd_worker_error_kind(e: &pump_n_dump_common::client::ClientError) -> ErrorKind {
+    use pump_n_dump_common::client::ClientError;
+
+    match e {
+        ClientError::Network(e) => reqwest_error_kind(e),
+        ClientError::Url(_) => ErrorKind::InvalidData,
+        ClientError::Signing(_) => ErrorKind::Unauthorized,
+        ClientError::Status { status, .. } => http_status_kind(*status),
+    }
+}
+
+pub(crate) fn reqwest_error_kind(e: &reqwest::Error) -> ErrorKind {
+    if let Some(status) = e.status() {
+        return http_status_kind(status.as_u16());
+    }
+    if e.is_timeout() || e.is_request() {
+        return ErrorKind::Transient;
+    }
+    if e.is_decode() {
+        return ErrorKind::InvalidData;
+    }
+    ErrorKind::Other
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Agent(e) => agent_error_kind(e),
+            Self::YralCanister(e) => e.kind(),
+            Self::Identity(_) | Self::YralIdentity(_) => ErrorKind::Unauthorized,
+            Self::Candid(_)
+            | Self::ParseTransaction
+            | Self::TipCertificate
+            | Self::CborDe(_)
+            | Self::Url(_)
+            | Self::Principal(_)
+            | Self::PndError(PndError::Parse(_)) => ErrorKind::InvalidData,
+            Self::PndError(PndError::Network(e))
+            | Self::Hon(HonError::Network(e))
+            | Self::Network(e) => reqwest_error_kind(e),
+            Self::HonWorker(e) => hon_worker_error_kind(e),
+            Self::PndError(PndError::Worker(e)) => pnd_worker_error_kind(e),
+            Self::Metadata(_) | Self::GetTransactions(_) | Self::Hon(HonError::Backend(_)) => {
+                ErrorKind::Other
+            }
+        }
+    }
+
+    /// Whether the failed request may succeed if retried as is
+    pub fn is_retryable(&self) -> bool {
+        self.kind() == ErrorKind::Transient
+    }
+}
+
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -2,6 +2,8 @@ use std::sync::Arc;
 
 use agent_wrapper::AgentWrapper;
 use candid::{Decode, Principal};
+#[cfg(any(test, feature = "mock"))]
+use canisters_client::transport::mock::MockTransport;
 use canisters_client::{
     individual_user_template::{IndividualUserTemplate, Result22, Result4, UserCanisterDetails},
     platform_orchestrator::PlatformOrchestrator,
@@ -12,12 +14,12 @@ use canisters_client::{
     sns_root::SnsRoot,
     sns_swap::SnsSwap,
     user_index::{Result_, UserIndex},
+    CanisterTransport,
 };
-use consts::{
-    canister_ids::{PLATFORM_ORCHESTRATOR_ID, POST_CACHE_ID},
-    CDAO_SWAP_TIME_SECS, METADATA_API_BASE,
-};
+use consts::CDAO_SWAP_TIME_SECS;
+use hon_worker_common::HonWorkerClient;
 use ic_agent::{identity::DelegatedIdentity, Identity};
+use pump_n_dump_common::client::PumpNDumpClient;
 use serde::{Deserialize, Serialize};
 use sns_validation::pbs::sns_pb::SnsInitPayload;
 use types::delegated_identity::DelegatedIdentityWire;
@@ -25,12 +27,16 @@ use utils::profile::ProfileDetails;
 use yral_metadata_client::MetadataClient;
 
 pub mod agent_wrapper;
+mod config;
 mod consts;
 pub mod cursored_data;
 mod error;
+mod retry;
 pub mod utils;
 
+pub use config::*;
 pub use error::*;
+pub use retry::*;
 use yral_metadata_types::UserMetadata;
 pub const CENT_TOKEN_NAME: &str = "CENTS";
 pub const SATS_TOKEN_NAME: &str = "Satoshi";
@@ -38,7 +44,9 @@ pub const SATS_TOKEN_SYMBOL: &str = "SATS";
 
 #[derive(Clone)]
 pub struct Canisters<const AUTH: bool> {
+    config: CanistersConfig,
     agent: AgentWrapper,
+    http_client: reqwest::Client,
     id: Option<Arc<DelegatedIdentity>>,
     id_wire: Option<Arc<DelegatedIdentityWire>>,
     metadata_client: MetadataClient<false>,
@@ -48,19 +56,52 @@ pub struct Canisters<const AUTH: bool> {
 }
 
 impl Default for Canisters<false> {
+    /// Panics if the HTTP client can't be built, like [`reqwest::Client::new`]
     fn default() -> Self {
-        Self {
-            agent: AgentWrapper::build(|b| b),
+        Self::new(CanistersConfig::default()).expect("default http client should build")
+    }
+}
+
+impl Canisters<false> {
+    /// Fails if `config.http` can't build an HTTP client
+    pub fn new(config: CanistersConfig) -> Result<Self> {
+        let agent = AgentWrapper::build(&config, |b| b);
+        Self::with_agent(config, agent)
+    }
+
+    /// Canisters that send every canister call to `transport`
+    ///
+    /// see [`Canisters::mock_authenticated`] for mocked authenticated canisters
+    #[cfg(any(test, feature = "mock"))]
+    pub fn mock(config: CanistersConfig, transport: MockTransport) -> Result<Self> {
+        let agent = AgentWrapper::mock(&config, transport);
+        Self::with_agent(config, agent)
+    }
+
+    fn with_agent(config: CanistersConfig, agent: AgentWrapper) -> Result<Self> {
+        Ok(Self {
+            agent,
+            http_client: config.http.build_client()?,
             id: None,
             id_wire: None,
-            metadata_client: MetadataClient::with_base_url(METADATA_API_BASE.clone()),
+            metadata_client: MetadataClient::with_base_url(config.metadata_api_base.clone()),
             user_canister: Principal::anonymous(),
             expiry: 0,
             profile_details: None,
-        }
+            config,
+        })
     }
 }
 
+/// Expiry of the shortest delegation in `id`'s chain, in nanoseconds since the epoch
+fn delegation_expiry(id: &DelegatedIdentity) -> u64 {
+    id.delegation_chain()
+        .iter()
+        .fold(u64::MAX, |prev_expiry, del| {
+            del.delegation.expiration.min(prev_expiry)
+        })
+}
+
 impl Canisters<true> {
     pub fn expiry_ns(&self) -> u64 {
         self.expiry
@@ -76,17 +117,15 @@ impl Canisters<true> {
         self.user_canister
     }
 
-    pub async fn authenticated_user(&self) -> IndividualUserTemplate<'_> {
+    pub async fn authenticated_user(&self) -> IndividualUserTemplate<'_, AgentWrapper> {
         self.individual_user(self.user_canister).await
     }
 
     pub async fn deploy_cdao_sns(&self, init_payload: SnsInitPayload) -> Result<Result4> {
-        let agent = self.agent.get_agent().await;
         let args = candid::encode_args((init_payload, CDAO_SWAP_TIME_SECS)).unwrap();
-        let bytes = agent
-            .update(&self.user_canister, "deploy_cdao_sns")
-            .with_arg(args)
-            .call_and_wait()
+        let bytes = self
+            .agent
+            .update_raw(&self.user_canister, "deploy_cdao_sns", args)
             .await?;
         Ok(Decode!(&bytes, Result4)?)
     }
@@ -120,7 +159,7 @@ impl Canisters<true> {
             .await?
         {
             Result_::Ok(val) => Ok(val),
-            Result_::Err(e) => Err(Error::YralCanister(e)),
+            Result_::Err(e) => Err(CanisterError::Other(e)),
         }?;
 
         self.metadata_client
@@ -156,25 +195,23 @@ impl Canisters<true> {
     }
 
     pub async fn authenticate_with_network(
+        config: CanistersConfig,
         auth: DelegatedIdentityWire,
         referrer: Option<Principal>,
     ) -> Result<Self> {
         let id: DelegatedIdentity = auth.clone().try_into()?;
-        let expiry = id
-            .delegation_chain()
-            .iter()
-            .fold(u64::MAX, |prev_expiry, del| {
-                del.delegation.expiration.min(prev_expiry)
-            });
+        let expiry = delegation_expiry(&id);
         let id = Arc::new(id);
         let mut res = Self {
-            agent: AgentWrapper::build(|b| b.with_arc_identity(id.clone())),
-            metadata_client: MetadataClient::with_base_url(METADATA_API_BASE.clone()),
+            agent: AgentWrapper::build(&config, |b| b.with_arc_identity(id.clone())),
+            http_client: config.http.build_client()?,
+            metadata_client: MetadataClient::with_base_url(config.metadata_api_base.clone()),
             id: Some(id.clone()),
             id_wire: Some(Arc::new(auth)),
             user_canister: Principal::anonymous(),
             expiry,
             profile_details: None,
+            config,
         };
 
         let maybe_user_canister = res
@@ -191,13 +228,10 @@ impl Canisters<true> {
         }
 
         let user = res.authenticated_user().await;
-        match user
-            .update_last_access_time()
-            .await
-            .map_err(|e| e.to_string())
-        {
+        match user.update_last_access_time().await {
             Ok(Result22::Ok(_)) => (),
-            Err(e) | Ok(Result22::Err(e)) => log::warn!("Failed to update last access time: {e}"),
+            Ok(Result22::Err(e)) => log::warn!("Failed to update last access time: {e}"),
+            Err(e) => log::warn!("Failed to update last access time: {e}"),
         }
 
         res.profile_details = Some(user.get_profile_details().await?.into());
@@ -205,6 +239,30 @@ impl Canisters<true> {
         Ok(res)
     }
 
+    /// Authenticated canisters for `id` that send every canister call to `transport`
+    ///
+    /// unlike [`Self::authenticate_with_network`] nothing is looked up or created,
+    /// the user canister and profile are taken as given
+    #[cfg(any(test, feature = "mock"))]
+    pub fn mock_authenticated(
+        config: CanistersConfig,
+        transport: MockTransport,
+        id: DelegatedIdentityWire,
+        user_canister: Principal,
+        profile_details: ProfileDetails,
+    ) -> Result<Self> {
+        let delegated: DelegatedIdentity = id.clone().try_into()?;
+        let wire = CanistersAuthWire {
+            id,
+            user_canister,
+            expiry: delegation_expiry(&delegated),
+            profile_details,
+        };
+        Self::from_wire(wire, Canisters::<false>::mock(config, transport)?)
+    }
+
+    /// Restore authenticated canisters from the wire format,
+    /// endpoints are taken from the config of `base`
     pub fn from_wire(wire: CanistersAuthWire, base: Canisters<false>) -> Result<Self> {
         let id: DelegatedIdentity = wire.id.clone().try_into()?;
         let arc_id = Arc::new(id);
@@ -213,7 +271,9 @@ impl Canisters<true> {
         agent.set_arc_id(arc_id.clone());
 
         Ok(Self {
+            config: base.config,
             agent,
+            http_client: base.http_client,
             id: Some(arc_id),
             id_wire: Some(Arc::new(wire.id)),
             metadata_client: base.metadata_client,
@@ -225,24 +285,48 @@ impl Canisters<true> {
 }
 
 impl<const A: bool> Canisters<A> {
-    pub async fn post_cache(&self) -> PostCache<'_> {
-        let agent = self.agent.get_agent().await;
-        PostCache(POST_CACHE_ID, agent)
+    pub fn config(&self) -> &CanistersConfig {
+        &self.config
+    }
+
+    /// HTTP client shared by all worker requests
+    pub fn http_client(&self) -> &reqwest::Client {
+        &self.http_client
+    }
+
+    /// Client for the Hot-or-Not worker at [`CanistersConfig::hon_worker_url`]
+    pub fn hon_worker(&self) -> HonWorkerClient {
+        HonWorkerClient::new(self.config.hon_worker_url.clone(), self.http_client.clone())
+    }
+
+    /// Client for the pump-n-dump worker at [`CanistersConfig::pump_and_dump_worker_url`]
+    pub fn pump_n_dump(&self) -> PumpNDumpClient {
+        PumpNDumpClient::new(
+            self.config.pump_and_dump_worker_url.clone(),
+            self.http_client.clone(),
+        )
+    }
+
+    pub async fn post_cache(&self) -> PostCache<'_, AgentWrapper> {
+        PostCache(self.config.canister_ids.post_cache, &self.agent)
     }
 
-    pub async fn individual_user(&self, user_canister: Principal) -> IndividualUserTemplate<'_> {
-        let agent = self.agent.get_agent().await;
-        IndividualUserTemplate(user_canister, agent)
+    pub async fn individual_user(
+        &self,
+        user_canister: Principal,
+    ) -> IndividualUserTemplate<'_, AgentWrapper> {
+        IndividualUserTemplate(user_canister, &self.agent)
     }
 
-    pub async fn user_index_with(&self, subnet_principal: Principal) -> UserIndex<'_> {
-        let agent = self.agent.get_agent().await;
-        UserIndex(subnet_principal, agent)
+    pub async fn user_index_with(
+        &self,
+        subnet_principal: Principal,
+    ) -> UserIndex<'_, AgentWrapper> {
+        UserIndex(subnet_principal, &self.agent)
     }
 
-    pub async fn orchestrator(&self) -> PlatformOrchestrator<'_> {
-        let agent = self.agent.get_agent().await;
-        PlatformOrchestrator(PLATFORM_ORCHESTRATOR_ID, agent)
+    pub async fn orchestrator(&self) -> PlatformOrchestrator<'_, AgentWrapper> {
+        PlatformOrchestrator(self.config.canister_ids.platform_orchestrator, &self.agent)
     }
 
     pub async fn get_individual_canister_by_user_principal(
@@ -266,46 +350,36 @@ impl<const A: bool> Canisters<A> {
         }
     }
 
-    pub async fn sns_governance(&self, canister_id: Principal) -> SnsGovernance<'_> {
-        let agent = self.agent.get_agent().await;
-        SnsGovernance(canister_id, agent)
+    pub async fn sns_governance(&self, canister_id: Principal) -> SnsGovernance<'_, AgentWrapper> {
+        SnsGovernance(canister_id, &self.agent)
     }
 
-    pub async fn sns_index(&self, canister_id: Principal) -> SnsIndex<'_> {
-        let agent = self.agent.get_agent().await;
-        SnsIndex(canister_id, agent)
+    pub async fn sns_index(&self, canister_id: Principal) -> SnsIndex<'_, AgentWrapper> {
+        SnsIndex(canister_id, &self.agent)
     }
 
-    pub async fn sns_ledger(&self, canister_id: Principal) -> SnsLedger<'_> {
-        let agent = self.agent.get_agent().await;
-        SnsLedger(canister_id, agent)
+    pub async fn sns_ledger(&self, canister_id: Principal) -> SnsLedger<'_, AgentWrapper> {
+        SnsLedger(canister_id, &self.agent)
     }
 
-    pub async fn sns_root(&self, canister_id: Principal) -> SnsRoot<'_> {
-        let agent = self.agent.get_agent().await;
-        SnsRoot(canister_id, agent)
+    pub async fn sns_root(&self, canister_id: Principal) -> SnsRoot<'_, AgentWrapper> {
+        SnsRoot(canister_id, &self.agent)
     }
 
-    pub async fn sns_swap(&self, canister_id: Principal) -> SnsSwap<'_> {
-        let agent = self.agent.get_agent().await;
-        SnsSwap(canister_id, agent)
+    pub async fn sns_swap(&self, canister_id: Principal) -> SnsSwap<'_, AgentWrapper> {
+        SnsSwap(canister_id, &self.agent)
     }
 
     async fn subnet_indexes(&self) -> Result<Vec<Principal>> {
-        #[cfg(feature = "local")]
-        {
-            use consts::canister_ids::USER_INDEX_ID;
-            Ok(vec![USER_INDEX_ID])
-        }
-        #[cfg(not(feature = "local"))]
-        {
-            let orchestrator = self.orchestrator().await;
-            Ok(orchestrator
-                .get_all_available_subnet_orchestrators()
-                .await?
-                .into_iter()
-                .collect())
+        if let Some(user_index) = self.config.canister_ids.user_index {
+            return Ok(vec![user_index]);
         }
+        let orchestrator = self.orchestrator().await;
+        Ok(orchestrator
+            .get_all_available_subnet_orchestrators()
+            .await?
+            .into_iter()
+            .collect())
     }
 }
 
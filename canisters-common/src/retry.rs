@@ -0,0 +1,86 @@
+use std::future::Future;
+
+use futures_timer::Delay;
+use web_time::Duration;
+
+/// Exponential backoff policy for transient failures
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total attempts including the first one, `1` disables retries
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Randomize each backoff between half and the full duration
+    pub jitter: bool,
+    /// Replay update calls on transient failures
+    ///
+    /// updates are not idempotent in general, a call that timed out
+    /// may still have been executed by the canister. Updates whose call
+    /// request is rate limited (429) are always retried, they never ran
+    pub retry_updates: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(2),
+            jitter: true,
+            retry_updates: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Backoff before the `attempt + 1`th attempt
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .initial_backoff
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16));
+        let backoff = exp.min(self.max_backoff);
+        if !self.jitter {
+            return backoff;
+        }
+
+        let mut buf = [0u8; 4];
+        if getrandom::getrandom(&mut buf).is_err() {
+            return backoff;
+        }
+        let frac = u32::from_le_bytes(buf) as f64 / u32::MAX as f64;
+        backoff.mul_f64(0.5 + frac / 2.0)
+    }
+
+    /// Run `op` until it succeeds, fails with an error rejected by `should_retry`
+    /// or runs out of attempts
+    pub async fn retry<T, E, Fut>(
+        &self,
+        should_retry: impl Fn(&E) -> bool,
+        mut op: impl FnMut() -> Fut,
+    ) -> Result<T, E>
+    where
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Ok(res) => return Ok(res),
+                Err(e) if attempt < self.max_attempts && should_retry(&e) => {
+                    let backoff = self.backoff(attempt);
+                    log::debug!("attempt {attempt} failed, retrying in {backoff:?}");
+                    Delay::new(backoff).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
@@ -137,10 +137,11 @@ impl<const A: bool> Canisters<A> {
         }
 
         let post_uuid = &post_details.video_uid;
-        let req_url = format!(
-            "https://customer-2p3jflss4r4hmpnz.cloudflarestream.com/{post_uuid}/manifest/video.m3u8",
-        );
-        let res = reqwest::Client::default().head(req_url).send().await;
+        let req_url = self
+            .config
+            .video_stream_base
+            .join(&format!("{post_uuid}/manifest/video.m3u8"))?;
+        let res = self.http_client.head(req_url).send().await;
         if res.is_err() || (res.is_ok() && res.unwrap().status() != 200) {
             return Ok(None);
         }
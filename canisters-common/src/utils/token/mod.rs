@@ -2,7 +2,6 @@ use canisters_client::sns_swap::GetInitArg;
 use hon_worker_common::SatsBalanceInfo;
 use pump_n_dump_common::{rest::BalanceInfoResponse, WithdrawalState};
 use std::{fmt::Display, str::FromStr};
-use url::Url;
 
 use balance::{TokenBalance, TokenBalanceOrClaiming};
 use candid::{Nat, Principal};
@@ -11,10 +10,11 @@ use ic_agent::export::PrincipalError;
 
 use crate::{
     consts::{
-        CKBTC_INDEX, CKBTC_LEDGER, CKUSDC_INDEX, CKUSDC_LEDGER, PUMP_AND_DUMP_WORKER_URL,
-        SUPPORTED_NON_YRAL_TOKENS_ROOT,
+        CKBTC_INDEX, CKBTC_LEDGER, CKUSDC_INDEX, CKUSDC_LEDGER, SUPPORTED_NON_YRAL_TOKENS_ROOT,
     },
-    error, Canisters, PndError, Result, CENT_TOKEN_NAME, SATS_TOKEN_NAME, SATS_TOKEN_SYMBOL,
+    error::{hon_worker_error_kind, pnd_worker_error_kind},
+    CanisterError, Canisters, ErrorKind, PndError, Result, CENT_TOKEN_NAME, SATS_TOKEN_NAME,
+    SATS_TOKEN_SYMBOL,
 };
 use canisters_client::{
     sns_governance::{DissolveState, GetMetadataArg, ListNeurons},
@@ -90,32 +90,38 @@ impl Display for RootType {
     }
 }
 
-async fn load_cents_balance(
-    user_canister: Principal,
-) -> std::result::Result<BalanceInfoResponse, PndError> {
-    let balance_url = PUMP_AND_DUMP_WORKER_URL
-        .join(&format!("/balance/{user_canister}"))
-        .expect("Url to be valid");
-
-    let res: BalanceInfoResponse = reqwest::get(balance_url).await?.json().await?;
-
-    Ok(res)
-}
+impl<const A: bool> Canisters<A> {
+    async fn load_cents_balance(
+        &self,
+        user_canister: Principal,
+    ) -> std::result::Result<BalanceInfoResponse, PndError> {
+        let pump_n_dump = &self.pump_n_dump();
+        let res = self
+            .config
+            .retry
+            .retry(
+                |e| pnd_worker_error_kind(e) == ErrorKind::Transient,
+                || pump_n_dump.balance(user_canister),
+            )
+            .await?;
 
-async fn load_sats_balance(
-    user_principal: Principal,
-) -> std::result::Result<SatsBalanceInfo, PndError> {
-    let url: Url = hon_worker_common::WORKER_URL.parse().unwrap();
-    let balance_url = url
-        .join(&format!("/balance/{user_principal}"))
-        .expect("Url to be valid");
+        Ok(res)
+    }
 
-    let res: SatsBalanceInfo = reqwest::get(balance_url).await?.json().await?;
+    async fn load_sats_balance(&self, user_principal: Principal) -> Result<SatsBalanceInfo> {
+        let hon_worker = &self.hon_worker();
+        let res = self
+            .config
+            .retry
+            .retry(
+                |e| hon_worker_error_kind(e) == ErrorKind::Transient,
+                || hon_worker.balance(user_principal),
+            )
+            .await?;
 
-    Ok(res)
-}
+        Ok(res)
+    }
 
-impl<const A: bool> Canisters<A> {
     pub async fn token_metadata_by_root_type(
         &self,
         nsfw_detector: &impl TokenInfoProvider,
@@ -173,7 +179,7 @@ impl<const A: bool> Canisters<A> {
                     return Ok(None);
                 };
 
-                let bal_info = load_cents_balance(user_canister).await?;
+                let bal_info = self.load_cents_balance(user_canister).await?;
                 let bal = bal_info.balance.clone();
 
                 let withdrawal_state = if bal_info.withdrawable == 0usize {
@@ -205,7 +211,7 @@ impl<const A: bool> Canisters<A> {
                     return Ok(None);
                 };
 
-                let bal_info = load_sats_balance(user_principal).await?;
+                let bal_info = self.load_sats_balance(user_principal).await?;
                 let bal = bal_info.balance.clone();
 
                 Ok(Some(TokenMetadata {
@@ -588,13 +594,14 @@ impl<const A: bool> Canisters<A> {
         let ListSnsCanistersResponse { swap, .. } =
             root.list_sns_canisters(ListSnsCanistersArg {}).await?;
 
-        let swap = self.sns_swap(swap.unwrap()).await;
+        let swap = swap.ok_or_else(|| CanisterError::NotFound("sns swap canister".into()))?;
+        let swap = self.sns_swap(swap).await;
 
         let init = swap.get_init(GetInitArg {}).await?;
 
         let token_owner_details = init
             .init
-            .unwrap()
+            .ok_or_else(|| CanisterError::NotFound("sns swap init".into()))?
             .fallback_controller_principal_ids
             .into_iter()
             .collect::<Vec<_>>();
@@ -603,14 +610,12 @@ impl<const A: bool> Canisters<A> {
             .iter()
             .find(|controller| controller.ends_with("-cai"))
             .map(Principal::from_text)
-            .transpose()
-            .map_err(|e| error::Error::YralCanister(e.to_string()))?;
+            .transpose()?;
         let principal_id: Option<Principal> = token_owner_details
             .iter()
             .find(|controller| !controller.ends_with("-cai"))
             .map(Principal::from_text)
-            .transpose()
-            .map_err(|e| error::Error::YralCanister(e.to_string()))?;
+            .transpose()?;
         match (canister_id, principal_id) {
             (Some(canister_id), Some(principal_id)) => Ok(Some(TokenOwner {
                 principal_id,
@@ -626,3 +631,77 @@ pub struct TokenOwner {
     pub principal_id: Principal,
     pub canister_id: Principal,
 }
+
+#[cfg(test)]
+mod tests {
+    use canisters_client::{sns_governance::ListNeuronsResponse, transport::mock::MockTransport};
+
+    use super::*;
+    use crate::CanistersConfig;
+
+    fn principals() -> (Principal, Principal, Principal) {
+        (
+            Principal::from_slice(&[1]),
+            Principal::from_slice(&[2]),
+            Principal::from_slice(&[3]),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_token_balance_claimed() {
+        let (user, governance, ledger) = principals();
+        let transport = MockTransport::new();
+        transport.reply_with(ledger, "icrc1_balance_of", Nat::from(150u64));
+        let canisters = Canisters::mock(CanistersConfig::default(), transport.clone()).unwrap();
+
+        let balance = canisters
+            .get_token_balance(user, governance, ledger, 2)
+            .await
+            .unwrap();
+
+        assert!(!balance.is_claiming());
+        assert_eq!(balance.humanize(), "1");
+        let calls = transport.calls_to(ledger, "icrc1_balance_of");
+        assert_eq!(calls.len(), 1);
+        let acc: LedgerAccount = calls[0].decode_arg().unwrap();
+        assert_eq!(acc.owner, user);
+        assert!(transport.calls_to(governance, "list_neurons").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_token_balance_zero_checks_neurons() {
+        let (user, governance, ledger) = principals();
+        let transport = MockTransport::new();
+        transport.reply_with(ledger, "icrc1_balance_of", Nat::from(0u64));
+        transport.reply_with(
+            governance,
+            "list_neurons",
+            ListNeuronsResponse { neurons: vec![] },
+        );
+        let canisters = Canisters::mock(CanistersConfig::default(), transport.clone()).unwrap();
+
+        let balance = canisters
+            .get_token_balance(user, governance, ledger, 8)
+            .await
+            .unwrap();
+
+        assert!(!balance.is_claiming());
+        assert_eq!(balance.humanize(), "0");
+        let calls = transport.calls_to(governance, "list_neurons");
+        assert_eq!(calls.len(), 1);
+        let arg: ListNeurons = calls[0].decode_arg().unwrap();
+        assert_eq!(arg.of_principal, Some(user));
+    }
+
+    #[tokio::test]
+    async fn test_token_balance_ledger_error() {
+        let (user, governance, ledger) = principals();
+        let transport = MockTransport::new();
+        let canisters = Canisters::mock(CanistersConfig::default(), transport).unwrap();
+
+        assert!(canisters
+            .get_token_balance(user, governance, ledger, 8)
+            .await
+            .is_err());
+    }
+}
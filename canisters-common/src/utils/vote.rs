@@ -2,12 +2,12 @@ use candid::{CandidType, Principal};
 use canisters_client::individual_user_template::{
     BetDirection, BetOutcomeForBetMaker, BettingStatus, PlaceBetArg, PlacedBetDetail, Result3,
 };
-use hon_worker_common::{GameInfo, GameInfoReq};
+use hon_worker_common::{GameInfo, GameInfoReq, HonWorkerClient};
 use serde::{Deserialize, Serialize};
 use web_time::Duration;
 use yral_identity::{ic_agent::sign_message, msg_builder::Message, Signature};
 
-use crate::{consts::CENTS_IN_E6S, Canisters, Error, HonError, Result};
+use crate::{consts::CENTS_IN_E6S, CanisterError, Canisters, Result};
 
 use super::time::current_epoch;
 
@@ -182,9 +182,7 @@ impl Canisters<true> {
             Result3::Ok(p) => p,
             Result3::Err(e) => {
                 // todo send event that betting failed
-                return Err(Error::YralCanister(format!(
-                    "bet_on_currently_viewing_post error {e:?}"
-                )));
+                return Err(CanisterError::from(e).into());
             }
         };
 
@@ -215,9 +213,7 @@ impl Canisters<true> {
             Result3::Ok(p) => p,
             Result3::Err(e) => {
                 // todo send event that betting failed
-                return Err(Error::YralCanister(format!(
-                    "bet_on_currently_viewing_post_v_1 error {e:?}"
-                )));
+                return Err(CanisterError::from(e).into());
             }
         };
 
@@ -245,9 +241,14 @@ impl Canisters<true> {
 
         let url = cloudflare_url.join("/place_hot_or_not_bet")?;
 
-        let client = reqwest::Client::new();
-        let betting_status: BettingStatus =
-            client.post(url).json(&req).send().await?.json().await?;
+        let betting_status: BettingStatus = self
+            .http_client
+            .post(url)
+            .json(&req)
+            .send()
+            .await?
+            .json()
+            .await?;
 
         Ok(betting_status)
     }
@@ -257,18 +258,9 @@ impl Canisters<true> {
         cloudflare_url: reqwest::Url,
         request: GameInfoReq,
     ) -> Result<Option<GameInfo>> {
-        let path = format!("/game_info/{}", self.user_principal());
-        let url = cloudflare_url.join(&path)?;
-
-        let client = reqwest::Client::new();
-        let res = client.post(url).json(&request).send().await?;
-
-        if !res.status().is_success() {
-            let err = res.text().await?;
-            return Err(Error::Hon(HonError::Backend(err)));
-        }
-
-        let info = res.json().await?;
+        let info = HonWorkerClient::new(cloudflare_url, self.http_client.clone())
+            .game_info(self.user_principal(), request)
+            .await?;
 
         Ok(info)
     }
@@ -3,6 +3,7 @@ use yral_config_keys::ConfigKey;
 pub struct KVConfig {
     url: String,
     token: String,
+    client: reqwest::Client,
 }
 
 #[derive(Debug)]
@@ -17,7 +18,13 @@ pub enum KVFetchError {
 
 impl KVConfig {
     pub fn new(url: String, token: String) -> KVConfig {
-        KVConfig { url, token }
+        Self::with_client(url, token, reqwest::Client::new())
+    }
+
+    /// Use `client` for all requests, e.g to share a client configured
+    /// with timeouts, a user agent or a proxy
+    pub fn with_client(url: String, token: String, client: reqwest::Client) -> KVConfig {
+        KVConfig { url, token, client }
     }
 
     fn url<K: ConfigKey>(&self, key: K) -> Result<String, KVFetchError> {
@@ -34,8 +41,8 @@ impl KVConfig {
     pub async fn get<K: ConfigKey>(&self, key: K) -> Result<K::Value, KVFetchError> {
         let url = self.url(key)?;
 
-        let client = reqwest::Client::new();
-        let value = match client
+        let value = match self
+            .client
             .get(url)
             .header("Authorization", format!("Bearer {}", self.token))
             .send()
@@ -75,8 +82,8 @@ impl KVConfig {
             Ok(value) => value,
         };
 
-        let client = reqwest::Client::new();
-        let _ = match client
+        let _ = match self
+            .client
             .post(url)
             .body(value)
             .header("Authorization", format!("Bearer {}", self.token))
@@ -0,0 +1,353 @@
+use candid::Principal;
+use ic_agent::Identity;
+use serde::{Serialize, de::DeserializeOwned};
+use thiserror::Error;
+use url::Url;
+
+use crate::{
+    GameInfo, GameInfoReq, HoNGameVoteReq, HoNGameWithdrawReq, HotOrNot, PaginatedGamesReq,
+    PaginatedGamesRes, RequestMeta, SatsBalanceInfo, VoteRequest, VoteRes, WORKER_URL,
+    WithdrawRequest, WorkerError, WorkerResponse, sign_vote_request, sign_withdraw_request,
+};
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("network error when accessing worker: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error("invalid worker url: {0}")]
+    Url(#[from] url::ParseError),
+    #[error("failed to sign request: {0}")]
+    Signing(#[from] yral_identity::Error),
+    #[error("failed to get sender principal: {0}")]
+    Identity(String),
+    #[error("{0}")]
+    Worker(#[from] WorkerError),
+    #[error("worker responded with status {status}: {body}")]
+    Status { status: u16, body: String },
+}
+
+/// Client for the Hot-or-Not Cloudflare worker
+///
+/// routes are resolved relative to `base_url`, so a path prefix
+/// (e.g `https://example.com/hon`) is kept
+#[derive(Clone, Debug)]
+pub struct HonWorkerClient {
+    base_url: Url,
+    client: reqwest::Client,
+}
+
+impl Default for HonWorkerClient {
+    fn default() -> Self {
+        Self::new(
+            WORKER_URL
+                .parse()
+                .expect("WORKER_URL should be a valid url"),
+            reqwest::Client::new(),
+        )
+    }
+}
+
+impl HonWorkerClient {
+    pub fn new(mut base_url: Url, client: reqwest::Client) -> Self {
+        // without the trailing slash joins would replace the last path segment
+        if !base_url.path().ends_with('/') {
+            let path = format!("{}/", base_url.path());
+            base_url.set_path(&path);
+        }
+        Self { base_url, client }
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// Vote on a post on behalf of `sender`
+    pub async fn vote(
+        &self,
+        sender: &impl Identity,
+        request: VoteRequest,
+        fetched_sentiment: HotOrNot,
+        post_creator: Option<Principal>,
+    ) -> Result<VoteRes, ClientError> {
+        let user_principal = sender.sender().map_err(ClientError::Identity)?;
+        let meta = RequestMeta::now();
+        let signature = sign_vote_request(sender, request.clone(), meta)?;
+        let req = HoNGameVoteReq {
+            request,
+            fetched_sentiment,
+            post_creator,
+            meta: Some(meta),
+            signature,
+        };
+
+        let url = self.base_url.join(&format!("vote/{user_principal}"))?;
+        let res = self.client.post(url).json(&req).send().await?;
+        decode_worker_response(res).await
+    }
+
+    /// Withdraw sats to `request.receiver`, signed by `sender`
+    pub async fn withdraw(
+        &self,
+        sender: &impl Identity,
+        request: WithdrawRequest,
+    ) -> Result<(), ClientError> {
+        let meta = RequestMeta::now();
+        let signature = sign_withdraw_request(sender, request.clone(), meta)?;
+        let req = HoNGameWithdrawReq {
+            request,
+            meta: Some(meta),
+            signature,
+        };
+
+        let url = self.base_url.join("withdraw")?;
+        let res = self.client.post(url).json(&req).send().await?;
+        decode_worker_response(res).await
+    }
+
+    pub async fn balance(&self, user_principal: Principal) -> Result<SatsBalanceInfo, ClientError> {
+        let url = self.base_url.join(&format!("balance/{user_principal}"))?;
+        let res = self.client.get(url).send().await?;
+        decode_json(res).await
+    }
+
+    /// Info about `user_principal`'s game on a post, if they played it
+    pub async fn game_info(
+        &self,
+        user_principal: Principal,
+        request: GameInfoReq,
+    ) -> Result<Option<GameInfo>, ClientError> {
+        let url = self.base_url.join(&format!("game_info/{user_principal}"))?;
+        self.post_json(url, &request).await
+    }
+
+    pub async fn games_paginated(
+        &self,
+        user_principal: Principal,
+        request: &PaginatedGamesReq,
+    ) -> Result<PaginatedGamesRes, ClientError> {
+        let url = self.base_url.join(&format!("games/{user_principal}"))?;
+        self.post_json(url, request).await
+    }
+
+    async fn post_json<T: DeserializeOwned>(
+        &self,
+        url: Url,
+        body: &impl Serialize,
+    ) -> Result<T, ClientError> {
+        let res = self.client.post(url).json(body).send().await?;
+        decode_json(res).await
+    }
+}
+
+/// Error for a non-2xx response, the worker sends a [`WorkerError`]
+/// body for errors it knows about
+async fn error_from_response(res: reqwest::Response) -> ClientError {
+    let status = res.status().as_u16();
+    let body = match res.text().await {
+        Ok(body) => body,
+        Err(e) => return e.into(),
+    };
+    match serde_json::from_str::<WorkerError>(&body) {
+        Ok(e) => e.into(),
+        Err(_) => ClientError::Status { status, body },
+    }
+}
+
+async fn decode_json<T: DeserializeOwned>(res: reqwest::Response) -> Result<T, ClientError> {
+    if !res.status().is_success() {
+        return Err(error_from_response(res).await);
+    }
+    Ok(res.json().await?)
+}
+
+async fn decode_worker_response<T: DeserializeOwned>(
+    res: reqwest::Response,
+) -> Result<T, ClientError> {
+    if !res.status().is_success() {
+        return Err(error_from_response(res).await);
+    }
+    let res: WorkerResponse<T> = res.json().await?;
+    Ok(res?)
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_agent::identity::Secp256k1Identity;
+    use num_bigint::BigUint;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+
+    use super::*;
+    use crate::{GameResult, ReplayWindow, verify_vote_request, verify_withdraw_request};
+
+    /// Answers a single request with `status` and `body`, resolves to the raw request
+    async fn serve_once(status: u16, body: String) -> (Url, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hon", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut req = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                req.extend_from_slice(&buf[..n]);
+                if n == 0 || request_complete(&req) {
+                    break;
+                }
+            }
+
+            let res = format!(
+                "HTTP/1.1 {status} STUB\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(res.as_bytes()).await.unwrap();
+            String::from_utf8(req).unwrap()
+        });
+        (url.parse().unwrap(), server)
+    }
+
+    fn request_complete(req: &[u8]) -> bool {
+        let req = String::from_utf8_lossy(req);
+        let Some((head, body)) = req.split_once("\r\n\r\n") else {
+            return false;
+        };
+        let len = head
+            .lines()
+            .find_map(|line| {
+                line.to_lowercase()
+                    .strip_prefix("content-length: ")
+                    .map(str::to_string)
+            })
+            .map_or(0, |len| len.trim().parse().unwrap());
+        body.len() >= len
+    }
+
+    fn client(base_url: Url) -> HonWorkerClient {
+        HonWorkerClient::new(base_url, reqwest::Client::new())
+    }
+
+    fn identity() -> Secp256k1Identity {
+        Secp256k1Identity::from_private_key(k256::SecretKey::from_slice(&[1; 32]).unwrap())
+    }
+
+    fn request_body<T: DeserializeOwned>(req: &str) -> T {
+        let (_, body) = req.split_once("\r\n\r\n").unwrap();
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_vote() {
+        let res: WorkerResponse<VoteRes> = Ok(VoteRes {
+            game_result: GameResult::Win {
+                win_amt: BigUint::from(20u32),
+            },
+        });
+        let (url, server) = serve_once(200, serde_json::to_string(&res).unwrap()).await;
+        let sender = identity();
+        let user_principal = sender.sender().unwrap();
+        let request = VoteRequest {
+            post_canister: Principal::anonymous(),
+            post_id: 1,
+            vote_amount: 10,
+            direction: HotOrNot::Hot,
+        };
+
+        let res = client(url)
+            .vote(&sender, request, HotOrNot::Not, None)
+            .await
+            .unwrap();
+
+        let req = server.await.unwrap();
+        assert!(
+            req.starts_with(&format!("POST /hon/vote/{user_principal} HTTP/1.1\r\n")),
+            "{req}"
+        );
+        let vote: HoNGameVoteReq = request_body(&req);
+        assert!(vote.meta.is_some());
+        let verified =
+            verify_vote_request(user_principal, &vote, &ReplayWindow::default(), &()).unwrap();
+        assert_eq!(verified, user_principal);
+        assert!(
+            matches!(res.game_result, GameResult::Win { win_amt } if win_amt == BigUint::from(20u32))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_withdraw() {
+        let res: WorkerResponse<()> = Ok(());
+        let (url, server) = serve_once(200, serde_json::to_string(&res).unwrap()).await;
+        let sender = identity();
+        let request = WithdrawRequest {
+            receiver: sender.sender().unwrap(),
+            amount: 10,
+        };
+
+        client(url).withdraw(&sender, request).await.unwrap();
+
+        let req = server.await.unwrap();
+        assert!(req.starts_with("POST /hon/withdraw HTTP/1.1\r\n"), "{req}");
+        let withdraw: HoNGameWithdrawReq = request_body(&req);
+        assert!(withdraw.meta.is_some());
+        assert_eq!(withdraw.request.amount, 10);
+        let verified = verify_withdraw_request(&withdraw, &ReplayWindow::default(), &()).unwrap();
+        assert_eq!(verified, sender.sender().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_worker_error_in_response() {
+        let res: WorkerResponse<VoteRes> = Err(WorkerError::AlreadyVotedOnPost);
+        let (url, server) = serve_once(200, serde_json::to_string(&res).unwrap()).await;
+        let request = WithdrawRequest {
+            receiver: Principal::anonymous(),
+            amount: 10,
+        };
+
+        let err = client(url)
+            .withdraw(&identity(), request)
+            .await
+            .unwrap_err();
+
+        server.await.unwrap();
+        assert!(
+            matches!(err, ClientError::Worker(WorkerError::AlreadyVotedOnPost)),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_worker_error_status() {
+        let body = serde_json::to_string(&WorkerError::InsufficientFunds).unwrap();
+        let (url, server) = serve_once(400, body).await;
+
+        let err = client(url)
+            .balance(Principal::anonymous())
+            .await
+            .unwrap_err();
+
+        server.await.unwrap();
+        assert!(
+            matches!(err, ClientError::Worker(WorkerError::InsufficientFunds)),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_status() {
+        let (url, server) = serve_once(502, "bad gateway".to_string()).await;
+
+        let err = client(url)
+            .balance(Principal::anonymous())
+            .await
+            .unwrap_err();
+
+        server.await.unwrap();
+        assert!(
+            matches!(&err, ClientError::Status { status: 502, body } if body == "bad gateway"),
+            "{err}"
+        );
+    }
+}
@@ -1,6 +1,13 @@
+#[cfg(feature = "client")]
+mod client;
 mod error;
+mod verify;
 
+#[cfg(feature = "client")]
+pub use client::*;
 pub use error::*;
+pub use signed_request::RequestMeta;
+pub use verify::*;
 
 use candid::{CandidType, Principal};
 use num_bigint::BigUint;
@@ -90,23 +97,35 @@ pub struct HoNGameVoteReq {
     /// Sentiment from alloydb
     pub fetched_sentiment: HotOrNot,
     pub post_creator: Option<Principal>,
+    #[serde(default)]
+    pub meta: Option<RequestMeta>,
     pub signature: Signature,
 }
 
-pub fn hon_game_vote_msg(request: VoteRequest) -> yral_identity::msg_builder::Message {
-    yral_identity::msg_builder::Message::default()
-        .method_name("hon_worker_game_vote".into())
-        .args((request,))
-        .expect("Vote request should serialize")
+pub fn hon_game_vote_msg(
+    request: VoteRequest,
+    meta: Option<RequestMeta>,
+) -> yral_identity::msg_builder::Message {
+    let msg = yral_identity::msg_builder::Message::default();
+    match meta {
+        None => msg
+            .method_name("hon_worker_game_vote".into())
+            .args((request,)),
+        Some(meta) => msg
+            .method_name("hon_worker_game_vote_v2".into())
+            .args((request, meta)),
+    }
+    .expect("Vote request should serialize")
 }
 
 #[cfg(feature = "client")]
 pub fn sign_vote_request(
     sender: &impl ic_agent::Identity,
     request: VoteRequest,
+    meta: RequestMeta,
 ) -> yral_identity::Result<Signature> {
     use yral_identity::ic_agent::sign_message;
-    let msg = hon_game_vote_msg(request.clone());
+    let msg = hon_game_vote_msg(request, Some(meta));
     sign_message(sender, msg)
 }
 
@@ -119,22 +138,34 @@ pub struct WithdrawRequest {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct HoNGameWithdrawReq {
     pub request: WithdrawRequest,
+    #[serde(default)]
+    pub meta: Option<RequestMeta>,
     pub signature: Signature,
 }
 
-pub fn hon_game_withdraw_msg(request: &WithdrawRequest) -> yral_identity::msg_builder::Message {
-    yral_identity::msg_builder::Message::default()
-        .method_name("hon_worker_game_withdraw".into())
-        .args((request.amount,))
-        .expect("Withdraw request should serialize")
+pub fn hon_game_withdraw_msg(
+    request: &WithdrawRequest,
+    meta: Option<RequestMeta>,
+) -> yral_identity::msg_builder::Message {
+    let msg = yral_identity::msg_builder::Message::default();
+    match meta {
+        None => msg
+            .method_name("hon_worker_game_withdraw".into())
+            .args((request.amount,)),
+        Some(meta) => msg
+            .method_name("hon_worker_game_withdraw_v2".into())
+            .args((request.amount, meta)),
+    }
+    .expect("Withdraw request should serialize")
 }
 
 #[cfg(feature = "client")]
 pub fn sign_withdraw_request(
     sender: &impl ic_agent::Identity,
     request: WithdrawRequest,
+    meta: RequestMeta,
 ) -> yral_identity::Result<Signature> {
     use yral_identity::ic_agent::sign_message;
-    let msg = hon_game_withdraw_msg(&request);
+    let msg = hon_game_withdraw_msg(&request, Some(meta));
     sign_message(sender, msg)
 }
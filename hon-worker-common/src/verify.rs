@@ -0,0 +1,171 @@
+//! Server side verification of signed worker requests
+
+use candid::Principal;
+use signed_request::verify_signed_request;
+pub use signed_request::{ReplayGuard, ReplayWindow, VerifyError};
+
+use crate::{HoNGameVoteReq, HoNGameWithdrawReq, hon_game_vote_msg, hon_game_withdraw_msg};
+
+/// Verify that `req` was signed by `sender`, returns the verified sender
+pub fn verify_vote_request(
+    sender: Principal,
+    req: &HoNGameVoteReq,
+    window: &ReplayWindow,
+    guard: &impl ReplayGuard,
+) -> Result<Principal, VerifyError> {
+    let msg = hon_game_vote_msg(req.request.clone(), req.meta);
+    verify_signed_request(
+        sender,
+        msg,
+        req.meta.as_ref(),
+        &req.signature,
+        window,
+        guard,
+    )
+}
+
+/// Verify that `req` was signed by its receiver, returns the verified sender
+pub fn verify_withdraw_request(
+    req: &HoNGameWithdrawReq,
+    window: &ReplayWindow,
+    guard: &impl ReplayGuard,
+) -> Result<Principal, VerifyError> {
+    let msg = hon_game_withdraw_msg(&req.request, req.meta);
+    verify_signed_request(
+        req.request.receiver,
+        msg,
+        req.meta.as_ref(),
+        &req.signature,
+        window,
+        guard,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_agent::Identity;
+    use signed_request::test_util::{SeenKeys, identity, meta};
+    use yral_identity::ic_agent::sign_message;
+
+    use super::*;
+    use crate::{HotOrNot, RequestMeta, VoteRequest, WithdrawRequest};
+
+    fn vote_req(signer: &impl Identity, meta: RequestMeta) -> HoNGameVoteReq {
+        let request = VoteRequest {
+            post_canister: Principal::anonymous(),
+            post_id: 1,
+            vote_amount: 10,
+            direction: HotOrNot::Hot,
+        };
+        let signature =
+            sign_message(signer, hon_game_vote_msg(request.clone(), Some(meta))).unwrap();
+        HoNGameVoteReq {
+            request,
+            fetched_sentiment: HotOrNot::Not,
+            post_creator: None,
+            meta: Some(meta),
+            signature,
+        }
+    }
+
+    fn withdraw_req(
+        signer: &impl Identity,
+        receiver: Principal,
+        meta: RequestMeta,
+    ) -> HoNGameWithdrawReq {
+        let request = WithdrawRequest {
+            receiver,
+            amount: 10,
+        };
+        let signature = sign_message(signer, hon_game_withdraw_msg(&request, Some(meta))).unwrap();
+        HoNGameWithdrawReq {
+            request,
+            meta: Some(meta),
+            signature,
+        }
+    }
+
+    #[test]
+    fn test_vote_request_round_trip() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+        let req = vote_req(&signer, meta(0));
+
+        let verified = verify_vote_request(sender, &req, &ReplayWindow::default(), &());
+        assert_eq!(verified.unwrap(), sender);
+    }
+
+    #[test]
+    fn test_vote_request_wrong_sender() {
+        let req = vote_req(&identity(1), meta(0));
+        let other = identity(2).sender().unwrap();
+
+        let res = verify_vote_request(other, &req, &ReplayWindow::default(), &());
+        assert!(matches!(res, Err(VerifyError::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn test_vote_request_covers_payload_and_meta() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+
+        let mut req = vote_req(&signer, meta(0));
+        req.request.vote_amount += 1;
+        let res = verify_vote_request(sender, &req, &ReplayWindow::default(), &());
+        assert!(matches!(res, Err(VerifyError::InvalidSignature(_))));
+
+        let mut req = vote_req(&signer, meta(0));
+        req.meta = Some(meta(-1));
+        let res = verify_vote_request(sender, &req, &ReplayWindow::default(), &());
+        assert!(matches!(res, Err(VerifyError::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn test_legacy_vote_request() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+        let mut req = vote_req(&signer, meta(0));
+        req.meta = None;
+        req.signature =
+            sign_message(&signer, hon_game_vote_msg(req.request.clone(), None)).unwrap();
+        let window = ReplayWindow {
+            allow_legacy: true,
+            ..Default::default()
+        };
+
+        let guard = SeenKeys::default();
+        verify_vote_request(sender, &req, &window, &guard).unwrap();
+        let res = verify_vote_request(sender, &req, &window, &guard);
+        assert!(matches!(res, Err(VerifyError::Replayed)));
+    }
+
+    #[test]
+    fn test_withdraw_request_round_trip() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+        let req = withdraw_req(&signer, sender, meta(0));
+
+        let verified = verify_withdraw_request(&req, &ReplayWindow::default(), &());
+        assert_eq!(verified.unwrap(), sender);
+    }
+
+    #[test]
+    fn test_withdraw_request_wrong_sender() {
+        let other = identity(2).sender().unwrap();
+        let req = withdraw_req(&identity(1), other, meta(0));
+
+        let res = verify_withdraw_request(&req, &ReplayWindow::default(), &());
+        assert!(matches!(res, Err(VerifyError::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn test_withdraw_request_covers_meta() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+        let mut req = withdraw_req(&signer, sender, meta(0));
+        req.meta = Some(meta(-1));
+
+        let res = verify_withdraw_request(&req, &ReplayWindow::default(), &());
+        assert!(matches!(res, Err(VerifyError::InvalidSignature(_))));
+    }
+}
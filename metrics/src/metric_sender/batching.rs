@@ -0,0 +1,520 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use thiserror::Error;
+use web_time::Instant;
+
+use crate::metrics::{serialized::SerializedMetric, Metric, MetricEvent, MetricEventList};
+
+#[cfg(target_arch = "wasm32")]
+use super::LocalMetricEventTx;
+#[cfg(not(target_arch = "wasm32"))]
+use super::MetricEventTx;
+
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+pub const DEFAULT_CAPACITY: usize = 10_000;
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Error)]
+pub enum BatchingError<E> {
+    #[error("failed to serialize metric: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("failed to send metrics: {0}")]
+    Tx(E),
+}
+
+struct Buffered {
+    /// tag of the list the event is sent in
+    tag: String,
+    event: MetricEvent<SerializedMetric>,
+}
+
+#[derive(Default)]
+struct State {
+    events: VecDeque<Buffered>,
+    /// when the oldest buffered event was added
+    since: Option<Instant>,
+    backoff: Duration,
+    /// no automatic flush before this
+    retry_at: Option<Instant>,
+    dropped: u64,
+    reported_dropped: u64,
+    shut_down: bool,
+}
+
+impl State {
+    /// drop the oldest events above `capacity`
+    fn trim(&mut self, capacity: usize) {
+        while self.events.len() > capacity {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+    }
+}
+
+/// takes a metric sender and buffers events, sending them as [`MetricEventList`]s
+/// of the same source and tag
+///
+/// the buffer is flushed once it holds `max_batch_size` events, on the first push
+/// after `flush_interval` and on [`Self::shutdown`]. Use [`Self::flush_periodically`]
+/// to also flush idle buffers. After a failed flush, flushes on push and periodic
+/// flushes back off exponentially. Pushing never fails because of the sender,
+/// its errors are logged and the events stay buffered, [`Self::flush`] returns them.
+/// When more than `capacity` events are waiting the oldest are dropped,
+/// see [`Self::dropped`]
+#[derive(Clone)]
+pub struct BatchingMetricTx<Tx> {
+    tx: Tx,
+    state: Arc<Mutex<State>>,
+    max_batch_size: usize,
+    flush_interval: Duration,
+    capacity: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl<Tx> BatchingMetricTx<Tx> {
+    pub fn new(tx: Tx) -> Self {
+        Self {
+            tx,
+            state: Arc::new(Mutex::new(State {
+                backoff: DEFAULT_INITIAL_BACKOFF,
+                ..Default::default()
+            })),
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            capacity: DEFAULT_CAPACITY,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+        }
+    }
+
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = max_batch_size.max(1);
+        self
+    }
+
+    pub fn with_flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    /// Max events kept while waiting to be sent
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Wait before flushing again after a failure, doubling up to `max`
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self.state().backoff = initial;
+        self
+    }
+
+    /// Events dropped because the buffer was full, since creation
+    pub fn dropped(&self) -> u64 {
+        self.state().dropped
+    }
+
+    /// Events waiting to be sent
+    pub fn buffered(&self) -> usize {
+        self.state().events.len()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // the state is never left half updated
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn is_shut_down(&self) -> bool {
+        self.state().shut_down
+    }
+
+    fn backing_off(state: &State) -> bool {
+        state.retry_at.is_some_and(|at| Instant::now() < at)
+    }
+
+    fn failed(&self) {
+        let mut state = self.state();
+        state.retry_at = Some(Instant::now() + state.backoff);
+        state.backoff = (state.backoff * 2).min(self.max_backoff);
+    }
+
+    fn succeeded(&self) {
+        let mut state = self.state();
+        state.retry_at = None;
+        state.backoff = self.initial_backoff;
+    }
+
+    /// Buffer `events` to be sent under `tag`, returns whether a flush is due
+    fn enqueue(&self, tag: String, events: Vec<MetricEvent<SerializedMetric>>) -> bool {
+        let mut state = self.state();
+        let since = *state.since.get_or_insert_with(Instant::now);
+        state
+            .events
+            .extend(events.into_iter().map(|event| Buffered {
+                tag: tag.clone(),
+                event,
+            }));
+        state.trim(self.capacity);
+
+        !Self::backing_off(&state)
+            && (state.shut_down
+                || state.events.len() >= self.max_batch_size
+                || since.elapsed() >= self.flush_interval)
+    }
+
+    fn take(&self) -> VecDeque<Buffered> {
+        let mut state = self.state();
+        if state.dropped > state.reported_dropped {
+            log::warn!(
+                "dropped {} metric events, buffer full",
+                state.dropped - state.reported_dropped
+            );
+            state.reported_dropped = state.dropped;
+        }
+        state.since = None;
+        std::mem::take(&mut state.events)
+    }
+
+    /// Put events that failed to send back in front of the buffer
+    fn restore(&self, mut unsent: VecDeque<Buffered>) {
+        let mut state = self.state();
+        unsent.append(&mut state.events);
+        state.events = unsent;
+        state.trim(self.capacity);
+        if state.since.is_none() {
+            state.since = Some(Instant::now());
+        }
+    }
+
+    async fn flush_with<F, Fut, E>(&self, send: F) -> Result<(), BatchingError<E>>
+    where
+        F: Fn(MetricEventList<SerializedMetric>) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+    {
+        let mut pending = self.take();
+        while let Some(first) = pending.front() {
+            // the next events with the same source and tag, in order
+            let (source, tag) = (first.event.source, first.tag.clone());
+            let mut batch = VecDeque::new();
+            let mut rest = VecDeque::with_capacity(pending.len());
+            for buffered in pending {
+                if batch.len() < self.max_batch_size
+                    && buffered.event.source == source
+                    && buffered.tag == tag
+                {
+                    batch.push_back(buffered);
+                } else {
+                    rest.push_back(buffered);
+                }
+            }
+            pending = rest;
+
+            let events = batch.iter().map(|b| b.event.clone()).collect();
+            if let Err(e) = send(MetricEventList::new(source, tag, events)).await {
+                batch.append(&mut pending);
+                self.restore(batch);
+                self.failed();
+                return Err(BatchingError::Tx(e));
+            }
+        }
+
+        self.succeeded();
+        Ok(())
+    }
+
+    /// Flush due on push, send errors are only logged
+    /// as the pushed events are buffered either way
+    async fn flush_on_push<FF, E>(&self, flush: FF)
+    where
+        FF: Future<Output = Result<(), BatchingError<E>>>,
+        E: std::fmt::Display,
+    {
+        if let Err(e) = flush.await {
+            log::warn!("failed to flush metrics, keeping them buffered {e}");
+        }
+    }
+
+    async fn flush_periodically_with<S, SF, F, FF, E>(&self, sleep: S, flush: F)
+    where
+        S: Fn(Duration) -> SF,
+        SF: Future<Output = ()>,
+        F: Fn() -> FF,
+        FF: Future<Output = Result<(), BatchingError<E>>>,
+        E: std::fmt::Display,
+    {
+        while !self.is_shut_down() {
+            sleep(self.flush_interval).await;
+            if Self::backing_off(&self.state()) {
+                continue;
+            }
+            if let Err(e) = flush().await {
+                log::warn!("failed to flush metrics {e}");
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<Tx: MetricEventTx + Sync> BatchingMetricTx<Tx> {
+    /// Send all buffered events, even while backing off
+    pub async fn flush(&self) -> Result<(), BatchingError<Tx::Error>> {
+        self.flush_with(|list| self.tx.push_list(list)).await
+    }
+
+    /// Send all buffered events, events pushed afterwards are sent right away
+    pub async fn shutdown(&self) -> Result<(), BatchingError<Tx::Error>> {
+        self.state().shut_down = true;
+        self.flush().await
+    }
+
+    /// Flush every `flush_interval` until [`Self::shutdown`]
+    ///
+    /// `sleep` is the runtime's timer, e.g `tokio::time::sleep`
+    pub async fn flush_periodically<S, SF>(&self, sleep: S)
+    where
+        S: Fn(Duration) -> SF,
+        SF: Future<Output = ()>,
+    {
+        self.flush_periodically_with(sleep, || self.flush()).await
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<Tx: MetricEventTx + Sync> MetricEventTx for BatchingMetricTx<Tx> {
+    type Error = BatchingError<Tx::Error>;
+
+    async fn push<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEvent<M>,
+    ) -> Result<(), Self::Error> {
+        let tag = ev.tag.clone();
+        if self.enqueue(tag, vec![ev.serialized()?]) {
+            self.flush_on_push(self.flush()).await;
+        }
+        Ok(())
+    }
+
+    async fn push_list<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEventList<M>,
+    ) -> Result<(), Self::Error> {
+        let ev = ev.serialized()?;
+        if self.enqueue(ev.tag, ev.metric) {
+            self.flush_on_push(self.flush()).await;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl<Tx: LocalMetricEventTx> BatchingMetricTx<Tx> {
+    /// Send all buffered events, even while backing off
+    pub async fn flush(&self) -> Result<(), BatchingError<Tx::Error>> {
+        self.flush_with(|list| self.tx.push_list_local(list)).await
+    }
+
+    /// Send all buffered events, events pushed afterwards are sent right away
+    pub async fn shutdown(&self) -> Result<(), BatchingError<Tx::Error>> {
+        self.state().shut_down = true;
+        self.flush().await
+    }
+
+    /// Flush every `flush_interval` until [`Self::shutdown`]
+    ///
+    /// `sleep` is the runtime's timer, e.g `gloo_timers::future::sleep`
+    pub async fn flush_periodically<S, SF>(&self, sleep: S)
+    where
+        S: Fn(Duration) -> SF,
+        SF: Future<Output = ()>,
+    {
+        self.flush_periodically_with(sleep, || self.flush()).await
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl<Tx: LocalMetricEventTx> LocalMetricEventTx for BatchingMetricTx<Tx> {
+    type Error = BatchingError<Tx::Error>;
+
+    async fn push_local<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEvent<M>,
+    ) -> Result<(), Self::Error> {
+        let tag = ev.tag.clone();
+        if self.enqueue(tag, vec![ev.serialized()?]) {
+            self.flush_on_push(self.flush()).await;
+        }
+        Ok(())
+    }
+
+    async fn push_list_local<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEventList<M>,
+    ) -> Result<(), Self::Error> {
+        let ev = ev.serialized()?;
+        if self.enqueue(ev.tag, ev.metric) {
+            self.flush_on_push(self.flush()).await;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        metric_sender::test_util::{round, rounds, withdrawal, FlakyMetricTx},
+        metrics::EventSource,
+    };
+
+    use super::*;
+
+    const LONG: Duration = Duration::from_secs(3600);
+
+    fn batching(tx: &FlakyMetricTx) -> BatchingMetricTx<FlakyMetricTx> {
+        BatchingMetricTx::new(tx.clone()).with_flush_interval(LONG)
+    }
+
+    #[tokio::test]
+    async fn test_flush_on_batch_size() {
+        let tx = FlakyMetricTx::new();
+        let batching = batching(&tx).with_max_batch_size(3);
+
+        for n in 0..2 {
+            batching.push(round(EventSource::Yral, n)).await.unwrap();
+        }
+        assert!(tx.recording.events().is_empty());
+        assert_eq!(batching.buffered(), 2);
+
+        batching.push(round(EventSource::Yral, 2)).await.unwrap();
+        assert_eq!(rounds(&tx.recording), [0, 1, 2]);
+        assert_eq!(tx.attempts(), 1);
+        assert_eq!(batching.buffered(), 0);
+        let ev = tx
+            .recording
+            .assert_emitted("tides_turned", |ev| ev.payload["round_num"] == 2);
+        assert_eq!(ev.list_tag.as_deref(), Some("tides_turned"));
+    }
+
+    #[tokio::test]
+    async fn test_flush_groups_by_source_and_tag() {
+        let tx = FlakyMetricTx::new();
+        let batching = batching(&tx);
+
+        batching.push(round(EventSource::Yral, 0)).await.unwrap();
+        batching.push(withdrawal(EventSource::Yral)).await.unwrap();
+        batching
+            .push(round(EventSource::PumpNDumpWorker, 1))
+            .await
+            .unwrap();
+        batching.push(round(EventSource::Yral, 2)).await.unwrap();
+        batching.flush().await.unwrap();
+
+        // one list per (source, tag), in order of their first event
+        assert_eq!(tx.attempts(), 3);
+        let sources = tx
+            .recording
+            .events()
+            .iter()
+            .map(|ev| (ev.source, ev.tag.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            [
+                (EventSource::Yral, "tides_turned".to_string()),
+                (EventSource::Yral, "tides_turned".to_string()),
+                (EventSource::Yral, "cents_withdrawal".to_string()),
+                (EventSource::PumpNDumpWorker, "tides_turned".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flush_on_interval() {
+        let tx = FlakyMetricTx::new();
+        let batching =
+            BatchingMetricTx::new(tx.clone()).with_flush_interval(Duration::from_millis(20));
+
+        batching.push(round(EventSource::Yral, 0)).await.unwrap();
+        assert!(tx.recording.events().is_empty());
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        batching.push(round(EventSource::Yral, 1)).await.unwrap();
+        assert_eq!(rounds(&tx.recording), [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_flush_periodically_until_shutdown() {
+        let tx = FlakyMetricTx::new();
+        let batching =
+            BatchingMetricTx::new(tx.clone()).with_flush_interval(Duration::from_millis(5));
+
+        batching.push(round(EventSource::Yral, 0)).await.unwrap();
+        let stop = async {
+            while tx.recording.events().is_empty() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            batching.push(round(EventSource::Yral, 1)).await.unwrap();
+            batching.shutdown().await.unwrap();
+        };
+        tokio::join!(batching.flush_periodically(tokio::time::sleep), stop);
+
+        assert_eq!(rounds(&tx.recording), [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_flushes_and_sends_later_pushes() {
+        let tx = FlakyMetricTx::new();
+        let batching = batching(&tx);
+
+        batching.push(round(EventSource::Yral, 0)).await.unwrap();
+        batching.shutdown().await.unwrap();
+        assert_eq!(rounds(&tx.recording), [0]);
+
+        batching.push(round(EventSource::Yral, 1)).await.unwrap();
+        assert_eq!(rounds(&tx.recording), [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_flush_backs_off_and_drops_oldest() {
+        let tx = FlakyMetricTx::new();
+        let batching = batching(&tx)
+            .with_max_batch_size(1)
+            .with_capacity(2)
+            .with_backoff(Duration::from_millis(20), Duration::from_millis(20));
+        tx.set_failing(true);
+
+        // the sender's errors aren't returned, the events are buffered
+        for n in 0..4 {
+            batching.push(round(EventSource::Yral, n)).await.unwrap();
+        }
+        assert_eq!(tx.attempts(), 1);
+        assert_eq!(batching.buffered(), 2);
+        assert_eq!(batching.dropped(), 2);
+
+        tx.set_failing(false);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        batching.push(round(EventSource::Yral, 4)).await.unwrap();
+        assert_eq!(rounds(&tx.recording), [3, 4]);
+        assert_eq!(batching.dropped(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_flush_returns_send_errors() {
+        let tx = FlakyMetricTx::new();
+        let batching = batching(&tx);
+        tx.set_failing(true);
+
+        batching.push(round(EventSource::Yral, 0)).await.unwrap();
+        assert!(matches!(batching.flush().await, Err(BatchingError::Tx(_))));
+        assert_eq!(batching.buffered(), 1);
+    }
+}
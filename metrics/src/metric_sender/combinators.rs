@@ -0,0 +1,400 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use thiserror::Error;
+use web_time::{SystemTime, UNIX_EPOCH};
+
+use crate::metrics::{EventSource, Metric, MetricEvent, MetricEventList};
+
+use super::MetricEventTx;
+
+#[derive(Debug, Error)]
+pub enum TeeError<A, B> {
+    #[error("failed to serialize metric: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("first sender failed: {0}")]
+    First(A),
+    #[error("second sender failed: {0}")]
+    Second(B),
+    #[error("both senders failed: {0}, {1}")]
+    Both(A, B),
+}
+
+impl<A, B> TeeError<A, B> {
+    fn from_results(first: Result<(), A>, second: Result<(), B>) -> Result<(), Self> {
+        match (first, second) {
+            (Ok(()), Ok(())) => Ok(()),
+            (Err(a), Ok(())) => Err(Self::First(a)),
+            (Ok(()), Err(b)) => Err(Self::Second(b)),
+            (Err(a), Err(b)) => Err(Self::Both(a, b)),
+        }
+    }
+}
+
+/// Sends every event to both senders, one after the other
+///
+/// the second sender is used even if the first one fails
+#[derive(Clone)]
+pub struct TeeMetricTx<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> TeeMetricTx<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+impl<A, B> MetricEventTx for TeeMetricTx<A, B>
+where
+    A: MetricEventTx + Sync,
+    A::Error: Send,
+    B: MetricEventTx + Sync,
+{
+    type Error = TeeError<A::Error, B::Error>;
+
+    async fn push<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEvent<M>,
+    ) -> Result<(), Self::Error> {
+        let ev = ev.serialized()?;
+        let first = self.first.push(ev.clone()).await;
+        let second = self.second.push(ev).await;
+        TeeError::from_results(first, second)
+    }
+
+    async fn push_list<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEventList<M>,
+    ) -> Result<(), Self::Error> {
+        let ev = ev.serialized()?;
+        let first = self.first.push_list(ev.clone()).await;
+        let second = self.second.push_list(ev).await;
+        TeeError::from_results(first, second)
+    }
+}
+
+/// Only sends events for which `filter(source, tag)` returns true,
+/// other events are dropped without error
+///
+/// events in lists are filtered one by one, lists left empty are not sent
+#[derive(Clone)]
+pub struct FilterMetricTx<Tx, F> {
+    tx: Tx,
+    filter: F,
+}
+
+impl<Tx, F> FilterMetricTx<Tx, F>
+where
+    F: Fn(EventSource, &str) -> bool,
+{
+    pub fn new(tx: Tx, filter: F) -> Self {
+        Self { tx, filter }
+    }
+}
+
+impl<Tx, F> MetricEventTx for FilterMetricTx<Tx, F>
+where
+    Tx: MetricEventTx + Sync,
+    F: Fn(EventSource, &str) -> bool + Send + Sync,
+{
+    type Error = Tx::Error;
+
+    async fn push<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEvent<M>,
+    ) -> Result<(), Self::Error> {
+        if !(self.filter)(ev.source, &ev.tag) {
+            return Ok(());
+        }
+        self.tx.push(ev).await
+    }
+
+    async fn push_list<M: Metric + Send + 'static>(
+        &self,
+        mut ev: MetricEventList<M>,
+    ) -> Result<(), Self::Error> {
+        ev.metric
+            .retain(|event| (self.filter)(event.source, &event.tag));
+        if ev.metric.is_empty() {
+            return Ok(());
+        }
+        self.tx.push_list(ev).await
+    }
+}
+
+/// Sends a random fraction of the events of each tag, other events are
+/// dropped without error
+///
+/// tags without a rate set through [`Self::with_rate`] use the default rate,
+/// 1.0 (send everything) unless changed with [`Self::with_default_rate`]
+pub struct SampleMetricTx<Tx> {
+    tx: Tx,
+    default_rate: f64,
+    rates: HashMap<String, f64>,
+    rng: AtomicU64,
+}
+
+impl<Tx: Clone> Clone for SampleMetricTx<Tx> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            default_rate: self.default_rate,
+            rates: self.rates.clone(),
+            rng: AtomicU64::new(seed()),
+        }
+    }
+}
+
+/// Seed for the sampling rng, sampling does not need to be unpredictable
+fn seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    // xorshift never leaves 0
+    nanos | 1
+}
+
+impl<Tx> SampleMetricTx<Tx> {
+    pub fn new(tx: Tx) -> Self {
+        Self {
+            tx,
+            default_rate: 1.0,
+            rates: HashMap::new(),
+            rng: AtomicU64::new(seed()),
+        }
+    }
+
+    /// Fraction of events sent for tags without their own rate, clamped to [0, 1]
+    pub fn with_default_rate(mut self, rate: f64) -> Self {
+        self.default_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Fraction of `tag` events sent, clamped to [0, 1]
+    pub fn with_rate(mut self, tag: impl Into<String>, rate: f64) -> Self {
+        self.rates.insert(tag.into(), rate.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Uniform in [0, 1)
+    fn random(&self) -> f64 {
+        // xorshift64, a lost update between threads only repeats a value
+        let mut x = self.rng.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng.store(x, Ordering::Relaxed);
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn sampled(&self, tag: &str) -> bool {
+        let rate = self.rates.get(tag).copied().unwrap_or(self.default_rate);
+        rate >= 1.0 || self.random() < rate
+    }
+}
+
+impl<Tx: MetricEventTx + Sync> MetricEventTx for SampleMetricTx<Tx> {
+    type Error = Tx::Error;
+
+    async fn push<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEvent<M>,
+    ) -> Result<(), Self::Error> {
+        if !self.sampled(&ev.tag) {
+            return Ok(());
+        }
+        self.tx.push(ev).await
+    }
+
+    async fn push_list<M: Metric + Send + 'static>(
+        &self,
+        mut ev: MetricEventList<M>,
+    ) -> Result<(), Self::Error> {
+        ev.metric.retain(|event| self.sampled(&event.tag));
+        if ev.metric.is_empty() {
+            return Ok(());
+        }
+        self.tx.push_list(ev).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metric_sender::test_util::{round, rounds, withdrawal, FlakyError, FlakyMetricTx};
+
+    use super::*;
+
+    fn tee() -> (
+        FlakyMetricTx,
+        FlakyMetricTx,
+        TeeMetricTx<FlakyMetricTx, FlakyMetricTx>,
+    ) {
+        let (first, second) = (FlakyMetricTx::new(), FlakyMetricTx::new());
+        let tee = TeeMetricTx::new(first.clone(), second.clone());
+        (first, second, tee)
+    }
+
+    #[tokio::test]
+    async fn test_tee_sends_to_both() {
+        let (first, second, tee) = tee();
+
+        tee.push(round(EventSource::Yral, 0)).await.unwrap();
+        tee.push_list(MetricEventList::new(
+            EventSource::Yral,
+            "rounds".into(),
+            vec![round(EventSource::Yral, 1)],
+        ))
+        .await
+        .unwrap();
+
+        assert_eq!(rounds(&first.recording), [0, 1]);
+        assert_eq!(first.recording.events(), second.recording.events());
+    }
+
+    #[tokio::test]
+    async fn test_tee_error_mapping() {
+        let (first, second, tee) = tee();
+
+        first.set_failing(true);
+        let res = tee.push(round(EventSource::Yral, 0)).await;
+        assert!(matches!(res, Err(TeeError::First(FlakyError::Failing))));
+        // the second sender is used even if the first fails
+        assert_eq!(rounds(&second.recording), [0]);
+
+        first.set_failing(false);
+        second.set_failing(true);
+        let res = tee.push(round(EventSource::Yral, 1)).await;
+        assert!(matches!(res, Err(TeeError::Second(FlakyError::Failing))));
+        assert_eq!(rounds(&first.recording), [1]);
+
+        first.set_failing(true);
+        let res = tee
+            .push_list(MetricEventList::new(
+                EventSource::Yral,
+                "rounds".into(),
+                vec![round(EventSource::Yral, 2)],
+            ))
+            .await;
+        assert!(matches!(
+            res,
+            Err(TeeError::Both(FlakyError::Failing, FlakyError::Failing))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_filter_events() {
+        let tx = FlakyMetricTx::new();
+        let filter = FilterMetricTx::new(tx.clone(), |source, tag| {
+            source == EventSource::Yral && tag == "tides_turned"
+        });
+
+        filter.push(round(EventSource::Yral, 0)).await.unwrap();
+        filter
+            .push(round(EventSource::PumpNDumpWorker, 1))
+            .await
+            .unwrap();
+        filter.push(withdrawal(EventSource::Yral)).await.unwrap();
+
+        assert_eq!(rounds(&tx.recording), [0]);
+        assert_eq!(tx.attempts(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_filter_lists_per_event() {
+        let tx = FlakyMetricTx::new();
+        let filter = FilterMetricTx::new(tx.clone(), |source, _| source == EventSource::Yral);
+
+        filter
+            .push_list(MetricEventList::new(
+                EventSource::Yral,
+                "rounds".into(),
+                vec![
+                    round(EventSource::Yral, 0),
+                    round(EventSource::PumpNDumpWorker, 1),
+                    round(EventSource::Yral, 2),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(rounds(&tx.recording), [0, 2]);
+        tx.recording.assert_emitted("tides_turned", |ev| {
+            ev.list_tag.as_deref() == Some("rounds")
+        });
+
+        // lists left empty aren't sent
+        filter
+            .push_list(MetricEventList::new(
+                EventSource::PumpNDumpWorker,
+                "rounds".into(),
+                vec![round(EventSource::PumpNDumpWorker, 3)],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(tx.attempts(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sample_rates_clamped() {
+        let tx = FlakyMetricTx::new();
+        let sample = SampleMetricTx::new(tx.clone())
+            .with_default_rate(-1.0)
+            .with_rate("tides_turned", 2.0);
+
+        for n in 0..100 {
+            sample.push(round(EventSource::Yral, n)).await.unwrap();
+            sample.push(withdrawal(EventSource::Yral)).await.unwrap();
+        }
+
+        assert_eq!(tx.recording.events_with_tag("tides_turned").len(), 100);
+        assert!(tx.recording.events_with_tag("cents_withdrawal").is_empty());
+        assert_eq!(tx.attempts(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_sample_lists_per_event() {
+        let tx = FlakyMetricTx::new();
+        let sample = SampleMetricTx::new(tx.clone()).with_rate("cents_withdrawal", 0.0);
+
+        sample
+            .push_list(MetricEventList::new(
+                EventSource::Yral,
+                "mixed".into(),
+                vec![
+                    round(EventSource::Yral, 0).serialized().unwrap(),
+                    withdrawal(EventSource::Yral).serialized().unwrap(),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(rounds(&tx.recording), [0]);
+
+        sample
+            .push_list(MetricEventList::new(
+                EventSource::Yral,
+                "withdrawals".into(),
+                vec![withdrawal(EventSource::Yral)],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(tx.attempts(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sample_fraction() {
+        let tx = FlakyMetricTx::new();
+        let sample = SampleMetricTx::new(tx.clone()).with_default_rate(0.5);
+
+        for n in 0..1000 {
+            sample.push(round(EventSource::Yral, n)).await.unwrap();
+        }
+
+        // ~16 standard deviations either side
+        let sent = tx.recording.events().len();
+        assert!((250..750).contains(&sent), "sent {sent} of 1000");
+    }
+}
@@ -1,6 +1,12 @@
+pub mod batching;
+pub mod combinators;
 #[cfg(feature = "js")]
 pub mod js_spawn;
 pub mod mock;
+pub mod recording;
+pub mod spool;
+#[cfg(test)]
+mod test_util;
 pub mod vectordb;
 
 use std::{error::Error, future::Future};
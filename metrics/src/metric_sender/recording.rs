@@ -0,0 +1,186 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::metrics::{EventSource, Metric, MetricEvent, MetricEventList};
+
+use super::MetricEventTx;
+
+/// An event captured by [`RecordingMetricTx`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedMetric {
+    pub source: EventSource,
+    pub tag: String,
+    pub user_id: Option<String>,
+    /// the metric as sent to the ingest endpoint
+    pub payload: serde_json::Value,
+    pub unix_timestamp_secs: u64,
+    /// tag of the list the event was pushed in, `None` for single events
+    pub list_tag: Option<String>,
+}
+
+impl RecordedMetric {
+    fn new<M: Metric>(
+        ev: MetricEvent<M>,
+        list_tag: Option<String>,
+    ) -> Result<Self, serde_json::Error> {
+        let ev = ev.serialized()?;
+        Ok(Self {
+            source: ev.source,
+            tag: ev.tag,
+            user_id: ev.user_id,
+            payload: ev.metric.payload,
+            unix_timestamp_secs: ev.unix_timestamp_secs,
+            list_tag,
+        })
+    }
+}
+
+/// Records every event it receives, for assertions in tests
+///
+/// clones share the recorded events, keep one as a handle and pass the other
+/// where a sender is expected, local senders included. Events in lists are
+/// recorded one by one
+#[derive(Clone, Default)]
+pub struct RecordingMetricTx {
+    events: Arc<Mutex<Vec<RecordedMetric>>>,
+}
+
+impl RecordingMetricTx {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn recorded(&self) -> MutexGuard<'_, Vec<RecordedMetric>> {
+        // a panicking test must not hide the events from other tests
+        self.events.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// All recorded events, in the order they were pushed
+    pub fn events(&self) -> Vec<RecordedMetric> {
+        self.recorded().clone()
+    }
+
+    pub fn events_with_tag(&self, tag: &str) -> Vec<RecordedMetric> {
+        self.recorded()
+            .iter()
+            .filter(|ev| ev.tag == tag)
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.recorded().clear();
+    }
+
+    /// Panics unless an event with `tag` matching `predicate` was recorded,
+    /// returns the first match
+    ///
+    /// ```ignore
+    /// tx.assert_emitted("video_watched", |ev| ev.payload["post_id"] == 42);
+    /// ```
+    #[track_caller]
+    pub fn assert_emitted(
+        &self,
+        tag: &str,
+        predicate: impl Fn(&RecordedMetric) -> bool,
+    ) -> RecordedMetric {
+        let events = self.events_with_tag(tag);
+        if let Some(ev) = events.iter().find(|ev| predicate(ev)) {
+            return ev.clone();
+        }
+
+        if events.is_empty() {
+            let tags = self
+                .recorded()
+                .iter()
+                .map(|ev| ev.tag.clone())
+                .collect::<Vec<_>>();
+            panic!("no `{tag}` metric emitted, recorded tags: {tags:?}");
+        }
+        panic!("no matching `{tag}` metric emitted, recorded: {events:#?}");
+    }
+}
+
+impl MetricEventTx for RecordingMetricTx {
+    type Error = serde_json::Error;
+
+    async fn push<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEvent<M>,
+    ) -> Result<(), Self::Error> {
+        let ev = RecordedMetric::new(ev, None)?;
+        self.recorded().push(ev);
+        Ok(())
+    }
+
+    async fn push_list<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEventList<M>,
+    ) -> Result<(), Self::Error> {
+        let events = ev
+            .metric
+            .into_iter()
+            .map(|event| RecordedMetric::new(event, Some(ev.tag.clone())))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.recorded().extend(events);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        metric_sender::test_util::{round, withdrawal},
+        metrics::EventSource,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_records_single_events_and_lists() {
+        let tx = RecordingMetricTx::new();
+        tx.push(withdrawal(EventSource::Yral)).await.unwrap();
+        tx.push_list(MetricEventList::new(
+            EventSource::PumpNDumpWorker,
+            "rounds".into(),
+            vec![
+                round(EventSource::PumpNDumpWorker, 1),
+                round(EventSource::PumpNDumpWorker, 2),
+            ],
+        ))
+        .await
+        .unwrap();
+
+        assert_eq!(tx.events().len(), 3);
+        assert_eq!(tx.events()[0].list_tag, None);
+        let listed = tx.events_with_tag("tides_turned");
+        assert_eq!(listed.len(), 2);
+        assert!(listed
+            .iter()
+            .all(|ev| ev.list_tag.as_deref() == Some("rounds")
+                && ev.source == EventSource::PumpNDumpWorker));
+
+        let ev = tx.assert_emitted("tides_turned", |ev| ev.payload["round_num"] == 2);
+        assert_eq!(ev.payload["round_num"], 2);
+
+        tx.clear();
+        assert!(tx.events().is_empty());
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "no matching `tides_turned` metric emitted")]
+    async fn test_assert_emitted_panics_without_match() {
+        let tx = RecordingMetricTx::new();
+        tx.push(round(EventSource::Yral, 1)).await.unwrap();
+
+        tx.assert_emitted("tides_turned", |ev| ev.payload["round_num"] == 2);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "no `like_video` metric emitted, recorded tags: [\"tides_turned\"]")]
+    async fn test_assert_emitted_lists_recorded_tags() {
+        let tx = RecordingMetricTx::new();
+        tx.push(round(EventSource::Yral, 1)).await.unwrap();
+
+        tx.assert_emitted("like_video", |_| true);
+    }
+}
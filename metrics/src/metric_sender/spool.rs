@@ -0,0 +1,1010 @@
+use std::{
+    error::Error,
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use web_time::Instant;
+
+use crate::metrics::{
+    serialized::SerializedMetric, EventSource, Metric, MetricEvent, MetricEventList,
+};
+
+#[cfg(target_arch = "wasm32")]
+use super::LocalMetricEventTx;
+#[cfg(not(target_arch = "wasm32"))]
+use super::MetricEventTx;
+
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(300);
+pub const DEFAULT_MAX_RECORDS: usize = 100_000;
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 10;
+/// Records read and removed from the storage at once while replaying
+const REPLAY_CHUNK_SIZE: usize = 100;
+
+/// Where [`SpoolMetricTx`] keeps unsent events
+///
+/// records are single line JSON strings and must be returned in the order
+/// they were appended. Implement this over e.g `localStorage` or IndexedDB on wasm
+pub trait SpoolStorage {
+    type Error: Error;
+
+    /// Number of stored records
+    fn len(&self) -> Result<usize, Self::Error>;
+
+    fn is_empty(&self) -> Result<bool, Self::Error> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Up to `n` of the oldest records, oldest first
+    fn front(&self, n: usize) -> Result<Vec<String>, Self::Error>;
+
+    fn append(&self, record: String) -> Result<(), Self::Error>;
+
+    /// Remove the `n` oldest records
+    fn remove_front(&self, n: usize) -> Result<(), Self::Error>;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use file::FileSpoolStorage;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod file {
+    use std::{
+        ffi::OsString,
+        fs::{self, File, OpenOptions},
+        io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+        path::PathBuf,
+        sync::{Mutex, MutexGuard},
+    };
+
+    use super::SpoolStorage;
+
+    /// Consumed bytes kept at the start of the file before it is compacted
+    const COMPACT_MIN_BYTES: u64 = 1 << 20;
+
+    /// Position of the oldest record in the file
+    #[derive(Clone, Copy)]
+    struct Cursor {
+        offset: u64,
+        len: usize,
+    }
+
+    /// Stores records in a file, one per line
+    ///
+    /// removed records are skipped with an offset kept in `<path>.cursor`,
+    /// the file is truncated once every record is removed and compacted once
+    /// most of it was removed. Neither rewrite leaves the file longer than the
+    /// offset it replaces, so a crash in between is detected and the
+    /// remaining records are kept
+    pub struct FileSpoolStorage {
+        path: PathBuf,
+        cursor_path: PathBuf,
+        cursor: Mutex<Option<Cursor>>,
+    }
+
+    impl FileSpoolStorage {
+        pub fn new(path: impl Into<PathBuf>) -> Self {
+            let path = path.into();
+            let mut cursor_path = OsString::from(path.as_os_str());
+            cursor_path.push(".cursor");
+            Self {
+                path,
+                cursor_path: cursor_path.into(),
+                cursor: Mutex::new(None),
+            }
+        }
+
+        fn open(&self) -> io::Result<Option<File>> {
+            match File::open(&self.path) {
+                Ok(file) => Ok(Some(file)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+
+        /// Reader positioned at the oldest record
+        fn reader(&self, offset: u64) -> io::Result<Option<BufReader<File>>> {
+            let Some(mut file) = self.open()? else {
+                return Ok(None);
+            };
+            file.seek(SeekFrom::Start(offset))?;
+            Ok(Some(BufReader::new(file)))
+        }
+
+        fn file_len(&self) -> io::Result<u64> {
+            match fs::metadata(&self.path) {
+                Ok(meta) => Ok(meta.len()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+                Err(e) => Err(e),
+            }
+        }
+
+        fn write_offset(&self, offset: u64) -> io::Result<()> {
+            // write then rename, so a crash never leaves a partial cursor
+            let mut tmp = self.cursor_path.clone().into_os_string();
+            tmp.push(".tmp");
+            fs::write(&tmp, offset.to_string())?;
+            fs::rename(tmp, &self.cursor_path)
+        }
+
+        /// Skip up to `n` records from `offset`, returns the records skipped
+        /// and the offset after them
+        fn skip(&self, offset: u64, n: usize) -> io::Result<(usize, u64)> {
+            let Some(mut reader) = self.reader(offset)? else {
+                return Ok((0, offset));
+            };
+            let (mut skipped, mut offset) = (0, offset);
+            let mut line = Vec::new();
+            while skipped < n {
+                line.clear();
+                let read = reader.read_until(b'\n', &mut line)?;
+                if read == 0 {
+                    break;
+                }
+                offset += read as u64;
+                if !line.trim_ascii().is_empty() {
+                    skipped += 1;
+                }
+            }
+            Ok((skipped, offset))
+        }
+
+        fn lock(&self) -> io::Result<(MutexGuard<'_, Option<Cursor>>, Cursor)> {
+            let mut guard = self.cursor.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(cursor) = *guard {
+                return Ok((guard, cursor));
+            }
+
+            let offset = match fs::read_to_string(&self.cursor_path) {
+                Ok(offset) => offset.trim().parse().unwrap_or(0),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+                Err(e) => return Err(e),
+            };
+            // the file was truncated or compacted after the offset was written
+            let offset = if offset > self.file_len()? {
+                self.write_offset(0)?;
+                0
+            } else {
+                offset
+            };
+            let (len, _) = self.skip(offset, usize::MAX)?;
+
+            let cursor = Cursor { offset, len };
+            *guard = Some(cursor);
+            Ok((guard, cursor))
+        }
+
+        /// Drop the removed records before `offset` from the file
+        fn compact(&self, offset: u64) -> io::Result<()> {
+            let mut tmp = self.path.clone().into_os_string();
+            tmp.push(".tmp");
+            {
+                let mut reader = self.reader(offset)?.ok_or(io::ErrorKind::NotFound)?;
+                let mut writer = BufWriter::new(File::create(&tmp)?);
+                io::copy(&mut reader, &mut writer)?;
+                writer.into_inner()?.sync_all()?;
+            }
+            fs::rename(tmp, &self.path)?;
+            self.write_offset(0)
+        }
+    }
+
+    impl SpoolStorage for FileSpoolStorage {
+        type Error = io::Error;
+
+        fn len(&self) -> io::Result<usize> {
+            Ok(self.lock()?.1.len)
+        }
+
+        fn front(&self, n: usize) -> io::Result<Vec<String>> {
+            let (_guard, cursor) = self.lock()?;
+            let Some(reader) = self.reader(cursor.offset)? else {
+                return Ok(vec![]);
+            };
+            let mut records = Vec::new();
+            for line in reader.lines() {
+                if records.len() == n {
+                    break;
+                }
+                let line = line?;
+                if !line.trim().is_empty() {
+                    records.push(line);
+                }
+            }
+            Ok(records)
+        }
+
+        fn append(&self, record: String) -> io::Result<()> {
+            let (mut guard, mut cursor) = self.lock()?;
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            writeln!(file, "{record}")?;
+            cursor.len += 1;
+            *guard = Some(cursor);
+            Ok(())
+        }
+
+        fn remove_front(&self, n: usize) -> io::Result<()> {
+            let (mut guard, cursor) = self.lock()?;
+            let (removed, offset) = self.skip(cursor.offset, n)?;
+            if removed == 0 {
+                return Ok(());
+            }
+            let file_len = self.file_len()?;
+
+            let offset = if offset >= file_len {
+                OpenOptions::new()
+                    .write(true)
+                    .open(&self.path)?
+                    .set_len(0)?;
+                self.write_offset(0)?;
+                0
+            } else if offset >= COMPACT_MIN_BYTES && offset > file_len - offset {
+                self.compact(offset)?;
+                0
+            } else {
+                self.write_offset(offset)?;
+                offset
+            };
+
+            *guard = Some(Cursor {
+                offset,
+                len: cursor.len.saturating_sub(removed),
+            });
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SpoolError<E> {
+    #[error("failed to serialize metric: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("failed to access spool: {0}")]
+    Storage(E),
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredEvent {
+    source: EventSource,
+    tag: String,
+    user_id: Option<String>,
+    metric_tag: String,
+    metric_user_id: Option<String>,
+    metric_user_canister: Option<Principal>,
+    metric: serde_json::Value,
+    unix_timestamp_secs: u64,
+    page_location: String,
+    host: String,
+}
+
+impl StoredEvent {
+    fn new<M: Metric>(ev: MetricEvent<M>) -> Result<Self, serde_json::Error> {
+        let metric = SerializedMetric::new(&ev.metric)?;
+        Ok(Self {
+            source: ev.source,
+            tag: ev.tag,
+            user_id: ev.user_id,
+            metric_tag: metric.tag,
+            metric_user_id: metric.user_id,
+            metric_user_canister: metric.user_canister,
+            metric: metric.payload,
+            unix_timestamp_secs: ev.unix_timestamp_secs,
+            page_location: ev.page_location,
+            host: ev.host,
+        })
+    }
+
+    fn into_event(self) -> MetricEvent<SerializedMetric> {
+        MetricEvent {
+            source: self.source,
+            tag: self.tag,
+            user_id: self.user_id,
+            metric: SerializedMetric {
+                tag: self.metric_tag,
+                user_id: self.metric_user_id,
+                user_canister: self.metric_user_canister,
+                payload: self.metric,
+            },
+            unix_timestamp_secs: self.unix_timestamp_secs,
+            page_location: self.page_location,
+            host: self.host,
+        }
+    }
+}
+
+/// An event or list as it was pushed, with its original timestamps
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum SpoolRecord {
+    Event(StoredEvent),
+    List {
+        source: EventSource,
+        tag: String,
+        user_id: Option<String>,
+        unix_timestamp_secs: u64,
+        events: Vec<StoredEvent>,
+    },
+}
+
+impl SpoolRecord {
+    fn event<M: Metric>(ev: MetricEvent<M>) -> Result<Self, serde_json::Error> {
+        StoredEvent::new(ev).map(Self::Event)
+    }
+
+    fn list<M: Metric>(ev: MetricEventList<M>) -> Result<Self, serde_json::Error> {
+        Ok(Self::List {
+            source: ev.source,
+            tag: ev.tag,
+            user_id: ev.user_id,
+            unix_timestamp_secs: ev.unix_timestamp_secs,
+            events: ev
+                .metric
+                .into_iter()
+                .map(StoredEvent::new)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// What to send, a record is either an event or a list
+enum Replayed {
+    Event(MetricEvent<SerializedMetric>),
+    List(MetricEventList<SerializedMetric>),
+}
+
+impl From<SpoolRecord> for Replayed {
+    fn from(record: SpoolRecord) -> Self {
+        match record {
+            SpoolRecord::Event(ev) => Self::Event(ev.into_event()),
+            SpoolRecord::List {
+                source,
+                tag,
+                user_id,
+                unix_timestamp_secs,
+                events,
+            } => Self::List(MetricEventList {
+                source,
+                tag,
+                metric: events.into_iter().map(StoredEvent::into_event).collect(),
+                user_id,
+                unix_timestamp_secs,
+            }),
+        }
+    }
+}
+
+struct State {
+    /// records in the spool, `None` until the storage is read
+    len: Option<usize>,
+    backoff: Duration,
+    /// no replay before this
+    retry_at: Option<Instant>,
+    /// failed replays of the oldest record
+    front_attempts: u32,
+    replaying: bool,
+}
+
+/// takes a metric sender and stores events it fails to send, replaying them
+/// later in the order they were pushed
+///
+/// events keep the timestamps they were created with. While the spool is not
+/// empty new events are spooled too, so they are never sent before older ones.
+/// Replays are attempted on push, backing off exponentially after failures,
+/// use [`Self::replay_periodically`] to also replay while idle.
+/// A record that failed `max_attempts` replays in a row (e.g it is rejected
+/// by the endpoint) is moved to the dead letter storage, or dropped without
+/// one, so it doesn't hold back the rest. Once the spool holds `max_records`,
+/// new events are dropped
+pub struct SpoolMetricTx<Tx, S> {
+    tx: Tx,
+    storage: Arc<S>,
+    dead_letter: Option<Arc<S>>,
+    state: Arc<Mutex<State>>,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    max_records: usize,
+    max_attempts: u32,
+}
+
+impl<Tx: Clone, S> Clone for SpoolMetricTx<Tx, S> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            storage: self.storage.clone(),
+            dead_letter: self.dead_letter.clone(),
+            state: self.state.clone(),
+            initial_backoff: self.initial_backoff,
+            max_backoff: self.max_backoff,
+            max_records: self.max_records,
+            max_attempts: self.max_attempts,
+        }
+    }
+}
+
+impl<Tx, S: SpoolStorage> SpoolMetricTx<Tx, S> {
+    pub fn new(tx: Tx, storage: S) -> Self {
+        Self {
+            tx,
+            storage: Arc::new(storage),
+            dead_letter: None,
+            state: Arc::new(Mutex::new(State {
+                len: None,
+                backoff: DEFAULT_INITIAL_BACKOFF,
+                retry_at: None,
+                front_attempts: 0,
+                replaying: false,
+            })),
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            max_records: DEFAULT_MAX_RECORDS,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        }
+    }
+
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self.state().backoff = initial;
+        self
+    }
+
+    /// Max events or lists kept in the spool
+    pub fn with_max_records(mut self, max_records: usize) -> Self {
+        self.max_records = max_records;
+        self
+    }
+
+    /// Failed replays in a row before a record is dead lettered
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Keep dead lettered records in `storage` instead of dropping them
+    pub fn with_dead_letter(mut self, storage: S) -> Self {
+        self.dead_letter = Some(Arc::new(storage));
+        self
+    }
+
+    /// Events and lists waiting to be replayed
+    pub fn spooled(&self) -> Result<usize, SpoolError<S::Error>> {
+        let mut state = self.state();
+        self.len(&mut state)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // the state is never left half updated
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn len(&self, state: &mut State) -> Result<usize, SpoolError<S::Error>> {
+        if let Some(len) = state.len {
+            return Ok(len);
+        }
+        let len = self.storage.len().map_err(SpoolError::Storage)?;
+        state.len = Some(len);
+        Ok(len)
+    }
+
+    fn is_empty(&self) -> Result<bool, SpoolError<S::Error>> {
+        self.spooled().map(|len| len == 0)
+    }
+
+    fn spool(&self, record: String) -> Result<(), SpoolError<S::Error>> {
+        let mut state = self.state();
+        if self.len(&mut state)? >= self.max_records {
+            log::warn!("metric spool full, dropping event");
+            return Ok(());
+        }
+        self.storage.append(record).map_err(SpoolError::Storage)?;
+        state.len = state.len.map(|len| len + 1);
+        Ok(())
+    }
+
+    fn failed(&self) {
+        let mut state = self.state();
+        state.retry_at = Some(Instant::now() + state.backoff);
+        state.backoff = (state.backoff * 2).min(self.max_backoff);
+    }
+
+    fn succeeded(&self) {
+        let mut state = self.state();
+        state.retry_at = None;
+        state.backoff = self.initial_backoff;
+    }
+
+    fn replay_due(&self) -> bool {
+        let state = self.state();
+        state.len != Some(0)
+            && !state.replaying
+            && state.retry_at.is_none_or(|at| Instant::now() >= at)
+    }
+
+    /// Time until the next replay is due
+    fn retry_in(&self) -> Duration {
+        let state = self.state();
+        state.retry_at.map_or(self.initial_backoff, |at| {
+            at.saturating_duration_since(Instant::now())
+        })
+    }
+
+    async fn push_with<F, Fut, E>(
+        &self,
+        record: SpoolRecord,
+        send: F,
+    ) -> Result<(), SpoolError<S::Error>>
+    where
+        F: Fn(Replayed) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: std::fmt::Display,
+    {
+        let line = serde_json::to_string(&record)?;
+        if self.is_empty()? {
+            match send(record.into()).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    log::warn!("failed to send metric, spooling {e}");
+                    self.failed();
+                }
+            }
+        }
+
+        self.spool(line)?;
+        if self.replay_due() {
+            self.replay_with(send).await?;
+        }
+        Ok(())
+    }
+
+    /// Replay spooled records until one fails, returns how many were sent
+    async fn replay_with<F, Fut, E>(&self, send: F) -> Result<usize, SpoolError<S::Error>>
+    where
+        F: Fn(Replayed) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: std::fmt::Display,
+    {
+        {
+            let mut state = self.state();
+            if state.replaying {
+                return Ok(0);
+            }
+            state.replaying = true;
+        }
+        let res = self.replay_records(send).await;
+        self.state().replaying = false;
+        res
+    }
+
+    async fn replay_records<F, Fut, E>(&self, send: F) -> Result<usize, SpoolError<S::Error>>
+    where
+        F: Fn(Replayed) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: std::fmt::Display,
+    {
+        {
+            // appends hold the state lock, so the count matches the storage
+            let mut state = self.state();
+            state.len = Some(self.storage.len().map_err(SpoolError::Storage)?);
+        }
+
+        let mut sent = 0;
+        loop {
+            let records = self
+                .storage
+                .front(REPLAY_CHUNK_SIZE)
+                .map_err(SpoolError::Storage)?;
+            if records.is_empty() {
+                break;
+            }
+
+            // sent records are removed a chunk at a time,
+            // a crash replays at most a chunk of records twice
+            let mut done = 0;
+            for line in records {
+                match serde_json::from_str::<SpoolRecord>(&line) {
+                    Ok(record) => match send(record.into()).await {
+                        Ok(()) => {
+                            self.state().front_attempts = 0;
+                            sent += 1;
+                        }
+                        Err(e) if self.retry_front() => {
+                            log::warn!("failed to replay metrics {e}");
+                            self.remove_front(done)?;
+                            self.failed();
+                            return Ok(sent);
+                        }
+                        Err(e) => {
+                            log::error!("dead lettering metric after repeated failures {e}");
+                            if let Err(e) = self.dead_letter(line) {
+                                self.remove_front(done)?;
+                                return Err(e);
+                            }
+                        }
+                    },
+                    Err(e) => log::warn!("dropping invalid spooled metric {e}"),
+                }
+                done += 1;
+            }
+            self.remove_front(done)?;
+        }
+
+        self.succeeded();
+        Ok(sent)
+    }
+
+    /// Count a failed replay of the oldest record,
+    /// returns whether it should be retried later
+    fn retry_front(&self) -> bool {
+        let mut state = self.state();
+        state.front_attempts += 1;
+        if state.front_attempts < self.max_attempts {
+            return true;
+        }
+        state.front_attempts = 0;
+        false
+    }
+
+    fn dead_letter(&self, record: String) -> Result<(), SpoolError<S::Error>> {
+        let Some(dead_letter) = &self.dead_letter else {
+            return Ok(());
+        };
+        dead_letter.append(record).map_err(SpoolError::Storage)
+    }
+
+    fn remove_front(&self, n: usize) -> Result<(), SpoolError<S::Error>> {
+        if n == 0 {
+            return Ok(());
+        }
+        let mut state = self.state();
+        self.storage.remove_front(n).map_err(SpoolError::Storage)?;
+        state.len = state.len.map(|len| len.saturating_sub(n));
+        Ok(())
+    }
+
+    async fn replay_periodically_with<SL, SF, F, FF>(&self, sleep: SL, replay: F)
+    where
+        SL: Fn(Duration) -> SF,
+        SF: Future<Output = ()>,
+        F: Fn() -> FF,
+        FF: Future<Output = Result<usize, SpoolError<S::Error>>>,
+    {
+        loop {
+            sleep(self.retry_in()).await;
+            if !self.replay_due() {
+                continue;
+            }
+            if let Err(e) = replay().await {
+                log::warn!("failed to replay metrics {e}");
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<Tx, S> SpoolMetricTx<Tx, S>
+where
+    Tx: MetricEventTx + Sync,
+    S: SpoolStorage + Send + Sync,
+{
+    async fn send(&self, replayed: Replayed) -> Result<(), Tx::Error> {
+        match replayed {
+            Replayed::Event(ev) => self.tx.push(ev).await,
+            Replayed::List(ev) => self.tx.push_list(ev).await,
+        }
+    }
+
+    /// Send spooled events until one fails, returns how many events or lists were sent
+    pub async fn replay(&self) -> Result<usize, SpoolError<S::Error>> {
+        self.replay_with(|replayed| self.send(replayed)).await
+    }
+
+    /// Replay whenever the backoff allows it, never returns
+    ///
+    /// `sleep` is the runtime's timer, e.g `tokio::time::sleep`
+    pub async fn replay_periodically<SL, SF>(&self, sleep: SL)
+    where
+        SL: Fn(Duration) -> SF,
+        SF: Future<Output = ()>,
+    {
+        self.replay_periodically_with(sleep, || self.replay()).await
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<Tx, S> MetricEventTx for SpoolMetricTx<Tx, S>
+where
+    Tx: MetricEventTx + Sync,
+    S: SpoolStorage + Send + Sync,
+{
+    type Error = SpoolError<S::Error>;
+
+    async fn push<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEvent<M>,
+    ) -> Result<(), Self::Error> {
+        self.push_with(SpoolRecord::event(ev)?, |replayed| self.send(replayed))
+            .await
+    }
+
+    async fn push_list<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEventList<M>,
+    ) -> Result<(), Self::Error> {
+        self.push_with(SpoolRecord::list(ev)?, |replayed| self.send(replayed))
+            .await
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl<Tx: LocalMetricEventTx, S: SpoolStorage> SpoolMetricTx<Tx, S> {
+    async fn send(&self, replayed: Replayed) -> Result<(), Tx::Error> {
+        match replayed {
+            Replayed::Event(ev) => self.tx.push_local(ev).await,
+            Replayed::List(ev) => self.tx.push_list_local(ev).await,
+        }
+    }
+
+    /// Send spooled events until one fails, returns how many events or lists were sent
+    pub async fn replay(&self) -> Result<usize, SpoolError<S::Error>> {
+        self.replay_with(|replayed| self.send(replayed)).await
+    }
+
+    /// Replay whenever the backoff allows it, never returns
+    ///
+    /// `sleep` is the runtime's timer, e.g `gloo_timers::future::sleep`
+    pub async fn replay_periodically<SL, SF>(&self, sleep: SL)
+    where
+        SL: Fn(Duration) -> SF,
+        SF: Future<Output = ()>,
+    {
+        self.replay_periodically_with(sleep, || self.replay()).await
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl<Tx: LocalMetricEventTx, S: SpoolStorage> LocalMetricEventTx for SpoolMetricTx<Tx, S> {
+    type Error = SpoolError<S::Error>;
+
+    async fn push_local<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEvent<M>,
+    ) -> Result<(), Self::Error> {
+        self.push_with(SpoolRecord::event(ev)?, |replayed| self.send(replayed))
+            .await
+    }
+
+    async fn push_list_local<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEventList<M>,
+    ) -> Result<(), Self::Error> {
+        self.push_with(SpoolRecord::list(ev)?, |replayed| self.send(replayed))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use crate::{
+        metric_sender::test_util::{round, rounds, withdrawal, FlakyMetricTx},
+        metrics::{EventSource, MetricEventList},
+    };
+
+    use super::*;
+
+    const LONG: Duration = Duration::from_secs(3600);
+
+    /// Spool file in the temp dir, removed on drop
+    struct TempSpool(PathBuf);
+
+    impl TempSpool {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("yral-metrics-{name}-{}", std::process::id()));
+            let spool = Self(path);
+            spool.remove();
+            spool
+        }
+
+        fn storage(&self) -> FileSpoolStorage {
+            FileSpoolStorage::new(&self.0)
+        }
+
+        fn cursor(&self) -> PathBuf {
+            PathBuf::from(format!("{}.cursor", self.0.display()))
+        }
+
+        fn file_len(&self) -> u64 {
+            fs::metadata(&self.0).map(|meta| meta.len()).unwrap_or(0)
+        }
+
+        fn remove(&self) {
+            _ = fs::remove_file(&self.0);
+            _ = fs::remove_file(self.cursor());
+        }
+    }
+
+    impl Drop for TempSpool {
+        fn drop(&mut self) {
+            self.remove();
+        }
+    }
+
+    fn records(range: std::ops::Range<usize>) -> Vec<String> {
+        range.map(|n| format!("record {n}")).collect()
+    }
+
+    fn append_all(storage: &FileSpoolStorage, records: Vec<String>) {
+        for record in records {
+            storage.append(record).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_file_storage_removes_front_without_rewriting() {
+        let spool = TempSpool::new("remove-front");
+        let storage = spool.storage();
+        append_all(&storage, records(0..5));
+        let file_len = spool.file_len();
+
+        storage.remove_front(2).unwrap();
+        assert_eq!(storage.len().unwrap(), 3);
+        assert_eq!(storage.front(10).unwrap(), records(2..5));
+        assert_eq!(spool.file_len(), file_len);
+
+        // a restart picks up from the cursor
+        let storage = spool.storage();
+        assert_eq!(storage.len().unwrap(), 3);
+        assert_eq!(storage.front(2).unwrap(), records(2..4));
+    }
+
+    #[test]
+    fn test_file_storage_truncated_once_drained() {
+        let spool = TempSpool::new("drained");
+        let storage = spool.storage();
+        append_all(&storage, records(0..3));
+
+        storage.remove_front(5).unwrap();
+        assert!(storage.is_empty().unwrap());
+        assert_eq!(spool.file_len(), 0);
+
+        storage.append("record 3".into()).unwrap();
+        assert_eq!(spool.storage().front(10).unwrap(), records(3..4));
+    }
+
+    #[test]
+    fn test_file_storage_compacts_mostly_removed_file() {
+        let spool = TempSpool::new("compact");
+        let storage = spool.storage();
+        let padded = |n: usize| format!("{n:0>1000}");
+        append_all(&storage, (0..2000).map(padded).collect());
+
+        storage.remove_front(1900).unwrap();
+        assert_eq!(spool.file_len(), 100 * 1001);
+        assert_eq!(storage.front(1).unwrap(), [padded(1900)]);
+
+        let storage = spool.storage();
+        assert_eq!(storage.len().unwrap(), 100);
+        assert_eq!(storage.front(1).unwrap(), [padded(1900)]);
+    }
+
+    #[test]
+    fn test_file_storage_ignores_cursor_past_the_end() {
+        let spool = TempSpool::new("stale-cursor");
+        append_all(&spool.storage(), records(0..3));
+        // as left by a crash between truncating the file and updating the cursor
+        fs::write(spool.cursor(), "1000").unwrap();
+
+        let storage = spool.storage();
+        assert_eq!(storage.len().unwrap(), 3);
+        assert_eq!(storage.front(10).unwrap(), records(0..3));
+    }
+
+    #[tokio::test]
+    async fn test_sends_directly_while_empty() {
+        let spool = TempSpool::new("direct");
+        let tx = FlakyMetricTx::new();
+        let spooling = SpoolMetricTx::new(tx.clone(), spool.storage());
+
+        spooling.push(round(EventSource::Yral, 0)).await.unwrap();
+        assert_eq!(rounds(&tx.recording), [0]);
+        assert_eq!(spooling.spooled().unwrap(), 0);
+        assert_eq!(spool.file_len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_replays_in_order_with_original_timestamps() {
+        let spool = TempSpool::new("order");
+        let tx = FlakyMetricTx::new();
+        let spooling = SpoolMetricTx::new(tx.clone(), spool.storage()).with_backoff(LONG, LONG);
+        tx.set_failing(true);
+
+        let mut ev = round(EventSource::Yral, 0);
+        ev.unix_timestamp_secs = 42;
+        spooling.push(ev).await.unwrap();
+        let mut listed = round(EventSource::Yral, 1);
+        listed.unix_timestamp_secs = 43;
+        spooling
+            .push_list(MetricEventList::new(
+                EventSource::Yral,
+                "rounds".into(),
+                vec![listed, round(EventSource::Yral, 2)],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(spooling.spooled().unwrap(), 2);
+
+        tx.set_failing(false);
+        assert_eq!(spooling.replay().await.unwrap(), 2);
+
+        assert_eq!(rounds(&tx.recording), [0, 1, 2]);
+        let events = tx.recording.events();
+        assert_eq!(events[0].unix_timestamp_secs, 42);
+        assert_eq!(events[0].list_tag, None);
+        assert_eq!(events[1].unix_timestamp_secs, 43);
+        assert_eq!(events[1].list_tag.as_deref(), Some("rounds"));
+        assert_eq!(spooling.spooled().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_backoff_gates_replay_on_push() {
+        let spool = TempSpool::new("backoff");
+        let tx = FlakyMetricTx::new();
+        let backoff = Duration::from_millis(50);
+        let spooling =
+            SpoolMetricTx::new(tx.clone(), spool.storage()).with_backoff(backoff, backoff);
+        tx.set_failing(true);
+
+        spooling.push(round(EventSource::Yral, 0)).await.unwrap();
+        tx.set_failing(false);
+        spooling.push(round(EventSource::Yral, 1)).await.unwrap();
+        // spooled behind the first event, nothing sent while backing off
+        assert_eq!(tx.attempts(), 1);
+        assert_eq!(spooling.spooled().unwrap(), 2);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        spooling.push(round(EventSource::Yral, 2)).await.unwrap();
+        assert_eq!(rounds(&tx.recording), [0, 1, 2]);
+        assert_eq!(spooling.spooled().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_record_dead_lettered() {
+        let spool = TempSpool::new("rejected");
+        let dead = TempSpool::new("rejected-dead-letter");
+        let tx = FlakyMetricTx::new();
+        let spooling = SpoolMetricTx::new(tx.clone(), spool.storage())
+            .with_backoff(LONG, LONG)
+            .with_max_attempts(2)
+            .with_dead_letter(dead.storage());
+        tx.set_failing(true);
+
+        spooling.push(round(EventSource::Yral, 0)).await.unwrap();
+        spooling.push(withdrawal(EventSource::Yral)).await.unwrap();
+        spooling.push(round(EventSource::Yral, 1)).await.unwrap();
+        tx.set_failing(false);
+        tx.reject_tag("cents_withdrawal");
+
+        assert_eq!(spooling.replay().await.unwrap(), 1);
+        // only sent records are removed, a restart still has the rest
+        assert_eq!(spooling.spooled().unwrap(), 2);
+        let restarted = SpoolMetricTx::new(tx.clone(), spool.storage());
+        assert_eq!(restarted.spooled().unwrap(), 2);
+
+        assert_eq!(spooling.replay().await.unwrap(), 1);
+        assert_eq!(rounds(&tx.recording), [0, 1]);
+        assert_eq!(spooling.spooled().unwrap(), 0);
+        let dead_lettered = dead.storage().front(10).unwrap();
+        assert_eq!(dead_lettered.len(), 1);
+        assert!(dead_lettered[0].contains("cents_withdrawal"));
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use candid::{Nat, Principal};
+use thiserror::Error;
+
+use crate::metrics::{
+    cents_withdrawal::CentsWithdrawal, tides_turned::TidesTurned, EventSource, Metric, MetricEvent,
+    MetricEventList,
+};
+
+use super::{recording::RecordingMetricTx, MetricEventTx};
+
+#[derive(Debug, Error)]
+pub enum FlakyError {
+    #[error("sender is failing")]
+    Failing,
+    #[error("`{0}` rejected")]
+    Rejected(String),
+    #[error(transparent)]
+    Recording(#[from] serde_json::Error),
+}
+
+/// Records through a [`RecordingMetricTx`] unless told to fail
+#[derive(Clone, Default)]
+pub struct FlakyMetricTx {
+    pub recording: RecordingMetricTx,
+    failing: Arc<AtomicBool>,
+    rejected_tag: Arc<Mutex<Option<String>>>,
+    attempts: Arc<AtomicUsize>,
+}
+
+impl FlakyMetricTx {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail every push until called with `false`
+    pub fn set_failing(&self, failing: bool) {
+        self.failing.store(failing, Ordering::SeqCst);
+    }
+
+    /// Fail every push of events or lists tagged `tag`
+    pub fn reject_tag(&self, tag: &str) {
+        *self.rejected_tag.lock().unwrap() = Some(tag.to_string());
+    }
+
+    /// Pushes received, failed ones included
+    pub fn attempts(&self) -> usize {
+        self.attempts.load(Ordering::SeqCst)
+    }
+
+    fn check(&self, tag: &str) -> Result<(), FlakyError> {
+        self.attempts.fetch_add(1, Ordering::SeqCst);
+        if self.failing.load(Ordering::SeqCst) {
+            return Err(FlakyError::Failing);
+        }
+        if self.rejected_tag.lock().unwrap().as_deref() == Some(tag) {
+            return Err(FlakyError::Rejected(tag.to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl MetricEventTx for FlakyMetricTx {
+    type Error = FlakyError;
+
+    async fn push<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEvent<M>,
+    ) -> Result<(), Self::Error> {
+        self.check(&ev.tag)?;
+        Ok(self.recording.push(ev).await?)
+    }
+
+    async fn push_list<M: Metric + Send + 'static>(
+        &self,
+        ev: MetricEventList<M>,
+    ) -> Result<(), Self::Error> {
+        self.check(&ev.tag)?;
+        Ok(self.recording.push_list(ev).await?)
+    }
+}
+
+/// An event identified by its `round_num`
+pub fn round(source: EventSource, round_num: u64) -> MetricEvent<TidesTurned> {
+    MetricEvent::new(
+        source,
+        TidesTurned {
+            user_canister: Principal::anonymous(),
+            staked_amount: 0,
+            round_num,
+            user_pumps: 0,
+            user_dumps: 0,
+            round_pumps: 0,
+            round_dumps: 0,
+            cumulative_pumps: 0,
+            cumulative_dumps: 0,
+            token_root: Principal::anonymous(),
+        },
+    )
+}
+
+/// An event with a different tag than [`round`]
+pub fn withdrawal(source: EventSource) -> MetricEvent<CentsWithdrawal> {
+    MetricEvent::new(
+        source,
+        CentsWithdrawal {
+            user_canister: Principal::anonymous(),
+            amount: Nat::from(1u64),
+        },
+    )
+}
+
+/// `round_num` of the recorded events, in order
+pub fn rounds(recording: &RecordingMetricTx) -> Vec<u64> {
+    recording
+        .events()
+        .iter()
+        .map(|ev| ev.payload["round_num"].as_u64().unwrap())
+        .collect()
+}
@@ -1,53 +1,170 @@
-use reqwest::Url;
+use std::{io::Write, time::Duration};
+
+use flate2::{write::GzEncoder, Compression};
+use reqwest::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    StatusCode, Url,
+};
+use serde::Serialize;
+use thiserror::Error;
 
 use crate::metrics::{Metric, MetricEvent, MetricEventList};
 
 const VECTOR_DB_URL: &str = "https://vector-dev-yral.fly.dev/";
 
+#[derive(Debug, Error)]
+pub enum VectorDbError {
+    #[error("failed to serialize metrics: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("failed to compress metrics: {0}")]
+    Compress(#[from] std::io::Error),
+    #[error("failed to send metrics: {0}")]
+    Http(#[from] reqwest::Error),
+    /// The ingest endpoint answered with a non-2xx status, the events were not stored
+    #[error("metrics rejected with status {status}: {body}")]
+    Rejected { status: StatusCode, body: String },
+}
+
 /// Sends metrics to Yral's vectordb instance
 #[derive(Clone)]
 pub struct VectorDbMetricTx {
     client: reqwest::Client,
     ingest_url: Url,
+    bearer_token: Option<String>,
+    headers: HeaderMap,
+    gzip: bool,
 }
 
 impl Default for VectorDbMetricTx {
+    fn default() -> Self {
+        VectorDbMetricTxBuilder::default().build().unwrap()
+    }
+}
+
+/// Builder for [`VectorDbMetricTx`], defaults to Yral's dev instance without auth
+#[derive(Clone, Debug)]
+pub struct VectorDbMetricTxBuilder {
+    ingest_url: Url,
+    bearer_token: Option<String>,
+    headers: HeaderMap,
+    gzip: bool,
+    timeout: Option<Duration>,
+}
+
+impl Default for VectorDbMetricTxBuilder {
     fn default() -> Self {
         Self {
-            client: reqwest::Client::new(),
             ingest_url: VECTOR_DB_URL.parse().unwrap(),
+            bearer_token: None,
+            headers: HeaderMap::new(),
+            gzip: false,
+            timeout: None,
         }
     }
 }
 
+impl VectorDbMetricTxBuilder {
+    pub fn with_ingest_url(mut self, ingest_url: Url) -> Self {
+        self.ingest_url = ingest_url;
+        self
+    }
+
+    /// Sent as `Authorization: Bearer <token>`
+    pub fn with_bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.bearer_token = Some(token.into());
+        self
+    }
+
+    /// Header added to every request, replaces earlier values of the same header
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Gzip request bodies, the endpoint must accept `Content-Encoding: gzip`
+    pub fn with_gzip(mut self, gzip: bool) -> Self {
+        self.gzip = gzip;
+        self
+    }
+
+    /// Request timeout, only applies outside the browser (wasm32)
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn build(self) -> Result<VectorDbMetricTx, reqwest::Error> {
+        #[allow(unused_mut)]
+        let mut builder = reqwest::Client::builder();
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        Ok(VectorDbMetricTx {
+            client: builder.build()?,
+            ingest_url: self.ingest_url,
+            bearer_token: self.bearer_token,
+            headers: self.headers,
+            gzip: self.gzip,
+        })
+    }
+}
+
 impl VectorDbMetricTx {
-    async fn push_inner<M: Metric + Send>(&self, ev: MetricEvent<M>) -> Result<(), reqwest::Error> {
-        _ = self
+    pub fn builder() -> VectorDbMetricTxBuilder {
+        VectorDbMetricTxBuilder::default()
+    }
+
+    /// JSON body, serialized before sending so events need not be `Sync`
+    fn body(&self, ev: &impl Serialize) -> Result<Vec<u8>, VectorDbError> {
+        let body = serde_json::to_vec(ev)?;
+        if !self.gzip {
+            return Ok(body);
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&body)?;
+        Ok(encoder.finish()?)
+    }
+
+    async fn send(&self, body: Vec<u8>) -> Result<(), VectorDbError> {
+        let mut req = self
             .client
             .post(self.ingest_url.clone())
-            .json(&ev)
-            .send()
-            .await?;
+            .headers(self.headers.clone())
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(token) = &self.bearer_token {
+            req = req.bearer_auth(token);
+        }
+        if self.gzip {
+            req = req.header(header::CONTENT_ENCODING, "gzip");
+        }
+
+        let res = req.body(body).send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(VectorDbError::Rejected { status, body });
+        }
         Ok(())
     }
 
+    async fn push_inner<M: Metric + Send>(&self, ev: MetricEvent<M>) -> Result<(), VectorDbError> {
+        self.send(self.body(&ev)?).await
+    }
+
     async fn push_list_inner<M: Metric + Send>(
         &self,
         ev: MetricEventList<M>,
-    ) -> Result<(), reqwest::Error> {
-        _ = self
-            .client
-            .post(self.ingest_url.clone())
-            .json(&ev)
-            .send()
-            .await?;
-        Ok(())
+    ) -> Result<(), VectorDbError> {
+        self.send(self.body(&ev)?).await
     }
 }
 
 #[cfg(feature = "js")]
 impl super::LocalMetricEventTx for VectorDbMetricTx {
-    type Error = reqwest::Error;
+    type Error = VectorDbError;
 
     async fn push_local<M: Metric + Send>(&self, ev: MetricEvent<M>) -> Result<(), Self::Error> {
         self.push_inner(ev).await
@@ -63,7 +180,7 @@ impl super::LocalMetricEventTx for VectorDbMetricTx {
 
 #[cfg(not(feature = "js"))]
 impl super::MetricEventTx for VectorDbMetricTx {
-    type Error = reqwest::Error;
+    type Error = VectorDbError;
 
     async fn push<M: Metric + Send>(&self, ev: MetricEvent<M>) -> Result<(), Self::Error> {
         self.push_inner(ev).await
@@ -72,4 +189,152 @@ impl super::MetricEventTx for VectorDbMetricTx {
     async fn push_list<M: Metric + Send>(&self, ev: MetricEventList<M>) -> Result<(), Self::Error> {
         self.push_list_inner(ev).await
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+
+    use super::*;
+    use crate::{
+        metric_sender::{test_util::round, MetricEventTx},
+        metrics::EventSource,
+    };
+
+    /// A request received by [`serve_once`]
+    struct Request {
+        /// request line and headers, lowercased
+        head: String,
+        body: Vec<u8>,
+    }
+
+    /// Answers a single request with `status` and `body`
+    async fn serve_once(status: u16, body: &'static str) -> (Url, JoinHandle<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut req = Vec::new();
+            let mut buf = [0u8; 4096];
+            let req = loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                req.extend_from_slice(&buf[..n]);
+                if let Some(req) = parse_request(&req) {
+                    break req;
+                }
+                assert_ne!(n, 0, "connection closed before the request was complete");
+            };
+
+            let res = format!(
+                "HTTP/1.1 {status} STUB\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(res.as_bytes()).await.unwrap();
+            req
+        });
+        (url.parse().unwrap(), server)
+    }
+
+    /// `None` until the whole body was received
+    fn parse_request(req: &[u8]) -> Option<Request> {
+        let head_end = req.windows(4).position(|w| w == b"\r\n\r\n")?;
+        let head = String::from_utf8_lossy(&req[..head_end]).to_lowercase();
+        let len = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length: "))
+            .map_or(0, |len| len.trim().parse().unwrap());
+        let body = &req[head_end + 4..];
+        (body.len() >= len).then(|| Request {
+            head,
+            body: body.to_vec(),
+        })
+    }
+
+    fn header<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+        req.head
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+    }
+
+    #[tokio::test]
+    async fn test_rejected() {
+        let (url, server) = serve_once(403, "bad token").await;
+        let tx = VectorDbMetricTx::builder()
+            .with_ingest_url(url)
+            .build()
+            .unwrap();
+
+        let err = tx.push(round(EventSource::Yral, 1)).await.unwrap_err();
+
+        server.await.unwrap();
+        assert!(
+            matches!(
+                &err,
+                VectorDbError::Rejected { status, body }
+                    if *status == StatusCode::FORBIDDEN && body == "bad token"
+            ),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_auth_and_headers() {
+        let (url, server) = serve_once(200, "").await;
+        let tx = VectorDbMetricTx::builder()
+            .with_ingest_url(url)
+            .with_bearer_token("secret")
+            .with_header(
+                HeaderName::from_static("x-yral-env"),
+                HeaderValue::from_static("staging"),
+            )
+            .build()
+            .unwrap();
+        let ev = round(EventSource::Yral, 1);
+        let expected = serde_json::to_value(&ev).unwrap();
+
+        tx.push(ev).await.unwrap();
+
+        let req = server.await.unwrap();
+        assert!(
+            req.head.starts_with("post /ingest http/1.1\r\n"),
+            "{}",
+            req.head
+        );
+        assert_eq!(header(&req, "authorization"), Some("bearer secret"));
+        assert_eq!(header(&req, "x-yral-env"), Some("staging"));
+        assert_eq!(header(&req, "content-type"), Some("application/json"));
+        assert_eq!(header(&req, "content-encoding"), None);
+        let body: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+        assert_eq!(body, expected);
+    }
+
+    #[tokio::test]
+    async fn test_gzip() {
+        let (url, server) = serve_once(200, "").await;
+        let tx = VectorDbMetricTx::builder()
+            .with_ingest_url(url)
+            .with_gzip(true)
+            .build()
+            .unwrap();
+        let ev = round(EventSource::Yral, 1);
+        let expected = serde_json::to_value(&ev).unwrap();
+
+        tx.push(ev).await.unwrap();
+
+        let req = server.await.unwrap();
+        assert_eq!(header(&req, "content-encoding"), Some("gzip"));
+        let mut body = Vec::new();
+        GzDecoder::new(&req.body[..])
+            .read_to_end(&mut body)
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, expected);
+    }
+}
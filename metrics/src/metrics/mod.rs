@@ -1,11 +1,12 @@
 pub mod cents_withdrawal;
 pub mod like_video;
+pub mod serialized;
 pub mod tides_turned;
 pub mod video_duration_watched;
 pub mod video_watched;
 
 use sealed_metric::SealedMetric;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use web_time::{SystemTime, UNIX_EPOCH};
 
 pub mod sealed_metric {
@@ -25,7 +26,7 @@ pub trait Metric: SealedMetric {}
 
 impl<T: SealedMetric> Metric for T {}
 
-#[derive(Serialize, Clone, Copy, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum EventSource {
     PumpNDumpWorker,
     Yral,
@@ -45,7 +46,7 @@ impl EventSource {
     }
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Clone, Debug)]
 pub struct MetricEvent<M: Metric> {
     pub source: EventSource,
     pub tag: String,
@@ -75,7 +76,7 @@ impl<M: Metric> MetricEvent<M> {
     }
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Clone, Debug)]
 pub struct MetricEventList<M: Metric> {
     pub source: EventSource,
     pub tag: String,
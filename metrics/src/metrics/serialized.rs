@@ -0,0 +1,82 @@
+use candid::Principal;
+use serde::{Serialize, Serializer};
+
+use super::{sealed_metric::SealedMetric, Metric, MetricEvent, MetricEventList};
+
+/// A metric already serialized to JSON
+///
+/// lets senders hold events of different metric types together,
+/// serializes to the same JSON as the original metric
+#[derive(Clone, Debug, PartialEq)]
+pub struct SerializedMetric {
+    pub(crate) tag: String,
+    pub(crate) user_id: Option<String>,
+    pub(crate) user_canister: Option<Principal>,
+    pub(crate) payload: serde_json::Value,
+}
+
+impl SerializedMetric {
+    pub fn new<M: Metric>(metric: &M) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            tag: metric.tag(),
+            user_id: metric.user_id(),
+            user_canister: metric.user_canister(),
+            payload: serde_json::to_value(metric)?,
+        })
+    }
+
+    pub fn payload(&self) -> &serde_json::Value {
+        &self.payload
+    }
+}
+
+impl Serialize for SerializedMetric {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.payload.serialize(serializer)
+    }
+}
+
+impl SealedMetric for SerializedMetric {
+    fn tag(&self) -> String {
+        self.tag.clone()
+    }
+
+    fn user_id(&self) -> Option<String> {
+        self.user_id.clone()
+    }
+
+    fn user_canister(&self) -> Option<Principal> {
+        self.user_canister
+    }
+}
+
+impl<M: Metric> MetricEvent<M> {
+    /// Same event with its metric serialized, timestamps are kept
+    pub fn serialized(self) -> Result<MetricEvent<SerializedMetric>, serde_json::Error> {
+        Ok(MetricEvent {
+            metric: SerializedMetric::new(&self.metric)?,
+            source: self.source,
+            tag: self.tag,
+            user_id: self.user_id,
+            unix_timestamp_secs: self.unix_timestamp_secs,
+            page_location: self.page_location,
+            host: self.host,
+        })
+    }
+}
+
+impl<M: Metric> MetricEventList<M> {
+    pub fn serialized(self) -> Result<MetricEventList<SerializedMetric>, serde_json::Error> {
+        Ok(MetricEventList {
+            metric: self
+                .metric
+                .into_iter()
+                .map(MetricEvent::serialized)
+                .collect::<Result<_, _>>()?,
+            source: self.source,
+            tag: self.tag,
+            user_id: self.user_id,
+            unix_timestamp_secs: self.unix_timestamp_secs,
+        })
+    }
+}
@@ -0,0 +1,125 @@
+//! Round trips and latency of inserting 10k items into a bounded cache,
+//! comparing the old zadd/zcard/zremrangebyrank sequence with
+//! `BoundedSortedSet::add`
+//!
+//! requires `ML_FEED_CACHE_REDIS_URL`, run with `cargo bench -p ml-feed-cache`
+
+use std::time::{Duration, Instant, SystemTime};
+
+use ml_feed_cache::{
+    consts::{MAX_WATCH_HISTORY_CACHE_LEN, WATCH_HISTORY_CACHE},
+    types::{get_history_item_score, MLFeedCacheHistoryItem},
+    MLFeedCacheState,
+};
+use redis::{aio::ConnectionLike, AsyncCommands, Cmd, Pipeline, RedisFuture, Value};
+
+const ITEMS: u64 = 10_000;
+const RUNS: u32 = 10;
+const KEY: &str = "bench_add_items";
+
+/// Counts requests sent to redis
+struct CountingConnection<C> {
+    inner: C,
+    round_trips: u64,
+}
+
+impl<C: ConnectionLike> ConnectionLike for CountingConnection<C> {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        self.round_trips += 1;
+        self.inner.req_packed_command(cmd)
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        self.round_trips += 1;
+        self.inner.req_packed_commands(cmd, offset, count)
+    }
+
+    fn get_db(&self) -> i64 {
+        self.inner.get_db()
+    }
+}
+
+/// The add path used before `BoundedSortedSet`
+async fn add_sequential<C: ConnectionLike + Send + Sync>(
+    conn: &mut C,
+    items: &[(f64, MLFeedCacheHistoryItem)],
+) -> redis::RedisResult<()> {
+    for chunk in items.chunks(1000) {
+        conn.zadd_multiple::<_, _, _, ()>(KEY, chunk).await?;
+    }
+    let num_items: u64 = conn.zcard(KEY).await?;
+    if num_items > MAX_WATCH_HISTORY_CACHE_LEN {
+        conn.zremrangebyrank::<_, ()>(
+            KEY,
+            0,
+            (num_items - (MAX_WATCH_HISTORY_CACHE_LEN + 1)) as isize,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+fn report(name: &str, round_trips: u64, elapsed: Duration) {
+    println!(
+        "{name:<12} {:>4} round trips/insert {:>10.2?}/insert",
+        round_trips / RUNS as u64,
+        elapsed / RUNS
+    );
+}
+
+fn main() {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    rt.block_on(async {
+        let state = MLFeedCacheState::new().await;
+        let conn = state.redis_pool.get().await.unwrap();
+        let mut conn = CountingConnection {
+            inner: conn.clone(),
+            round_trips: 0,
+        };
+
+        let items = (0..ITEMS)
+            .map(|i| MLFeedCacheHistoryItem {
+                canister_id: "bench_canister_id".to_string(),
+                post_id: i,
+                video_id: format!("bench_video_id{i}"),
+                nsfw_probability: 0.0,
+                item_type: "video_viewed".to_string(),
+                timestamp: SystemTime::now(),
+                percent_watched: i as f32 / 100.0,
+            })
+            .collect::<Vec<_>>();
+        let scored = items
+            .iter()
+            .map(|item| (get_history_item_score(item), item.clone()))
+            .collect::<Vec<_>>();
+
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            conn.inner.del::<_, ()>(KEY).await.unwrap();
+            add_sequential(&mut conn, &scored).await.unwrap();
+        }
+        report("sequential", conn.round_trips, start.elapsed());
+
+        conn.round_trips = 0;
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            conn.inner.del::<_, ()>(KEY).await.unwrap();
+            WATCH_HISTORY_CACHE
+                .add(&mut conn, KEY, &items)
+                .await
+                .unwrap();
+        }
+        report("atomic", conn.round_trips, start.elapsed());
+
+        conn.inner.del::<_, ()>(KEY).await.unwrap();
+    });
+}
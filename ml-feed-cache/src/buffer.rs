@@ -0,0 +1,330 @@
+//! Claim / ack processing of buffer items
+//!
+//! [`MLFeedCacheState::claim_user_buffer_batch`] atomically moves items from the buffer
+//! to an in-flight set. Consumers then either ack processed items or requeue failed
+//! ones, items failing [`MAX_BUFFER_ITEM_ATTEMPTS`] times are moved to a dead-letter set.
+//! Claims never acked (e.g consumer crashed) are requeued by
+//! [`MLFeedCacheState::requeue_expired_user_buffer_claims`]. Unlike reading and removing
+//! by timestamp, no two consumers get the same item
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use redis::{AsyncCommands, RedisResult, Script};
+
+use crate::{
+    consts::{
+        BUFFER_ATTEMPTS_SUFFIX, BUFFER_DEAD_LETTER_SUFFIX, BUFFER_IN_FLIGHT_SUFFIX,
+        MAX_BUFFER_CLAIM_BATCH, MAX_BUFFER_ITEM_ATTEMPTS,
+    },
+    encoding::{decode, member_forms},
+    keys::{FeedKey, KeyFamily},
+    types::BufferItem,
+    FeedCacheError, MLFeedCacheState,
+};
+
+/// KEYS: buffer, in_flight
+/// ARGV: cutoff, limit, now
+const CLAIM_SCRIPT: &str = r"
+local items = redis.call('ZRANGEBYSCORE', KEYS[1], 0, ARGV[1], 'LIMIT', 0, ARGV[2])
+for _, item in ipairs(items) do
+    redis.call('ZREM', KEYS[1], item)
+    redis.call('ZADD', KEYS[2], ARGV[3], item)
+end
+return items
+";
+
+/// Move a failed item back to the buffer or to the dead-letter set
+///
+/// only items still in flight are touched, so an item acked
+/// concurrently is never requeued
+const FAIL_ITEM_FN: &str = r"
+local function fail_item(item, score, max_attempts, now)
+    if redis.call('ZREM', KEYS[2], item) == 0 then
+        return 0
+    end
+    local attempts = redis.call('HINCRBY', KEYS[3], item, 1)
+    if attempts >= max_attempts then
+        redis.call('HDEL', KEYS[3], item)
+        redis.call('ZADD', KEYS[4], now, item)
+        return 1
+    end
+    redis.call('ZADD', KEYS[1], score, item)
+    return 0
+end
+";
+
+/// KEYS: buffer, in_flight, attempts, dead_letter
+/// ARGV: max_attempts, now, (item, item in the other encoding, score)...
+const REQUEUE_SCRIPT: &str = r"
+local dead = 0
+for i = 3, #ARGV, 3 do
+    local item = ARGV[i]
+    if not redis.call('ZSCORE', KEYS[2], item) then
+        item = ARGV[i + 1]
+    end
+    dead = dead + fail_item(item, ARGV[i + 2], tonumber(ARGV[1]), ARGV[2])
+end
+return dead
+";
+
+/// KEYS: buffer, in_flight, attempts, dead_letter
+/// ARGV: max_attempts, now, claimed_before
+const REQUEUE_EXPIRED_SCRIPT: &str = r"
+local dead = 0
+local items = redis.call('ZRANGEBYSCORE', KEYS[2], 0, ARGV[3])
+for _, item in ipairs(items) do
+    -- requeue with the item's own timestamp, falling back to now
+    local score = ARGV[2]
+    if string.byte(item, 1) == 1 then
+        -- compact member, big endian seconds follow the tag
+        local secs = 0
+        for j = 2, 9 do
+            secs = secs * 256 + string.byte(item, j)
+        end
+        score = secs
+    else
+        local ok, decoded = pcall(cjson.decode, item)
+        if ok and type(decoded) == 'table' and type(decoded.timestamp) == 'table' then
+            score = decoded.timestamp.secs_since_epoch or score
+        end
+    end
+    dead = dead + fail_item(item, score, tonumber(ARGV[1]), ARGV[2])
+end
+return dead
+";
+
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub(crate) fn item_score(item: &BufferItem) -> u64 {
+    item.timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn decode_items(members: &[Vec<u8>]) -> RedisResult<Vec<BufferItem>> {
+    members.iter().map(|member| decode(member, &[])).collect()
+}
+
+/// Keys of the sets backing the buffer at `key`
+pub(crate) fn buffer_keys(key: &str) -> [String; 4] {
+    [
+        key.to_string(),
+        format!("{key}{BUFFER_IN_FLIGHT_SUFFIX}"),
+        format!("{key}{BUFFER_ATTEMPTS_SUFFIX}"),
+        format!("{key}{BUFFER_DEAD_LETTER_SUFFIX}"),
+    ]
+}
+
+impl MLFeedCacheState {
+    /// Claim up to `limit` (at most [`MAX_BUFFER_CLAIM_BATCH`]) items with
+    /// timestamps <= `cutoff_secs`, oldest first
+    ///
+    /// claimed items must be passed to [`Self::ack_user_buffer_items`] once processed
+    /// or [`Self::requeue_user_buffer_items`] on failure
+    pub async fn claim_user_buffer_batch(
+        &self,
+        cutoff_secs: u64,
+        limit: usize,
+    ) -> Result<Vec<BufferItem>, FeedCacheError> {
+        self.claim_user_buffer_batch_impl(&FeedKey::HotOrNotBuffer, cutoff_secs, limit)
+            .await
+    }
+
+    /// `key` must be a [`KeyFamily::HotOrNotBuffer`] key
+    pub async fn claim_user_buffer_batch_impl(
+        &self,
+        key: &FeedKey,
+        cutoff_secs: u64,
+        limit: usize,
+    ) -> Result<Vec<BufferItem>, FeedCacheError> {
+        let key = key.expect(&[KeyFamily::HotOrNotBuffer])?;
+        self.claim_buffer_batch_at(&key, cutoff_secs, limit).await
+    }
+
+    /// [`Self::claim_user_buffer_batch_impl`] on the redis key `key`
+    pub(crate) async fn claim_buffer_batch_at(
+        &self,
+        key: &str,
+        cutoff_secs: u64,
+        limit: usize,
+    ) -> Result<Vec<BufferItem>, FeedCacheError> {
+        let mut conn = self.conn().await?;
+        let [buffer, in_flight, ..] = buffer_keys(key);
+
+        let members: Vec<Vec<u8>> = Script::new(CLAIM_SCRIPT)
+            .key(buffer)
+            .key(in_flight)
+            .arg(cutoff_secs)
+            .arg(limit.min(MAX_BUFFER_CLAIM_BATCH))
+            .arg(now_secs())
+            .invoke_async(&mut *conn)
+            .await?;
+
+        Ok(decode_items(&members)?)
+    }
+
+    /// Mark claimed items as processed
+    pub async fn ack_user_buffer_items(&self, items: &[BufferItem]) -> Result<u64, FeedCacheError> {
+        self.ack_user_buffer_items_impl(&FeedKey::HotOrNotBuffer, items)
+            .await
+    }
+
+    /// `key` must be a [`KeyFamily::HotOrNotBuffer`] key
+    pub async fn ack_user_buffer_items_impl(
+        &self,
+        key: &FeedKey,
+        items: &[BufferItem],
+    ) -> Result<u64, FeedCacheError> {
+        let key = key.expect(&[KeyFamily::HotOrNotBuffer])?;
+        self.ack_buffer_items_at(&key, items).await
+    }
+
+    /// [`Self::ack_user_buffer_items_impl`] on the redis key `key`
+    pub(crate) async fn ack_buffer_items_at(
+        &self,
+        key: &str,
+        items: &[BufferItem],
+    ) -> Result<u64, FeedCacheError> {
+        if items.is_empty() {
+            return Ok(0);
+        }
+        let mut conn = self.conn().await?;
+        let [_, in_flight, attempts, _] = buffer_keys(key);
+        // items may have been claimed before their key was migrated
+        let members = items.iter().flat_map(member_forms).collect::<Vec<_>>();
+
+        let (acked,): (u64,) = redis::pipe()
+            .atomic()
+            .zrem(in_flight, &members)
+            .hdel(attempts, &members)
+            .ignore()
+            .query_async(&mut *conn)
+            .await?;
+
+        Ok(acked)
+    }
+
+    /// Return claimed items that failed processing to the buffer,
+    /// returns how many were moved to the dead-letter set instead
+    pub async fn requeue_user_buffer_items(
+        &self,
+        items: &[BufferItem],
+    ) -> Result<u64, FeedCacheError> {
+        self.requeue_user_buffer_items_impl(&FeedKey::HotOrNotBuffer, items)
+            .await
+    }
+
+    /// `key` must be a [`KeyFamily::HotOrNotBuffer`] key
+    pub async fn requeue_user_buffer_items_impl(
+        &self,
+        key: &FeedKey,
+        items: &[BufferItem],
+    ) -> Result<u64, FeedCacheError> {
+        let key = key.expect(&[KeyFamily::HotOrNotBuffer])?;
+        self.requeue_buffer_items_at(&key, items).await
+    }
+
+    /// [`Self::requeue_user_buffer_items_impl`] on the redis key `key`
+    pub(crate) async fn requeue_buffer_items_at(
+        &self,
+        key: &str,
+        items: &[BufferItem],
+    ) -> Result<u64, FeedCacheError> {
+        if items.is_empty() {
+            return Ok(0);
+        }
+        let mut conn = self.conn().await?;
+
+        let script = Script::new(&format!("{FAIL_ITEM_FN}{REQUEUE_SCRIPT}"));
+        let mut invocation = script.prepare_invoke();
+        for key in buffer_keys(key) {
+            invocation.key(key);
+        }
+        invocation.arg(MAX_BUFFER_ITEM_ATTEMPTS).arg(now_secs());
+        for item in items {
+            let [compact, json] = member_forms(item);
+            invocation.arg(compact).arg(json).arg(item_score(item));
+        }
+
+        let dead = invocation.invoke_async(&mut *conn).await?;
+        Ok(dead)
+    }
+
+    /// Requeue items claimed at or before `claimed_before_secs` and never acked,
+    /// each counts as a failed attempt. Returns how many were dead lettered
+    pub async fn requeue_expired_user_buffer_claims(
+        &self,
+        claimed_before_secs: u64,
+    ) -> Result<u64, FeedCacheError> {
+        self.requeue_expired_user_buffer_claims_impl(&FeedKey::HotOrNotBuffer, claimed_before_secs)
+            .await
+    }
+
+    /// `key` must be a [`KeyFamily::HotOrNotBuffer`] key
+    pub async fn requeue_expired_user_buffer_claims_impl(
+        &self,
+        key: &FeedKey,
+        claimed_before_secs: u64,
+    ) -> Result<u64, FeedCacheError> {
+        let key = key.expect(&[KeyFamily::HotOrNotBuffer])?;
+        self.requeue_expired_buffer_claims_at(&key, claimed_before_secs)
+            .await
+    }
+
+    /// [`Self::requeue_expired_user_buffer_claims_impl`] on the redis key `key`
+    pub(crate) async fn requeue_expired_buffer_claims_at(
+        &self,
+        key: &str,
+        claimed_before_secs: u64,
+    ) -> Result<u64, FeedCacheError> {
+        let mut conn = self.conn().await?;
+
+        let script = Script::new(&format!("{FAIL_ITEM_FN}{REQUEUE_EXPIRED_SCRIPT}"));
+        let mut invocation = script.prepare_invoke();
+        for key in buffer_keys(key) {
+            invocation.key(key);
+        }
+        invocation
+            .arg(MAX_BUFFER_ITEM_ATTEMPTS)
+            .arg(now_secs())
+            .arg(claimed_before_secs);
+
+        let dead = invocation.invoke_async(&mut *conn).await?;
+        Ok(dead)
+    }
+
+    /// Items that failed processing [`MAX_BUFFER_ITEM_ATTEMPTS`] times
+    pub async fn get_user_buffer_dead_letter_items(
+        &self,
+    ) -> Result<Vec<BufferItem>, FeedCacheError> {
+        self.get_user_buffer_dead_letter_items_impl(&FeedKey::HotOrNotBuffer)
+            .await
+    }
+
+    /// `key` must be a [`KeyFamily::HotOrNotBuffer`] key
+    pub async fn get_user_buffer_dead_letter_items_impl(
+        &self,
+        key: &FeedKey,
+    ) -> Result<Vec<BufferItem>, FeedCacheError> {
+        let key = key.expect(&[KeyFamily::HotOrNotBuffer])?;
+        self.get_buffer_dead_letter_items_at(&key).await
+    }
+
+    /// [`Self::get_user_buffer_dead_letter_items_impl`] on the redis key `key`
+    pub(crate) async fn get_buffer_dead_letter_items_at(
+        &self,
+        key: &str,
+    ) -> Result<Vec<BufferItem>, FeedCacheError> {
+        let mut conn = self.conn().await?;
+        let [.., dead_letter] = buffer_keys(key);
+
+        let members: Vec<Vec<u8>> = conn.zrange(dead_letter, 0, -1).await?;
+        Ok(decode_items(&members)?)
+    }
+}
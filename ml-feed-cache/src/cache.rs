@@ -0,0 +1,257 @@
+use std::future::Future;
+
+use crate::{
+    keys::FeedKey,
+    types::{
+        BufferItem, FeedParams, FeedResponse, MLFeedCacheHistoryItem, PlainPostItem, PostItem,
+    },
+    FeedCacheError, MLFeedCacheState,
+};
+
+/// Feed cache operations, implemented by [`MLFeedCacheState`] (redis)
+/// and [`crate::memory::InMemoryFeedCache`] (for tests)
+///
+/// see the [`MLFeedCacheState`] methods of the same name for details,
+/// the buffer methods always use the `user_hotornot_buffer` key
+pub trait FeedCache: Send + Sync {
+    fn add_user_watch_history_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<MLFeedCacheHistoryItem>,
+    ) -> impl Future<Output = Result<(), FeedCacheError>> + Send;
+
+    fn add_user_success_history_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<MLFeedCacheHistoryItem>,
+    ) -> impl Future<Output = Result<(), FeedCacheError>> + Send;
+
+    fn get_history_items(
+        &self,
+        key: &FeedKey,
+        start: u64,
+        end: u64,
+    ) -> impl Future<Output = Result<Vec<MLFeedCacheHistoryItem>, FeedCacheError>> + Send;
+
+    fn get_history_items_len(
+        &self,
+        key: &FeedKey,
+    ) -> impl Future<Output = Result<u64, FeedCacheError>> + Send;
+
+    fn add_user_history_plain_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<MLFeedCacheHistoryItem>,
+    ) -> impl Future<Output = Result<(), FeedCacheError>> + Send;
+
+    fn is_user_history_plain_item_exists(
+        &self,
+        key: &FeedKey,
+        item: PlainPostItem,
+    ) -> impl Future<Output = Result<bool, FeedCacheError>> + Send;
+
+    fn add_user_cache_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<PostItem>,
+    ) -> impl Future<Output = Result<(), FeedCacheError>> + Send;
+
+    fn add_global_cache_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<PostItem>,
+    ) -> impl Future<Output = Result<(), FeedCacheError>> + Send;
+
+    fn get_cache_items(
+        &self,
+        key: &FeedKey,
+        start: u64,
+        end: u64,
+    ) -> impl Future<Output = Result<Vec<PostItem>, FeedCacheError>> + Send;
+
+    fn get_cache_items_len(
+        &self,
+        key: &FeedKey,
+    ) -> impl Future<Output = Result<u64, FeedCacheError>> + Send;
+
+    fn next_feed(
+        &self,
+        user: &str,
+        params: &FeedParams,
+    ) -> impl Future<Output = Result<FeedResponse, FeedCacheError>> + Send;
+
+    fn add_user_buffer_items(
+        &self,
+        items: Vec<BufferItem>,
+    ) -> impl Future<Output = Result<(), FeedCacheError>> + Send;
+
+    #[deprecated(note = "not safe with several consumers, use `claim_user_buffer_batch` instead")]
+    fn get_user_buffer_items_by_timestamp(
+        &self,
+        timestamp_secs: u64,
+    ) -> impl Future<Output = Result<Vec<BufferItem>, FeedCacheError>> + Send;
+
+    #[deprecated(note = "not safe with several consumers, use `ack_user_buffer_items` instead")]
+    fn remove_user_buffer_items_by_timestamp(
+        &self,
+        timestamp_secs: u64,
+    ) -> impl Future<Output = Result<u64, FeedCacheError>> + Send;
+
+    fn claim_user_buffer_batch(
+        &self,
+        cutoff_secs: u64,
+        limit: usize,
+    ) -> impl Future<Output = Result<Vec<BufferItem>, FeedCacheError>> + Send;
+
+    fn ack_user_buffer_items(
+        &self,
+        items: &[BufferItem],
+    ) -> impl Future<Output = Result<u64, FeedCacheError>> + Send;
+
+    fn requeue_user_buffer_items(
+        &self,
+        items: &[BufferItem],
+    ) -> impl Future<Output = Result<u64, FeedCacheError>> + Send;
+
+    fn requeue_expired_user_buffer_claims(
+        &self,
+        claimed_before_secs: u64,
+    ) -> impl Future<Output = Result<u64, FeedCacheError>> + Send;
+
+    fn get_user_buffer_dead_letter_items(
+        &self,
+    ) -> impl Future<Output = Result<Vec<BufferItem>, FeedCacheError>> + Send;
+}
+
+// inherent methods take precedence, so `MLFeedCacheState::method` below
+// resolves to the redis implementation and not back to the trait
+impl FeedCache for MLFeedCacheState {
+    async fn add_user_watch_history_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<MLFeedCacheHistoryItem>,
+    ) -> Result<(), FeedCacheError> {
+        MLFeedCacheState::add_user_watch_history_items(self, key, items).await
+    }
+
+    async fn add_user_success_history_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<MLFeedCacheHistoryItem>,
+    ) -> Result<(), FeedCacheError> {
+        MLFeedCacheState::add_user_success_history_items(self, key, items).await
+    }
+
+    async fn get_history_items(
+        &self,
+        key: &FeedKey,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<MLFeedCacheHistoryItem>, FeedCacheError> {
+        MLFeedCacheState::get_history_items(self, key, start, end).await
+    }
+
+    async fn get_history_items_len(&self, key: &FeedKey) -> Result<u64, FeedCacheError> {
+        MLFeedCacheState::get_history_items_len(self, key).await
+    }
+
+    async fn add_user_history_plain_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<MLFeedCacheHistoryItem>,
+    ) -> Result<(), FeedCacheError> {
+        MLFeedCacheState::add_user_history_plain_items(self, key, items).await
+    }
+
+    async fn is_user_history_plain_item_exists(
+        &self,
+        key: &FeedKey,
+        item: PlainPostItem,
+    ) -> Result<bool, FeedCacheError> {
+        MLFeedCacheState::is_user_history_plain_item_exists(self, key, item).await
+    }
+
+    async fn add_user_cache_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<PostItem>,
+    ) -> Result<(), FeedCacheError> {
+        MLFeedCacheState::add_user_cache_items(self, key, items).await
+    }
+
+    async fn add_global_cache_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<PostItem>,
+    ) -> Result<(), FeedCacheError> {
+        MLFeedCacheState::add_global_cache_items(self, key, items).await
+    }
+
+    async fn get_cache_items(
+        &self,
+        key: &FeedKey,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<PostItem>, FeedCacheError> {
+        MLFeedCacheState::get_cache_items(self, key, start, end).await
+    }
+
+    async fn get_cache_items_len(&self, key: &FeedKey) -> Result<u64, FeedCacheError> {
+        MLFeedCacheState::get_cache_items_len(self, key).await
+    }
+
+    async fn next_feed(
+        &self,
+        user: &str,
+        params: &FeedParams,
+    ) -> Result<FeedResponse, FeedCacheError> {
+        MLFeedCacheState::next_feed(self, user, params).await
+    }
+
+    async fn add_user_buffer_items(&self, items: Vec<BufferItem>) -> Result<(), FeedCacheError> {
+        MLFeedCacheState::add_user_buffer_items(self, items).await
+    }
+
+    #[allow(deprecated)]
+    async fn get_user_buffer_items_by_timestamp(
+        &self,
+        timestamp_secs: u64,
+    ) -> Result<Vec<BufferItem>, FeedCacheError> {
+        MLFeedCacheState::get_user_buffer_items_by_timestamp(self, timestamp_secs).await
+    }
+
+    #[allow(deprecated)]
+    async fn remove_user_buffer_items_by_timestamp(
+        &self,
+        timestamp_secs: u64,
+    ) -> Result<u64, FeedCacheError> {
+        MLFeedCacheState::remove_user_buffer_items_by_timestamp(self, timestamp_secs).await
+    }
+
+    async fn claim_user_buffer_batch(
+        &self,
+        cutoff_secs: u64,
+        limit: usize,
+    ) -> Result<Vec<BufferItem>, FeedCacheError> {
+        MLFeedCacheState::claim_user_buffer_batch(self, cutoff_secs, limit).await
+    }
+
+    async fn ack_user_buffer_items(&self, items: &[BufferItem]) -> Result<u64, FeedCacheError> {
+        MLFeedCacheState::ack_user_buffer_items(self, items).await
+    }
+
+    async fn requeue_user_buffer_items(&self, items: &[BufferItem]) -> Result<u64, FeedCacheError> {
+        MLFeedCacheState::requeue_user_buffer_items(self, items).await
+    }
+
+    async fn requeue_expired_user_buffer_claims(
+        &self,
+        claimed_before_secs: u64,
+    ) -> Result<u64, FeedCacheError> {
+        MLFeedCacheState::requeue_expired_user_buffer_claims(self, claimed_before_secs).await
+    }
+
+    async fn get_user_buffer_dead_letter_items(&self) -> Result<Vec<BufferItem>, FeedCacheError> {
+        MLFeedCacheState::get_user_buffer_dead_letter_items(self).await
+    }
+}
@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{keys::KeyFamily, FeedCacheError};
+
+pub const REDIS_URL_ENV: &str = "ML_FEED_CACHE_REDIS_URL";
+
+/// Connection settings for [`crate::MLFeedCacheState`]
+#[derive(Clone, Debug)]
+pub struct FeedCacheConfig {
+    pub redis_url: String,
+    pub pool_size: u32,
+    /// Max wait for a pooled connection
+    pub connection_timeout: Duration,
+    /// Close pooled connections idle for this long
+    pub idle_timeout: Option<Duration>,
+    pub ttls: KeyTtls,
+    pub member_encoding: MemberEncoding,
+}
+
+impl FeedCacheConfig {
+    pub fn new(redis_url: impl Into<String>) -> Self {
+        Self {
+            redis_url: redis_url.into(),
+            pool_size: 10,
+            connection_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(10 * 60)),
+            ttls: KeyTtls::default(),
+            member_encoding: MemberEncoding::default(),
+        }
+    }
+
+    /// Read the redis url from `ML_FEED_CACHE_REDIS_URL`
+    pub fn from_env() -> Result<Self, FeedCacheError> {
+        let redis_url = std::env::var(REDIS_URL_ENV)
+            .map_err(|_| FeedCacheError::Config(format!("{REDIS_URL_ENV} must be set")))?;
+        Ok(Self::new(redis_url))
+    }
+
+    pub fn with_pool_size(mut self, pool_size: u32) -> Self {
+        self.pool_size = pool_size;
+        self
+    }
+
+    pub fn with_connection_timeout(mut self, connection_timeout: Duration) -> Self {
+        self.connection_timeout = connection_timeout;
+        self
+    }
+
+    pub fn with_idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    pub fn with_ttls(mut self, ttls: KeyTtls) -> Self {
+        self.ttls = ttls;
+        self
+    }
+
+    pub fn with_member_encoding(mut self, member_encoding: MemberEncoding) -> Self {
+        self.member_encoding = member_encoding;
+        self
+    }
+}
+
+/// How items are stored as sorted set members
+///
+/// Reads in [`MemberEncoding::Compact`] mode also decode JSON members, switch to it
+/// first and then rewrite existing keys with
+/// [`crate::MLFeedCacheState::migrate_member_encoding`]
+#[derive(Serialize, Deserialize, Clone, Copy, ToSchema, Debug, Default, PartialEq, Eq)]
+pub enum MemberEncoding {
+    /// JSON of the whole item
+    #[default]
+    Json,
+    /// Binary identity (principal bytes, post id, ...) as the member, the remaining
+    /// fields in a `{key}_meta` hash. Posts are compared by canister and post id only,
+    /// like [`crate::types::PostItem`]'s `PartialEq`
+    Compact,
+}
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Expiry of each key family, refreshed on every write
+///
+/// `None` never expires. Nothing expires by default, deployments opt in
+/// with [`KeyTtls::recommended`] or their own values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyTtls {
+    pub user_cache: Option<Duration>,
+    pub global_cache: Option<Duration>,
+    pub watch_history: Option<Duration>,
+    pub success_history: Option<Duration>,
+    pub plain_history: Option<Duration>,
+}
+
+impl Default for KeyTtls {
+    fn default() -> Self {
+        Self::never()
+    }
+}
+
+impl KeyTtls {
+    /// No key ever expires
+    pub fn never() -> Self {
+        Self {
+            user_cache: None,
+            global_cache: None,
+            watch_history: None,
+            success_history: None,
+            plain_history: None,
+        }
+    }
+
+    /// A week for user caches, 90 days for histories, the global cache never expires
+    pub fn recommended() -> Self {
+        Self {
+            user_cache: Some(DAY * 7),
+            global_cache: None,
+            watch_history: Some(DAY * 90),
+            success_history: Some(DAY * 90),
+            plain_history: Some(DAY * 90),
+        }
+    }
+
+    pub fn get(&self, family: KeyFamily) -> Option<Duration> {
+        match family {
+            KeyFamily::UserCache => self.user_cache,
+            KeyFamily::GlobalCache => self.global_cache,
+            KeyFamily::WatchHistory => self.watch_history,
+            KeyFamily::SuccessHistory => self.success_history,
+            KeyFamily::PlainHistory => self.plain_history,
+            // items are removed once processed
+            KeyFamily::HotOrNotBuffer => None,
+        }
+    }
+}
@@ -1,3 +1,8 @@
+use crate::{
+    sorted_set::{now_score, BoundedSortedSet},
+    types::{get_history_item_score, MLFeedCacheHistoryItem, PlainPostItem, PostItem},
+};
+
 pub const MAX_WATCH_HISTORY_CACHE_LEN: u64 = 10000;
 pub const MAX_SUCCESS_HISTORY_CACHE_LEN: u64 = 10000;
 pub const MAX_GLOBAL_CACHE_LEN: u64 = 3000;
@@ -17,7 +22,31 @@ pub const USER_WATCH_HISTORY_PLAIN_POST_ITEM_SUFFIX: &str = "_watch_plain_post_i
 pub const USER_LIKE_HISTORY_PLAIN_POST_ITEM_SUFFIX: &str = "_like_plain_post_item";
 
 pub const USER_HOTORNOT_BUFFER_KEY: &str = "user_hotornot_buffer";
+pub const BUFFER_IN_FLIGHT_SUFFIX: &str = "_in_flight";
+pub const BUFFER_ATTEMPTS_SUFFIX: &str = "_attempts";
+pub const BUFFER_DEAD_LETTER_SUFFIX: &str = "_dead_letter";
+pub const MAX_BUFFER_CLAIM_BATCH: usize = 1000;
+/// Failed processing attempts before a buffer item is dead lettered
+pub const MAX_BUFFER_ITEM_ATTEMPTS: u32 = 5;
+
+/// Sorted set of user ids scored by their last write, see
+/// [`crate::MLFeedCacheState::evict_inactive_users`]
+pub const USER_LAST_ACTIVE_KEY: &str = "user_last_active";
+
+/// Hash of member metadata next to sorted sets using [`crate::MemberEncoding::Compact`]
+pub const MEMBER_META_SUFFIX: &str = "_meta";
 
 pub const USER_CACHE_CLEAN_SUFFIX: &str = "_cache_clean";
 pub const USER_CACHE_NSFW_SUFFIX: &str = "_cache_nsfw";
 pub const USER_CACHE_MIXED_SUFFIX: &str = "_cache_mixed";
+
+pub const WATCH_HISTORY_CACHE: BoundedSortedSet<MLFeedCacheHistoryItem> =
+    BoundedSortedSet::new(MAX_WATCH_HISTORY_CACHE_LEN, get_history_item_score);
+pub const SUCCESS_HISTORY_CACHE: BoundedSortedSet<MLFeedCacheHistoryItem> =
+    BoundedSortedSet::new(MAX_SUCCESS_HISTORY_CACHE_LEN, get_history_item_score);
+pub const HISTORY_PLAIN_POST_ITEM_CACHE: BoundedSortedSet<PlainPostItem> =
+    BoundedSortedSet::new(MAX_HISTORY_PLAIN_POST_ITEM_CACHE_LEN, now_score);
+pub const USER_CACHE: BoundedSortedSet<PostItem> =
+    BoundedSortedSet::new(MAX_USER_CACHE_LEN, now_score);
+pub const GLOBAL_CACHE: BoundedSortedSet<PostItem> =
+    BoundedSortedSet::new(MAX_GLOBAL_CACHE_LEN, now_score);
@@ -0,0 +1,375 @@
+//! Binary member format of [`MemberEncoding::Compact`]
+//!
+//! Compact members start with [`COMPACT_TAG`], which never starts a JSON member,
+//! so both formats can be told apart and decoded while keys are migrated
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use candid::Principal;
+use redis::{ErrorKind, FromRedisValue, RedisError, RedisResult, ToRedisArgs, Value};
+
+use crate::{
+    consts::MEMBER_META_SUFFIX,
+    types::{BufferItem, MLFeedCacheHistoryItem, PlainPostItem, PostItem},
+    MemberEncoding,
+};
+
+pub(crate) const COMPACT_TAG: u8 = 0x01;
+
+const CANISTER_PRINCIPAL: u8 = 0;
+const CANISTER_TEXT: u8 = 1;
+
+fn invalid(detail: &str) -> RedisError {
+    RedisError::from((
+        ErrorKind::TypeError,
+        "invalid compact member",
+        detail.to_string(),
+    ))
+}
+
+/// Hash holding the metadata of compact members of `key`
+pub(crate) fn meta_key(key: &str) -> String {
+    format!("{key}{MEMBER_META_SUFFIX}")
+}
+
+#[derive(Default)]
+pub(crate) struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn f32(&mut self, v: f32) {
+        self.u32(v.to_bits());
+    }
+
+    /// LEB128 length followed by the bytes
+    fn bytes(&mut self, v: &[u8]) {
+        let mut len = v.len();
+        loop {
+            let byte = (len & 0x7f) as u8;
+            len >>= 7;
+            if len == 0 {
+                self.u8(byte);
+                break;
+            }
+            self.u8(byte | 0x80);
+        }
+        self.0.extend_from_slice(v);
+    }
+
+    fn str(&mut self, v: &str) {
+        self.bytes(v.as_bytes());
+    }
+
+    /// Principal bytes for canister ids, text for anything else
+    fn canister(&mut self, id: &str) {
+        match Principal::from_text(id) {
+            // only if the id round trips exactly
+            Ok(principal) if principal.to_text() == id => {
+                self.u8(CANISTER_PRINCIPAL);
+                self.bytes(principal.as_slice());
+            }
+            _ => {
+                self.u8(CANISTER_TEXT);
+                self.str(id);
+            }
+        }
+    }
+
+    fn time(&mut self, time: SystemTime) {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.u64(since_epoch.as_secs());
+        self.u32(since_epoch.subsec_nanos());
+    }
+}
+
+pub(crate) struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> RedisResult<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(invalid("unexpected end"));
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> RedisResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> RedisResult<u32> {
+        let bytes = self.take(4)?.try_into().expect("took 4 bytes");
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> RedisResult<u64> {
+        let bytes = self.take(8)?.try_into().expect("took 8 bytes");
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    fn f32(&mut self) -> RedisResult<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn bytes(&mut self) -> RedisResult<&'a [u8]> {
+        let mut len = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.u8()?;
+            len |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return self.take(len);
+            }
+        }
+        Err(invalid("length overflow"))
+    }
+
+    fn str(&mut self) -> RedisResult<String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| invalid("invalid utf-8"))
+    }
+
+    fn canister(&mut self) -> RedisResult<String> {
+        match self.u8()? {
+            CANISTER_PRINCIPAL => Principal::try_from_slice(self.bytes()?)
+                .map(|principal| principal.to_text())
+                .map_err(|_| invalid("invalid principal")),
+            CANISTER_TEXT => self.str(),
+            _ => Err(invalid("unknown canister id kind")),
+        }
+    }
+
+    fn time(&mut self) -> RedisResult<SystemTime> {
+        let secs = self.u64()?;
+        let nanos = self.u32()?;
+        if nanos >= 1_000_000_000 {
+            return Err(invalid("invalid timestamp"));
+        }
+        UNIX_EPOCH
+            .checked_add(Duration::new(secs, nanos))
+            .ok_or_else(|| invalid("invalid timestamp"))
+    }
+}
+
+/// Item stored as an identifying member plus optional metadata
+pub(crate) trait CompactMember: Sized {
+    fn write_member(&self, w: &mut Writer);
+
+    fn write_meta(&self, _w: &mut Writer) {}
+
+    fn read(member: &mut Reader, meta: &mut Reader) -> RedisResult<Self>;
+}
+
+impl CompactMember for PlainPostItem {
+    fn write_member(&self, w: &mut Writer) {
+        w.canister(&self.canister_id);
+        w.u64(self.post_id);
+    }
+
+    fn read(member: &mut Reader, _meta: &mut Reader) -> RedisResult<Self> {
+        Ok(Self {
+            canister_id: member.canister()?,
+            post_id: member.u64()?,
+        })
+    }
+}
+
+/// Same member as the post's [`PlainPostItem`]
+impl CompactMember for PostItem {
+    fn write_member(&self, w: &mut Writer) {
+        w.canister(&self.canister_id);
+        w.u64(self.post_id);
+    }
+
+    fn write_meta(&self, w: &mut Writer) {
+        w.str(&self.video_id);
+        w.f32(self.nsfw_probability);
+    }
+
+    fn read(member: &mut Reader, meta: &mut Reader) -> RedisResult<Self> {
+        Ok(Self {
+            canister_id: member.canister()?,
+            post_id: member.u64()?,
+            video_id: meta.str()?,
+            nsfw_probability: meta.f32()?,
+        })
+    }
+}
+
+/// Every event is its own member, like with JSON
+impl CompactMember for MLFeedCacheHistoryItem {
+    fn write_member(&self, w: &mut Writer) {
+        w.canister(&self.canister_id);
+        w.u64(self.post_id);
+        w.str(&self.item_type);
+        w.time(self.timestamp);
+    }
+
+    fn write_meta(&self, w: &mut Writer) {
+        w.str(&self.video_id);
+        w.f32(self.nsfw_probability);
+        w.f32(self.percent_watched);
+    }
+
+    fn read(member: &mut Reader, meta: &mut Reader) -> RedisResult<Self> {
+        Ok(Self {
+            canister_id: member.canister()?,
+            post_id: member.u64()?,
+            item_type: member.str()?,
+            timestamp: member.time()?,
+            video_id: meta.str()?,
+            nsfw_probability: meta.f32()?,
+            percent_watched: meta.f32()?,
+        })
+    }
+}
+
+/// Buffer items carry no metadata, the buffer scripts move whole members around.
+/// The timestamp comes first so scripts can read its seconds at bytes 2..=9
+impl CompactMember for BufferItem {
+    fn write_member(&self, w: &mut Writer) {
+        w.time(self.timestamp);
+        w.canister(&self.user_canister_id);
+        w.canister(&self.publisher_canister_id);
+        w.u64(self.post_id);
+        w.str(&self.video_id);
+        w.str(&self.item_type);
+        w.f32(self.percent_watched);
+    }
+
+    fn read(member: &mut Reader, _meta: &mut Reader) -> RedisResult<Self> {
+        Ok(Self {
+            timestamp: member.time()?,
+            user_canister_id: member.canister()?,
+            publisher_canister_id: member.canister()?,
+            post_id: member.u64()?,
+            video_id: member.str()?,
+            item_type: member.str()?,
+            percent_watched: member.f32()?,
+        })
+    }
+}
+
+pub(crate) fn is_compact(member: &[u8]) -> bool {
+    member.first() == Some(&COMPACT_TAG)
+}
+
+/// `(member, metadata)` of `item`, metadata is empty for JSON and for
+/// items without any
+pub(crate) fn encode<T: CompactMember + ToRedisArgs>(
+    encoding: MemberEncoding,
+    item: &T,
+) -> (Vec<u8>, Vec<u8>) {
+    match encoding {
+        MemberEncoding::Json => (item.to_redis_args().concat(), Vec::new()),
+        MemberEncoding::Compact => {
+            let mut member = Writer::default();
+            member.u8(COMPACT_TAG);
+            item.write_member(&mut member);
+            let mut meta = Writer::default();
+            item.write_meta(&mut meta);
+            (member.0, meta.0)
+        }
+    }
+}
+
+pub(crate) fn encode_member<T: CompactMember + ToRedisArgs>(
+    encoding: MemberEncoding,
+    item: &T,
+) -> Vec<u8> {
+    encode(encoding, item).0
+}
+
+/// Both encodings of `item`'s member, for lookups in keys that may not be migrated yet
+pub(crate) fn member_forms<T: CompactMember + ToRedisArgs>(item: &T) -> [Vec<u8>; 2] {
+    [
+        encode_member(MemberEncoding::Compact, item),
+        encode_member(MemberEncoding::Json, item),
+    ]
+}
+
+/// Decode a member of either encoding, `meta` is ignored for JSON members
+pub(crate) fn decode<T: CompactMember + FromRedisValue>(
+    member: &[u8],
+    meta: &[u8],
+) -> RedisResult<T> {
+    let Some(compact) = member.strip_prefix(&[COMPACT_TAG]) else {
+        return T::from_redis_value(&Value::BulkString(member.to_vec()));
+    };
+
+    let mut member = Reader(compact);
+    let item = T::read(&mut member, &mut Reader(meta))?;
+    if !member.0.is_empty() {
+        return Err(invalid("trailing bytes"));
+    }
+    Ok(item)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_round_trip() {
+        let post = PostItem {
+            canister_id: "ryjl3-tyaaa-aaaaa-aaaba-cai".to_string(),
+            post_id: 42,
+            video_id: "test_video_id".to_string(),
+            nsfw_probability: 0.1,
+        };
+        let (member, meta) = encode(MemberEncoding::Compact, &post);
+        assert!(is_compact(&member));
+        // principal bytes are much shorter than the json member
+        assert!(member.len() * 3 < encode_member(MemberEncoding::Json, &post).len());
+        let decoded: PostItem = decode(&member, &meta).unwrap();
+        assert_eq!(decoded.video_id, post.video_id);
+        assert_eq!(decoded.nsfw_probability, post.nsfw_probability);
+        assert_eq!(decoded, post);
+
+        // posts and plain posts share members, whatever the metadata
+        let plain = PlainPostItem::from(&post);
+        let other = PostItem {
+            nsfw_probability: 0.9,
+            ..post.clone()
+        };
+        assert_eq!(member, encode_member(MemberEncoding::Compact, &plain));
+        assert_eq!(member, encode_member(MemberEncoding::Compact, &other));
+
+        let item = BufferItem {
+            publisher_canister_id: "not a principal".to_string(),
+            user_canister_id: "ryjl3-tyaaa-aaaaa-aaaba-cai".to_string(),
+            post_id: 7,
+            video_id: "test_video_id".to_string(),
+            item_type: "video_viewed".to_string(),
+            percent_watched: 50.0,
+            timestamp: SystemTime::now(),
+        };
+        let (member, meta) = encode(MemberEncoding::Compact, &item);
+        assert!(meta.is_empty());
+        let decoded: BufferItem = decode(&member, &meta).unwrap();
+        assert_eq!(decoded.publisher_canister_id, item.publisher_canister_id);
+        assert_eq!(decoded.timestamp, item.timestamp);
+    }
+
+    #[test]
+    fn test_decode_json_member() {
+        let plain = PlainPostItem {
+            canister_id: "test_canister_id".to_string(),
+            post_id: 1,
+        };
+        let member = encode_member(MemberEncoding::Json, &plain);
+        assert!(!is_compact(&member));
+        let decoded: PlainPostItem = decode(&member, &[]).unwrap();
+        assert_eq!(decoded, plain);
+    }
+}
@@ -0,0 +1,30 @@
+use redis::{ErrorKind, RedisError};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FeedCacheError {
+    /// No connection became available before the pool's connection timeout
+    #[error("redis pool error: {0}")]
+    Pool(#[from] bb8::RunError<RedisError>),
+    #[error("redis connection error: {0}")]
+    Connection(RedisError),
+    #[error("failed to decode redis value: {0}")]
+    Decode(RedisError),
+    #[error("invalid config: {0}")]
+    Config(String),
+    /// A key kept being written to while it was being rewritten
+    #[error("key {0} changed during migration, retry later")]
+    MigrationConflict(String),
+    /// A key passed to an operation on another [`crate::keys::KeyFamily`]
+    #[error("key {0} is not accepted by this operation")]
+    UnexpectedKey(crate::keys::FeedKey),
+}
+
+impl From<RedisError> for FeedCacheError {
+    fn from(e: RedisError) -> Self {
+        match e.kind() {
+            ErrorKind::TypeError => Self::Decode(e),
+            _ => Self::Connection(e),
+        }
+    }
+}
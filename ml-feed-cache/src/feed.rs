@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+
+use crate::{
+    consts::USER_CACHE,
+    encoding::{encode_member, member_forms},
+    keys::FeedKey,
+    sorted_set::decode_compact_range,
+    types::{CacheVariant, FeedParams, FeedResponse, PlainPostItem, PostItem},
+    FeedCacheError, MLFeedCacheState, MemberEncoding,
+};
+
+/// Smallest number of candidates fetched from each cache per round
+pub(crate) const MIN_CANDIDATE_PAGE: u64 = 50;
+
+/// Plain history keys of posts `user` already watched or liked
+pub(crate) fn history_keys(user: &str) -> [String; 2] {
+    [
+        FeedKey::PlainWatch {
+            user: user.to_string(),
+        }
+        .to_string(),
+        FeedKey::PlainLike {
+            user: user.to_string(),
+        }
+        .to_string(),
+    ]
+}
+
+/// The user's cache, then the global cache of `variant`
+pub(crate) fn sources(user: &str, variant: CacheVariant) -> [Source; 2] {
+    [
+        Source::new(
+            FeedKey::UserCache {
+                user: user.to_string(),
+                variant,
+            }
+            .to_string(),
+        ),
+        Source::new(FeedKey::GlobalCache { variant }.to_string()),
+    ]
+}
+
+/// Pages through one cache, newest first
+pub(crate) struct Source {
+    pub(crate) key: String,
+    pub(crate) offset: u64,
+    pub(crate) exhausted: bool,
+}
+
+impl Source {
+    pub(crate) fn new(key: String) -> Self {
+        Self {
+            key,
+            offset: 0,
+            exhausted: false,
+        }
+    }
+}
+
+impl MLFeedCacheState {
+    /// Up to `params.num_results` posts for `user`, taken from the user's cache
+    /// first and topped up from the global cache
+    ///
+    /// posts in `params.filter_results` or in the user's watched / liked
+    /// history are skipped
+    pub async fn next_feed(
+        &self,
+        user: &str,
+        params: &FeedParams,
+    ) -> Result<FeedResponse, FeedCacheError> {
+        let mut conn = self.conn().await?;
+
+        let num_results = params.num_results as usize;
+        let page = (params.num_results as u64 * 2).max(MIN_CANDIDATE_PAGE);
+        let [watched_key, liked_key] = history_keys(user);
+
+        let mut sources = sources(user, params.variant);
+        let mut seen = params
+            .filter_results
+            .iter()
+            .cloned()
+            .collect::<HashSet<_>>();
+        let mut posts = Vec::with_capacity(num_results);
+
+        while posts.len() < num_results && sources.iter().any(|s| !s.exhausted) {
+            // fetch the next page of every source in one round trip
+            let mut pipe = redis::pipe();
+            let fetching = sources
+                .iter_mut()
+                .filter(|s| !s.exhausted)
+                .collect::<Vec<_>>();
+            for source in &fetching {
+                let (start, end) = (source.offset, source.offset + page - 1);
+                match self.member_encoding {
+                    MemberEncoding::Json => {
+                        pipe.zrevrange(&source.key, start as isize, end as isize);
+                    }
+                    MemberEncoding::Compact => {
+                        USER_CACHE.pipe_range_rev_compact(&mut pipe, &source.key, start, end);
+                    }
+                }
+            }
+            let pages: Vec<Vec<PostItem>> = match self.member_encoding {
+                MemberEncoding::Json => pipe.query_async(&mut *conn).await?,
+                MemberEncoding::Compact => {
+                    let replies: Vec<Vec<Vec<u8>>> = pipe.query_async(&mut *conn).await?;
+                    replies
+                        .iter()
+                        .map(|reply| decode_compact_range(reply))
+                        .collect::<Result<_, _>>()?
+                }
+            };
+
+            let mut candidates = Vec::new();
+            for (source, items) in fetching.into_iter().zip(pages) {
+                source.exhausted = (items.len() as u64) < page;
+                source.offset += page;
+                candidates.extend(items.into_iter().filter(|item| seen.insert(item.clone())));
+            }
+            if candidates.is_empty() {
+                continue;
+            }
+
+            // check all candidates against the history sets in one round trip
+            // in compact mode unmigrated history keys may still hold JSON members,
+            // so both forms of every candidate are looked up
+            let forms = candidates
+                .iter()
+                .map(|item| {
+                    let plain = PlainPostItem::from(item);
+                    match self.member_encoding {
+                        MemberEncoding::Json => {
+                            vec![encode_member(MemberEncoding::Json, &plain)]
+                        }
+                        MemberEncoding::Compact => member_forms(&plain).into(),
+                    }
+                })
+                .collect::<Vec<_>>();
+            let members = forms.concat();
+            let (watched, liked): (Vec<Option<f64>>, Vec<Option<f64>>) = redis::pipe()
+                .cmd("ZMSCORE")
+                .arg(&watched_key)
+                .arg(&members)
+                .cmd("ZMSCORE")
+                .arg(&liked_key)
+                .arg(&members)
+                .query_async(&mut *conn)
+                .await?;
+            let per_item = members.len() / candidates.len();
+            let in_history = |scores: Vec<Option<f64>>| {
+                scores
+                    .chunks(per_item)
+                    .map(|forms| forms.iter().any(Option::is_some))
+                    .collect::<Vec<_>>()
+            };
+            let (watched, liked) = (in_history(watched), in_history(liked));
+
+            let remaining = num_results - posts.len();
+            posts.extend(
+                candidates
+                    .into_iter()
+                    .zip(watched.into_iter().zip(liked))
+                    .filter(|(_, (watched, liked))| !watched && !liked)
+                    .map(|(item, _)| item)
+                    .take(remaining),
+            );
+        }
+
+        Ok(FeedResponse { posts })
+    }
+}
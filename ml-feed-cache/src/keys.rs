@@ -0,0 +1,316 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    consts::{
+        BUFFER_ATTEMPTS_SUFFIX, BUFFER_DEAD_LETTER_SUFFIX, BUFFER_IN_FLIGHT_SUFFIX,
+        GLOBAL_CACHE_CLEAN_KEY, GLOBAL_CACHE_MIXED_KEY, GLOBAL_CACHE_NSFW_KEY,
+        MAX_GLOBAL_CACHE_LEN, MAX_HISTORY_PLAIN_POST_ITEM_CACHE_LEN, MAX_SUCCESS_HISTORY_CACHE_LEN,
+        MAX_USER_CACHE_LEN, MAX_WATCH_HISTORY_CACHE_LEN, MEMBER_META_SUFFIX,
+        USER_CACHE_CLEAN_SUFFIX, USER_CACHE_MIXED_SUFFIX, USER_CACHE_NSFW_SUFFIX,
+        USER_HOTORNOT_BUFFER_KEY, USER_LIKE_HISTORY_PLAIN_POST_ITEM_SUFFIX,
+        USER_SUCCESS_HISTORY_CLEAN_SUFFIX, USER_SUCCESS_HISTORY_NSFW_SUFFIX,
+        USER_WATCH_HISTORY_CLEAN_SUFFIX, USER_WATCH_HISTORY_NSFW_SUFFIX,
+        USER_WATCH_HISTORY_PLAIN_POST_ITEM_SUFFIX,
+    },
+    sorted_set::BoundedSortedSet,
+    types::CacheVariant,
+    FeedCacheError,
+};
+
+/// Group of redis keys sharing a layout and expiry policy
+#[derive(Serialize, Deserialize, Clone, Copy, ToSchema, Debug, PartialEq, Eq, Hash)]
+pub enum KeyFamily {
+    /// `{user}_cache_{clean,nsfw,mixed}`
+    UserCache,
+    /// `global_cache_{clean,nsfw,mixed}`
+    GlobalCache,
+    /// `{user}_watch_{clean,nsfw}`
+    WatchHistory,
+    /// `{user}_success_{clean,nsfw}`
+    SuccessHistory,
+    /// `{user}_{watch,like}_plain_post_item`
+    PlainHistory,
+    /// `user_hotornot_buffer` and its in-flight, attempts and dead-letter keys
+    HotOrNotBuffer,
+}
+
+impl KeyFamily {
+    pub const ALL: [KeyFamily; 6] = [
+        KeyFamily::UserCache,
+        KeyFamily::GlobalCache,
+        KeyFamily::WatchHistory,
+        KeyFamily::SuccessHistory,
+        KeyFamily::PlainHistory,
+        KeyFamily::HotOrNotBuffer,
+    ];
+
+    /// Suffixes appended to a user id, empty for global families
+    pub fn user_suffixes(self) -> &'static [&'static str] {
+        match self {
+            KeyFamily::UserCache => &[
+                USER_CACHE_CLEAN_SUFFIX,
+                USER_CACHE_NSFW_SUFFIX,
+                USER_CACHE_MIXED_SUFFIX,
+            ],
+            KeyFamily::WatchHistory => &[
+                USER_WATCH_HISTORY_CLEAN_SUFFIX,
+                USER_WATCH_HISTORY_NSFW_SUFFIX,
+            ],
+            KeyFamily::SuccessHistory => &[
+                USER_SUCCESS_HISTORY_CLEAN_SUFFIX,
+                USER_SUCCESS_HISTORY_NSFW_SUFFIX,
+            ],
+            KeyFamily::PlainHistory => &[
+                USER_WATCH_HISTORY_PLAIN_POST_ITEM_SUFFIX,
+                USER_LIKE_HISTORY_PLAIN_POST_ITEM_SUFFIX,
+            ],
+            KeyFamily::GlobalCache | KeyFamily::HotOrNotBuffer => &[],
+        }
+    }
+
+    /// Every suffix of a per-user key
+    pub fn all_user_suffixes() -> impl Iterator<Item = &'static str> {
+        Self::ALL
+            .into_iter()
+            .flat_map(|family| family.user_suffixes().iter().copied())
+    }
+
+    fn global_family(key: &str) -> Option<KeyFamily> {
+        if [
+            GLOBAL_CACHE_CLEAN_KEY,
+            GLOBAL_CACHE_NSFW_KEY,
+            GLOBAL_CACHE_MIXED_KEY,
+        ]
+        .contains(&key)
+        {
+            return Some(KeyFamily::GlobalCache);
+        }
+
+        let buffer_suffix = key.strip_prefix(USER_HOTORNOT_BUFFER_KEY)?;
+        [
+            "",
+            BUFFER_IN_FLIGHT_SUFFIX,
+            BUFFER_ATTEMPTS_SUFFIX,
+            BUFFER_DEAD_LETTER_SUFFIX,
+        ]
+        .contains(&buffer_suffix)
+        .then_some(KeyFamily::HotOrNotBuffer)
+    }
+
+    /// Family of `key`, with the user id for per-user families. Metadata hashes of
+    /// [`crate::MemberEncoding::Compact`] belong to the family of their set
+    ///
+    /// `None` for keys not written by this crate
+    pub fn parse(key: &str) -> Option<(KeyFamily, Option<&str>)> {
+        let key = key.strip_suffix(MEMBER_META_SUFFIX).unwrap_or(key);
+        if let Some(family) = Self::global_family(key) {
+            return Some((family, None));
+        }
+
+        Self::ALL.into_iter().find_map(|family| {
+            family
+                .user_suffixes()
+                .iter()
+                .find_map(|suffix| key.strip_suffix(suffix))
+                .filter(|user| !user.is_empty())
+                .map(|user| (family, Some(user)))
+        })
+    }
+
+    /// User id of a per-user `key`
+    pub fn user_of(key: &str) -> Option<&str> {
+        Self::parse(key).and_then(|(_, user)| user)
+    }
+}
+
+/// A key of one of the [`KeyFamily`]s, rendered with [`fmt::Display`]
+///
+/// `user` is the user's canister id
+#[derive(Serialize, Deserialize, Clone, ToSchema, Debug, PartialEq, Eq, Hash)]
+pub enum FeedKey {
+    /// `{user}_watch_{clean,nsfw}`
+    UserWatchHistory { user: String, nsfw: bool },
+    /// `{user}_success_{clean,nsfw}`
+    UserSuccessHistory { user: String, nsfw: bool },
+    /// `{user}_cache_{clean,nsfw,mixed}`
+    UserCache { user: String, variant: CacheVariant },
+    /// `global_cache_{clean,nsfw,mixed}`
+    GlobalCache { variant: CacheVariant },
+    /// `{user}_watch_plain_post_item`
+    PlainWatch { user: String },
+    /// `{user}_like_plain_post_item`
+    PlainLike { user: String },
+    /// `user_hotornot_buffer`
+    HotOrNotBuffer,
+}
+
+impl FeedKey {
+    pub fn family(&self) -> KeyFamily {
+        match self {
+            FeedKey::UserWatchHistory { .. } => KeyFamily::WatchHistory,
+            FeedKey::UserSuccessHistory { .. } => KeyFamily::SuccessHistory,
+            FeedKey::UserCache { .. } => KeyFamily::UserCache,
+            FeedKey::GlobalCache { .. } => KeyFamily::GlobalCache,
+            FeedKey::PlainWatch { .. } | FeedKey::PlainLike { .. } => KeyFamily::PlainHistory,
+            FeedKey::HotOrNotBuffer => KeyFamily::HotOrNotBuffer,
+        }
+    }
+
+    pub fn user(&self) -> Option<&str> {
+        match self {
+            FeedKey::UserWatchHistory { user, .. }
+            | FeedKey::UserSuccessHistory { user, .. }
+            | FeedKey::UserCache { user, .. }
+            | FeedKey::PlainWatch { user }
+            | FeedKey::PlainLike { user } => Some(user),
+            FeedKey::GlobalCache { .. } | FeedKey::HotOrNotBuffer => None,
+        }
+    }
+
+    /// Max number of members kept, `None` for the buffer which is drained by consumers
+    pub fn capacity(&self) -> Option<u64> {
+        match self.family() {
+            KeyFamily::UserCache => Some(MAX_USER_CACHE_LEN),
+            KeyFamily::GlobalCache => Some(MAX_GLOBAL_CACHE_LEN),
+            KeyFamily::WatchHistory => Some(MAX_WATCH_HISTORY_CACHE_LEN),
+            KeyFamily::SuccessHistory => Some(MAX_SUCCESS_HISTORY_CACHE_LEN),
+            KeyFamily::PlainHistory => Some(MAX_HISTORY_PLAIN_POST_ITEM_CACHE_LEN),
+            KeyFamily::HotOrNotBuffer => None,
+        }
+    }
+
+    /// `set` bounded to [`Self::capacity`]
+    pub(crate) fn bound<T>(&self, set: BoundedSortedSet<T>) -> BoundedSortedSet<T> {
+        match self.capacity() {
+            Some(capacity) => set.with_capacity(capacity),
+            None => set,
+        }
+    }
+
+    /// The rendered key, if it belongs to one of `families`
+    pub(crate) fn expect(&self, families: &[KeyFamily]) -> Result<String, FeedCacheError> {
+        if !families.contains(&self.family()) {
+            return Err(FeedCacheError::UnexpectedKey(self.clone()));
+        }
+        Ok(self.to_string())
+    }
+}
+
+impl fmt::Display for FeedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (user, suffix) = match self {
+            FeedKey::UserWatchHistory { user, nsfw } => (
+                user,
+                if *nsfw {
+                    USER_WATCH_HISTORY_NSFW_SUFFIX
+                } else {
+                    USER_WATCH_HISTORY_CLEAN_SUFFIX
+                },
+            ),
+            FeedKey::UserSuccessHistory { user, nsfw } => (
+                user,
+                if *nsfw {
+                    USER_SUCCESS_HISTORY_NSFW_SUFFIX
+                } else {
+                    USER_SUCCESS_HISTORY_CLEAN_SUFFIX
+                },
+            ),
+            FeedKey::UserCache { user, variant } => (
+                user,
+                match variant {
+                    CacheVariant::Clean => USER_CACHE_CLEAN_SUFFIX,
+                    CacheVariant::Nsfw => USER_CACHE_NSFW_SUFFIX,
+                    CacheVariant::Mixed => USER_CACHE_MIXED_SUFFIX,
+                },
+            ),
+            FeedKey::PlainWatch { user } => (user, USER_WATCH_HISTORY_PLAIN_POST_ITEM_SUFFIX),
+            FeedKey::PlainLike { user } => (user, USER_LIKE_HISTORY_PLAIN_POST_ITEM_SUFFIX),
+            FeedKey::GlobalCache { variant } => {
+                return f.write_str(match variant {
+                    CacheVariant::Clean => GLOBAL_CACHE_CLEAN_KEY,
+                    CacheVariant::Nsfw => GLOBAL_CACHE_NSFW_KEY,
+                    CacheVariant::Mixed => GLOBAL_CACHE_MIXED_KEY,
+                })
+            }
+            FeedKey::HotOrNotBuffer => return f.write_str(USER_HOTORNOT_BUFFER_KEY),
+        };
+        write!(f, "{user}{suffix}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(
+            KeyFamily::parse("user1_cache_nsfw"),
+            Some((KeyFamily::UserCache, Some("user1")))
+        );
+        assert_eq!(
+            KeyFamily::parse("user1_watch_plain_post_item"),
+            Some((KeyFamily::PlainHistory, Some("user1")))
+        );
+        // global keys share the user cache suffixes
+        assert_eq!(
+            KeyFamily::parse(GLOBAL_CACHE_CLEAN_KEY),
+            Some((KeyFamily::GlobalCache, None))
+        );
+        assert_eq!(
+            KeyFamily::parse("user_hotornot_buffer_dead_letter"),
+            Some((KeyFamily::HotOrNotBuffer, None))
+        );
+        assert_eq!(
+            KeyFamily::parse("user1_success_nsfw_meta"),
+            Some((KeyFamily::SuccessHistory, Some("user1")))
+        );
+        assert_eq!(KeyFamily::parse("test_key"), None);
+    }
+
+    #[test]
+    fn test_render_feed_key() {
+        let keys = [
+            FeedKey::UserWatchHistory {
+                user: "user1".to_string(),
+                nsfw: true,
+            },
+            FeedKey::UserCache {
+                user: "user1".to_string(),
+                variant: CacheVariant::Mixed,
+            },
+            FeedKey::GlobalCache {
+                variant: CacheVariant::Clean,
+            },
+            FeedKey::PlainLike {
+                user: "user1".to_string(),
+            },
+            FeedKey::HotOrNotBuffer,
+        ];
+        let rendered = keys.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            rendered,
+            [
+                "user1_watch_nsfw",
+                "user1_cache_mixed",
+                "global_cache_clean",
+                "user1_like_plain_post_item",
+                "user_hotornot_buffer",
+            ]
+        );
+
+        // rendered keys parse back to the same family and user
+        for key in &keys {
+            let rendered = key.to_string();
+            assert_eq!(
+                KeyFamily::parse(&rendered),
+                Some((key.family(), key.user()))
+            );
+        }
+        assert_eq!(keys[2].capacity(), Some(MAX_GLOBAL_CACHE_LEN));
+        assert_eq!(keys[4].capacity(), None);
+    }
+}
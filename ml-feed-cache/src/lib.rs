@@ -1,303 +1,407 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
 
+use buffer::now_secs;
 use consts::{
-    MAX_GLOBAL_CACHE_LEN, MAX_HISTORY_PLAIN_POST_ITEM_CACHE_LEN, MAX_SUCCESS_HISTORY_CACHE_LEN,
-    MAX_USER_CACHE_LEN, MAX_WATCH_HISTORY_CACHE_LEN, USER_HOTORNOT_BUFFER_KEY,
+    GLOBAL_CACHE, HISTORY_PLAIN_POST_ITEM_CACHE, SUCCESS_HISTORY_CACHE, USER_CACHE,
+    USER_LAST_ACTIVE_KEY, WATCH_HISTORY_CACHE,
 };
-use redis::AsyncCommands;
-use types::{get_history_item_score, BufferItem, MLFeedCacheHistoryItem, PlainPostItem, PostItem};
-
+use encoding::{decode, encode_member, member_forms, CompactMember};
+use keys::{FeedKey, KeyFamily};
+use redis::{AsyncCommands, FromRedisValue, Pipeline, RedisResult, ToRedisArgs};
+use scoring::{DefaultScorer, HistoryScorer};
+use sorted_set::BoundedSortedSet;
+use types::{BufferItem, MLFeedCacheHistoryItem, PlainPostItem, PostItem};
+
+mod buffer;
+mod cache;
+mod config;
 pub mod consts;
+mod encoding;
+mod error;
+mod feed;
+pub mod keys;
+mod maintenance;
+pub mod memory;
+mod migration;
+pub mod scoring;
+pub mod sorted_set;
 pub mod types;
 
+pub use cache::*;
+pub use config::*;
+pub use error::*;
+pub use maintenance::*;
+pub use migration::*;
+
+/// Families read by [`MLFeedCacheState::get_history_items`]
+pub(crate) const HISTORY_FAMILIES: &[KeyFamily] =
+    &[KeyFamily::WatchHistory, KeyFamily::SuccessHistory];
+/// Families read by [`MLFeedCacheState::get_cache_items`]
+pub(crate) const CACHE_FAMILIES: &[KeyFamily] = &[KeyFamily::UserCache, KeyFamily::GlobalCache];
+
 pub type RedisPool = bb8::Pool<bb8_redis::RedisConnectionManager>;
+pub type RedisConnection<'a> = bb8::PooledConnection<'a, bb8_redis::RedisConnectionManager>;
 
 #[derive(Clone)]
 pub struct MLFeedCacheState {
     pub redis_pool: RedisPool,
+    ttls: KeyTtls,
+    member_encoding: MemberEncoding,
+    watch_history_scorer: Arc<dyn HistoryScorer>,
+    success_history_scorer: Arc<dyn HistoryScorer>,
 }
 
 pub async fn init_redis() -> RedisPool {
-    let redis_url =
-        std::env::var("ML_FEED_CACHE_REDIS_URL").expect("ML_FEED_CACHE_REDIS_URL must be set");
+    let config = FeedCacheConfig::from_env().expect("ML_FEED_CACHE_REDIS_URL must be set");
+    init_redis_with_config(&config)
+        .await
+        .expect("failed to open connection to redis")
+}
+
+pub async fn init_redis_with_config(config: &FeedCacheConfig) -> Result<RedisPool, FeedCacheError> {
+    let manager = bb8_redis::RedisConnectionManager::new(config.redis_url.clone())
+        .map_err(|e| FeedCacheError::Config(format!("invalid redis url: {e}")))?;
+
+    let pool = RedisPool::builder()
+        .max_size(config.pool_size)
+        .connection_timeout(config.connection_timeout)
+        .idle_timeout(config.idle_timeout)
+        .build(manager)
+        .await?;
+
+    Ok(pool)
+}
 
-    let manager = bb8_redis::RedisConnectionManager::new(redis_url.clone())
-        .expect("failed to open connection to redis");
-    RedisPool::builder().build(manager).await.unwrap()
+pub(crate) fn score_items(
+    scorer: &dyn HistoryScorer,
+    items: Vec<MLFeedCacheHistoryItem>,
+) -> Vec<(f64, MLFeedCacheHistoryItem)> {
+    items
+        .into_iter()
+        .map(|item| (scorer.score(&item), item))
+        .collect()
+}
+
+/// Plain items scored by timestamp
+pub(crate) fn plain_items(items: &[MLFeedCacheHistoryItem]) -> Vec<(f64, PlainPostItem)> {
+    items
+        .iter()
+        .map(|item| {
+            (
+                item.timestamp
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs() as f64,
+                PlainPostItem {
+                    canister_id: item.canister_id.clone(),
+                    post_id: item.post_id,
+                },
+            )
+        })
+        .collect()
 }
 
 impl MLFeedCacheState {
+    /// # Panics
+    /// if `ML_FEED_CACHE_REDIS_URL` is not set or redis is unreachable,
+    /// use [`MLFeedCacheState::try_new`] to handle these errors
     pub async fn new() -> Self {
         let redis_pool = init_redis().await;
-        Self { redis_pool }
+        Self::with_pool(redis_pool)
     }
 
-    pub async fn add_user_watch_history_items(
-        &self,
-        key: &str,
-        items: Vec<MLFeedCacheHistoryItem>,
-    ) -> Result<(), anyhow::Error> {
-        let mut conn = self.redis_pool.get().await.unwrap();
-
-        let items = items
-            .iter()
-            .map(|item| (get_history_item_score(item), item.clone()))
-            .collect::<Vec<_>>();
+    pub async fn try_new(config: FeedCacheConfig) -> Result<Self, FeedCacheError> {
+        let redis_pool = init_redis_with_config(&config).await?;
+        Ok(Self::with_pool(redis_pool)
+            .with_ttls(config.ttls)
+            .with_member_encoding(config.member_encoding))
+    }
 
-        // zadd_multiple in groups of 1000
-        let chunk_size = 1000;
-        for chunk in items.chunks(chunk_size) {
-            conn
-                .zadd_multiple::<&str, f64, MLFeedCacheHistoryItem, ()>(key, chunk)
-                .await?;
+    pub fn with_pool(redis_pool: RedisPool) -> Self {
+        Self {
+            redis_pool,
+            ttls: KeyTtls::default(),
+            member_encoding: MemberEncoding::default(),
+            watch_history_scorer: Arc::new(DefaultScorer),
+            success_history_scorer: Arc::new(DefaultScorer),
         }
+    }
 
-        // get num items in the list
-        let num_items = conn.zcard::<&str, u64>(key).await?;
+    pub fn with_ttls(mut self, ttls: KeyTtls) -> Self {
+        self.ttls = ttls;
+        self
+    }
 
-        // if num items is greater than MAX_WATCH_HISTORY_CACHE_LEN, remove the oldest items till len is MAX_WATCH_HISTORY_CACHE_LEN without while loop
-        if num_items > MAX_WATCH_HISTORY_CACHE_LEN {
-            conn
-                .zremrangebyrank::<&str, ()>(
-                    key,
-                    0,
-                    (num_items - (MAX_WATCH_HISTORY_CACHE_LEN + 1)) as isize,
-                )
-                .await?;
-        }
+    /// Encoding of new members, see [`MemberEncoding`]
+    pub fn with_member_encoding(mut self, member_encoding: MemberEncoding) -> Self {
+        self.member_encoding = member_encoding;
+        self
+    }
 
-        Ok(())
+    /// Rank items added by [`Self::add_user_watch_history_items`] with `scorer`
+    pub fn with_watch_history_scorer(mut self, scorer: impl HistoryScorer + 'static) -> Self {
+        self.watch_history_scorer = Arc::new(scorer);
+        self
     }
 
-    pub async fn add_user_success_history_items(
-        &self,
-        key: &str,
-        items: Vec<MLFeedCacheHistoryItem>,
-    ) -> Result<(), anyhow::Error> {
-        let mut conn = self.redis_pool.get().await.unwrap();
+    /// Rank items added by [`Self::add_user_success_history_items`] with `scorer`
+    pub fn with_success_history_scorer(mut self, scorer: impl HistoryScorer + 'static) -> Self {
+        self.success_history_scorer = Arc::new(scorer);
+        self
+    }
 
-        let items = items
-            .iter()
-            .map(|item| (get_history_item_score(item), item.clone()))
-            .collect::<Vec<_>>();
+    async fn conn(&self) -> Result<RedisConnection<'_>, FeedCacheError> {
+        Ok(self.redis_pool.get().await?)
+    }
 
-        // zadd_multiple in groups of 1000
-        let chunk_size = 1000;
-        for chunk in items.chunks(chunk_size) {
-            conn
-                .zadd_multiple::<&str, f64, MLFeedCacheHistoryItem, ()>(key, chunk)
-                .await?;
+    /// Transaction for a write to `key`, marking its user (if any) as active
+    fn write_pipe(key: &FeedKey) -> Pipeline {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if let Some(user) = key.user() {
+            pipe.zadd(USER_LAST_ACTIVE_KEY, user, now_secs()).ignore();
         }
+        pipe
+    }
 
-        // get num items in the list
-        let num_items = conn.zcard::<&str, u64>(key).await?;
+    /// Queue adding scored `items` to `key` in the configured encoding
+    fn pipe_add_scored<T>(
+        &self,
+        set: BoundedSortedSet<T>,
+        pipe: &mut Pipeline,
+        key: &str,
+        items: &[(f64, T)],
+    ) where
+        T: CompactMember + ToRedisArgs + FromRedisValue + Send + Sync,
+    {
+        match self.member_encoding {
+            MemberEncoding::Json => set.pipe_add_scored(pipe, key, items),
+            MemberEncoding::Compact => set.pipe_add_compact_scored(pipe, key, items),
+        }
+    }
 
-        if num_items > MAX_SUCCESS_HISTORY_CACHE_LEN {
-            conn
-                .zremrangebyrank::<&str, ()>(
-                    key,
-                    0,
-                    (num_items - (MAX_SUCCESS_HISTORY_CACHE_LEN + 1)) as isize,
-                )
-                .await?;
+    /// Queue adding `items` to `key` in the configured encoding
+    fn pipe_add<T>(&self, set: BoundedSortedSet<T>, pipe: &mut Pipeline, key: &str, items: &[T])
+    where
+        T: CompactMember + ToRedisArgs + FromRedisValue + Send + Sync,
+    {
+        match self.member_encoding {
+            MemberEncoding::Json => set.pipe_add(pipe, key, items),
+            MemberEncoding::Compact => set.pipe_add_compact(pipe, key, items),
+        }
+    }
+
+    async fn range_rev<T>(
+        &self,
+        conn: &mut RedisConnection<'_>,
+        set: BoundedSortedSet<T>,
+        key: &str,
+        start: u64,
+        end: u64,
+    ) -> RedisResult<Vec<T>>
+    where
+        T: CompactMember + ToRedisArgs + FromRedisValue + Send + Sync,
+    {
+        match self.member_encoding {
+            MemberEncoding::Json => set.range_rev(&mut **conn, key, start, end).await,
+            MemberEncoding::Compact => set.range_rev_compact(&mut **conn, key, start, end).await,
         }
+    }
 
+    pub async fn add_user_watch_history_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<MLFeedCacheHistoryItem>,
+    ) -> Result<(), FeedCacheError> {
+        let redis_key = key.expect(&[KeyFamily::WatchHistory])?;
+        let mut conn = self.conn().await?;
+        let items = score_items(&*self.watch_history_scorer, items);
+        let mut pipe = Self::write_pipe(key);
+        self.pipe_add_scored(
+            key.bound(WATCH_HISTORY_CACHE)
+                .with_optional_ttl(self.ttls.watch_history),
+            &mut pipe,
+            &redis_key,
+            &items,
+        );
+        pipe.query_async::<()>(&mut *conn).await?;
         Ok(())
     }
 
+    pub async fn add_user_success_history_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<MLFeedCacheHistoryItem>,
+    ) -> Result<(), FeedCacheError> {
+        let redis_key = key.expect(&[KeyFamily::SuccessHistory])?;
+        let mut conn = self.conn().await?;
+        let items = score_items(&*self.success_history_scorer, items);
+        let mut pipe = Self::write_pipe(key);
+        self.pipe_add_scored(
+            key.bound(SUCCESS_HISTORY_CACHE)
+                .with_optional_ttl(self.ttls.success_history),
+            &mut pipe,
+            &redis_key,
+            &items,
+        );
+        pipe.query_async::<()>(&mut *conn).await?;
+        Ok(())
+    }
+
+    /// Items of a watch or success history key, highest score first
     pub async fn get_history_items(
         &self,
-        key: &str,
+        key: &FeedKey,
         start: u64,
         end: u64,
-    ) -> Result<Vec<MLFeedCacheHistoryItem>, anyhow::Error> {
-        let mut conn = self.redis_pool.get().await.unwrap();
-
-        let items = conn
-            .zrevrange::<&str, Vec<MLFeedCacheHistoryItem>>(key, start as isize, end as isize)
+    ) -> Result<Vec<MLFeedCacheHistoryItem>, FeedCacheError> {
+        let redis_key = key.expect(HISTORY_FAMILIES)?;
+        let mut conn = self.conn().await?;
+        let items = self
+            .range_rev(&mut conn, WATCH_HISTORY_CACHE, &redis_key, start, end)
             .await?;
-
         Ok(items)
     }
 
-    pub async fn get_history_items_len(&self, key: &str) -> Result<u64, anyhow::Error> {
-        let mut conn = self.redis_pool.get().await.unwrap();
-        let num_items = conn.zcard::<&str, u64>(key).await?;
+    pub async fn get_history_items_len(&self, key: &FeedKey) -> Result<u64, FeedCacheError> {
+        let redis_key = key.expect(HISTORY_FAMILIES)?;
+        let mut conn = self.conn().await?;
+        let num_items = WATCH_HISTORY_CACHE.len(&mut *conn, &redis_key).await?;
         Ok(num_items)
     }
 
     pub async fn add_user_history_plain_items(
         &self,
-        key: &str,
+        key: &FeedKey,
         items: Vec<MLFeedCacheHistoryItem>,
-    ) -> Result<(), anyhow::Error> {
-        let mut conn = self.redis_pool.get().await.unwrap();
-
-        let items = items
-            .iter()
-            .map(|item| {
-                (
-                    item.timestamp
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_secs(),
-                    PlainPostItem {
-                        canister_id: item.canister_id.clone(),
-                        post_id: item.post_id,
-                    },
-                )
-            })
-            .collect::<Vec<_>>();
-
-        // zadd_multiple in groups of 1000
-        let chunk_size = 1000;
-        for chunk in items.chunks(chunk_size) {
-            conn
-                .zadd_multiple::<&str, u64, PlainPostItem, ()>(key, chunk)
-                .await?;
-        }
-
-        // get num items in the list
-        let num_items = conn.zcard::<&str, u64>(key).await?;
-
-        // if num items is greater than MAX_HISTORY_PLAIN_POST_ITEM_CACHE_LEN, remove the oldest items till len is MAX_HISTORY_PLAIN_POST_ITEM_CACHE_LEN without while loop
-        if num_items > MAX_HISTORY_PLAIN_POST_ITEM_CACHE_LEN {
-            conn
-                .zremrangebyrank::<&str, ()>(
-                    key,
-                    0,
-                    (num_items - (MAX_HISTORY_PLAIN_POST_ITEM_CACHE_LEN + 1)) as isize,
-                )
-                .await?;
-        }
+    ) -> Result<(), FeedCacheError> {
+        let redis_key = key.expect(&[KeyFamily::PlainHistory])?;
+        let mut conn = self.conn().await?;
+        let items = plain_items(&items);
+        let mut pipe = Self::write_pipe(key);
+        self.pipe_add_scored(
+            key.bound(HISTORY_PLAIN_POST_ITEM_CACHE)
+                .with_optional_ttl(self.ttls.plain_history),
+            &mut pipe,
+            &redis_key,
+            &items,
+        );
+        pipe.query_async::<()>(&mut *conn).await?;
 
         Ok(())
     }
 
     pub async fn is_user_history_plain_item_exists(
         &self,
-        key: &str,
+        key: &FeedKey,
         item: PlainPostItem,
-    ) -> Result<bool, anyhow::Error> {
-        let mut conn = self.redis_pool.get().await.unwrap();
-
-        let res = conn
-            .zscore::<&str, PlainPostItem, Option<f64>>(key, item)
-            .await?;
-
-        Ok(res.is_some())
+    ) -> Result<bool, FeedCacheError> {
+        let key = key.expect(&[KeyFamily::PlainHistory])?;
+        let mut conn = self.conn().await?;
+        let res = match self.member_encoding {
+            MemberEncoding::Json => {
+                HISTORY_PLAIN_POST_ITEM_CACHE
+                    .contains(&mut *conn, &key, &item)
+                    .await?
+            }
+            // the key may not be migrated yet
+            MemberEncoding::Compact => {
+                let scores: Vec<Option<f64>> = redis::cmd("ZMSCORE")
+                    .arg(&key)
+                    .arg(member_forms(&item).as_slice())
+                    .query_async(&mut *conn)
+                    .await?;
+                scores.iter().any(Option::is_some)
+            }
+        };
+        Ok(res)
     }
 
     pub async fn add_user_cache_items(
         &self,
-        key: &str,
+        key: &FeedKey,
         items: Vec<PostItem>,
-    ) -> Result<(), anyhow::Error> {
-        let mut conn = self.redis_pool.get().await.unwrap();
-
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as f64;
-
-        let items = items
-            .iter()
-            .map(|item| (timestamp, item.clone()))
-            .collect::<Vec<_>>();
-
-        // zadd_multiple in groups of 1000
-        let chunk_size = 1000;
-        for chunk in items.chunks(chunk_size) {
-            conn
-                .zadd_multiple::<&str, f64, PostItem, ()>(key, chunk)
-                .await?;
-        }
-
-        // get num items in the list
-        let num_items = conn.zcard::<&str, u64>(key).await?;
-
-        if num_items > MAX_USER_CACHE_LEN {
-            conn
-                .zremrangebyrank::<&str, ()>(key, 0, (num_items - MAX_USER_CACHE_LEN - 1) as isize)
-                .await?;
-        }
-
+    ) -> Result<(), FeedCacheError> {
+        let redis_key = key.expect(&[KeyFamily::UserCache])?;
+        let mut conn = self.conn().await?;
+        let mut pipe = Self::write_pipe(key);
+        self.pipe_add(
+            key.bound(USER_CACHE)
+                .with_optional_ttl(self.ttls.user_cache),
+            &mut pipe,
+            &redis_key,
+            &items,
+        );
+        pipe.query_async::<()>(&mut *conn).await?;
         Ok(())
     }
 
     pub async fn add_global_cache_items(
         &self,
-        key: &str,
+        key: &FeedKey,
         items: Vec<PostItem>,
-    ) -> Result<(), anyhow::Error> {
-        let mut conn = self.redis_pool.get().await.unwrap();
-
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as f64;
-
-        let items = items
-            .iter()
-            .map(|item| (timestamp, item.clone()))
-            .collect::<Vec<_>>();
-
-        // zadd_multiple in groups of 1000
-        let chunk_size = 1000;
-        for chunk in items.chunks(chunk_size) {
-            conn
-                .zadd_multiple::<&str, f64, PostItem, ()>(key, chunk)
-                .await?;
-        }
-
-        // get num items in the list
-        let num_items = conn.zcard::<&str, u64>(key).await?;
-
-        if num_items > MAX_GLOBAL_CACHE_LEN {
-            conn
-                .zremrangebyrank::<&str, ()>(
-                    key,
-                    0,
-                    (num_items - MAX_GLOBAL_CACHE_LEN - 1) as isize,
-                )
-                .await?;
-        }
-
+    ) -> Result<(), FeedCacheError> {
+        let redis_key = key.expect(&[KeyFamily::GlobalCache])?;
+        let mut conn = self.conn().await?;
+        let mut pipe = Self::write_pipe(key);
+        self.pipe_add(
+            key.bound(GLOBAL_CACHE)
+                .with_optional_ttl(self.ttls.global_cache),
+            &mut pipe,
+            &redis_key,
+            &items,
+        );
+        pipe.query_async::<()>(&mut *conn).await?;
         Ok(())
     }
 
+    /// Items of a user or global cache key, newest first
     pub async fn get_cache_items(
         &self,
-        key: &str,
+        key: &FeedKey,
         start: u64,
         end: u64,
-    ) -> Result<Vec<PostItem>, anyhow::Error> {
-        let mut conn = self.redis_pool.get().await.unwrap();
-
-        let items = conn
-            .zrevrange::<&str, Vec<PostItem>>(key, start as isize, end as isize)
+    ) -> Result<Vec<PostItem>, FeedCacheError> {
+        let redis_key = key.expect(CACHE_FAMILIES)?;
+        let mut conn = self.conn().await?;
+        let items = self
+            .range_rev(&mut conn, USER_CACHE, &redis_key, start, end)
             .await?;
-
         Ok(items)
     }
 
-    pub async fn get_cache_items_len(&self, key: &str) -> Result<u64, anyhow::Error> {
-        let mut conn = self.redis_pool.get().await.unwrap();
-        let num_items = conn.zcard::<&str, u64>(key).await?;
+    pub async fn get_cache_items_len(&self, key: &FeedKey) -> Result<u64, FeedCacheError> {
+        let redis_key = key.expect(CACHE_FAMILIES)?;
+        let mut conn = self.conn().await?;
+        let num_items = USER_CACHE.len(&mut *conn, &redis_key).await?;
         Ok(num_items)
     }
 
-    pub async fn add_user_buffer_items(&self, items: Vec<BufferItem>) -> Result<(), anyhow::Error> {
-        self.add_user_buffer_items_impl(USER_HOTORNOT_BUFFER_KEY, items)
+    pub async fn add_user_buffer_items(
+        &self,
+        items: Vec<BufferItem>,
+    ) -> Result<(), FeedCacheError> {
+        self.add_user_buffer_items_impl(&FeedKey::HotOrNotBuffer, items)
             .await
     }
 
+    /// `key` must be a [`KeyFamily::HotOrNotBuffer`] key
     pub async fn add_user_buffer_items_impl(
+        &self,
+        key: &FeedKey,
+        items: Vec<BufferItem>,
+    ) -> Result<(), FeedCacheError> {
+        let key = key.expect(&[KeyFamily::HotOrNotBuffer])?;
+        self.add_buffer_items_at(&key, items).await
+    }
+
+    /// [`Self::add_user_buffer_items_impl`] on the redis key `key`
+    pub(crate) async fn add_buffer_items_at(
         &self,
         key: &str,
         items: Vec<BufferItem>,
-    ) -> Result<(), anyhow::Error> {
-        let mut conn = self.redis_pool.get().await.unwrap();
+    ) -> Result<(), FeedCacheError> {
+        let mut conn = self.conn().await?;
 
         let items = items
             .iter()
@@ -307,7 +411,7 @@ impl MLFeedCacheState {
                         .duration_since(std::time::UNIX_EPOCH)
                         .unwrap_or_default()
                         .as_secs(),
-                    item.clone(),
+                    encode_member(self.member_encoding, item),
                 )
             })
             .collect::<Vec<_>>();
@@ -315,53 +419,66 @@ impl MLFeedCacheState {
         // zadd_multiple in groups of 1000
         let chunk_size = 1000;
         for chunk in items.chunks(chunk_size) {
-            conn
-                .zadd_multiple::<&str, u64, BufferItem, ()>(key, chunk)
+            conn.zadd_multiple::<&str, u64, Vec<u8>, ()>(key, chunk)
                 .await?;
         }
 
         Ok(())
     }
 
+    #[deprecated(note = "not safe with several consumers, use `claim_user_buffer_batch` instead")]
+    #[allow(deprecated)]
     pub async fn get_user_buffer_items_by_timestamp(
         &self,
         timestamp: u64,
-    ) -> Result<Vec<BufferItem>, anyhow::Error> {
-        self.get_user_buffer_items_by_timestamp_impl(USER_HOTORNOT_BUFFER_KEY, timestamp)
+    ) -> Result<Vec<BufferItem>, FeedCacheError> {
+        self.get_user_buffer_items_by_timestamp_impl(&FeedKey::HotOrNotBuffer, timestamp)
             .await
     }
 
+    /// `key` must be a [`KeyFamily::HotOrNotBuffer`] key
+    #[deprecated(note = "not safe with several consumers, use `claim_user_buffer_batch` instead")]
     pub async fn get_user_buffer_items_by_timestamp_impl(
         &self,
-        key: &str,
+        key: &FeedKey,
         timestamp_secs: u64,
-    ) -> Result<Vec<BufferItem>, anyhow::Error> {
-        let mut conn = self.redis_pool.get().await.unwrap();
+    ) -> Result<Vec<BufferItem>, FeedCacheError> {
+        let key = key.expect(&[KeyFamily::HotOrNotBuffer])?;
+        let mut conn = self.conn().await?;
 
-        let items = conn
-            .zrangebyscore::<&str, u64, u64, Vec<BufferItem>>(key, 0, timestamp_secs)
+        let members = conn
+            .zrangebyscore::<&str, u64, u64, Vec<Vec<u8>>>(&key, 0, timestamp_secs)
             .await?;
 
+        let items = members
+            .iter()
+            .map(|member| decode(member, &[]))
+            .collect::<RedisResult<_>>()?;
         Ok(items)
     }
 
+    #[deprecated(note = "not safe with several consumers, use `ack_user_buffer_items` instead")]
+    #[allow(deprecated)]
     pub async fn remove_user_buffer_items_by_timestamp(
         &self,
         timestamp_secs: u64,
-    ) -> Result<u64, anyhow::Error> {
-        self.remove_user_buffer_items_by_timestamp_impl(USER_HOTORNOT_BUFFER_KEY, timestamp_secs)
+    ) -> Result<u64, FeedCacheError> {
+        self.remove_user_buffer_items_by_timestamp_impl(&FeedKey::HotOrNotBuffer, timestamp_secs)
             .await
     }
 
+    /// `key` must be a [`KeyFamily::HotOrNotBuffer`] key
+    #[deprecated(note = "not safe with several consumers, use `ack_user_buffer_items` instead")]
     pub async fn remove_user_buffer_items_by_timestamp_impl(
         &self,
-        key: &str,
+        key: &FeedKey,
         timestamp_secs: u64,
-    ) -> Result<u64, anyhow::Error> {
-        let mut conn = self.redis_pool.get().await.unwrap();
+    ) -> Result<u64, FeedCacheError> {
+        let key = key.expect(&[KeyFamily::HotOrNotBuffer])?;
+        let mut conn = self.conn().await?;
 
         let res = conn
-            .zrembyscore::<&str, u64, u64, u64>(key, 0, timestamp_secs)
+            .zrembyscore::<&str, u64, u64, u64>(&key, 0, timestamp_secs)
             .await?;
 
         Ok(res)
@@ -370,23 +487,37 @@ impl MLFeedCacheState {
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
     use super::*;
+    use consts::{
+        MAX_HISTORY_PLAIN_POST_ITEM_CACHE_LEN, MAX_SUCCESS_HISTORY_CACHE_LEN,
+        MAX_WATCH_HISTORY_CACHE_LEN,
+    };
+    use types::{CacheVariant, FeedParams};
 
     #[tokio::test]
+    #[ignore = "needs redis at ML_FEED_CACHE_REDIS_URL"]
     async fn test_add_user_watch_history_items() {
         let state = MLFeedCacheState::new().await;
 
         let mut conn = state.redis_pool.get().await.unwrap();
+        let key = FeedKey::UserWatchHistory {
+            user: "test_user".to_string(),
+            nsfw: false,
+        };
+        let plain_key = FeedKey::PlainWatch {
+            user: "test_user".to_string(),
+        };
+        let (redis_key, redis_plain_key) = (key.to_string(), plain_key.to_string());
         // delete the key
-        let _res = conn.del::<&str, ()>("test_key").await;
+        let _res = conn.del::<&str, ()>(&redis_key).await;
         assert!(_res.is_ok());
 
-        let _res = conn.del::<&str, ()>("test_key_plain").await;
+        let _res = conn.del::<&str, ()>(&redis_plain_key).await;
         assert!(_res.is_ok());
 
-        let num_items = conn.zcard::<&str, u64>("test_key").await.unwrap();
+        let num_items = conn.zcard::<&str, u64>(&redis_key).await.unwrap();
         assert_eq!(num_items, 0);
 
         let mut items = Vec::new();
@@ -403,24 +534,24 @@ mod tests {
         }
 
         let res = state
-            .add_user_watch_history_items("test_key", items.clone())
+            .add_user_watch_history_items(&key, items.clone())
             .await;
         assert!(res.is_ok());
 
         // add plain post items
         let res = state
-            .add_user_history_plain_items("test_key_plain", items.clone())
+            .add_user_history_plain_items(&plain_key, items.clone())
             .await;
         assert!(res.is_ok());
 
-        let num_items = conn.zcard::<&str, u64>("test_key").await.unwrap();
+        let num_items = conn.zcard::<&str, u64>(&redis_key).await.unwrap();
         assert_eq!(num_items, MAX_WATCH_HISTORY_CACHE_LEN);
 
-        let num_items_plain = conn.zcard::<&str, u64>("test_key_plain").await.unwrap();
+        let num_items_plain = conn.zcard::<&str, u64>(&redis_plain_key).await.unwrap();
         assert_eq!(num_items_plain, MAX_HISTORY_PLAIN_POST_ITEM_CACHE_LEN);
 
         let items = conn
-            .zrevrange_withscores::<&str, Vec<(MLFeedCacheHistoryItem, f64)>>("test_key", 0, 4)
+            .zrevrange_withscores::<&str, Vec<(MLFeedCacheHistoryItem, f64)>>(&redis_key, 0, 4)
             .await
             .unwrap();
         assert_eq!(items.len(), 5);
@@ -433,7 +564,7 @@ mod tests {
         // check if the plain item exists
         let res = state
             .is_user_history_plain_item_exists(
-                "test_key_plain",
+                &plain_key,
                 PlainPostItem {
                     canister_id: "test_canister_id".to_string(),
                     post_id: MAX_WATCH_HISTORY_CACHE_LEN + 10 - 1,
@@ -446,7 +577,7 @@ mod tests {
         // check if the plain item does not exist
         let res = state
             .is_user_history_plain_item_exists(
-                "test_key_plain",
+                &plain_key,
                 PlainPostItem {
                     canister_id: "test_canister_id".to_string(),
                     post_id: MAX_WATCH_HISTORY_CACHE_LEN + 10 + 1,
@@ -458,15 +589,21 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "needs redis at ML_FEED_CACHE_REDIS_URL"]
     async fn test_add_user_success_history_items() {
         let state = MLFeedCacheState::new().await;
 
         let mut conn = state.redis_pool.get().await.unwrap();
+        let key = FeedKey::UserSuccessHistory {
+            user: "test_user".to_string(),
+            nsfw: false,
+        };
+        let redis_key = key.to_string();
         // delete the key
-        let _res = conn.del::<&str, ()>("test_key").await;
+        let _res = conn.del::<&str, ()>(&redis_key).await;
         assert!(_res.is_ok());
 
-        let num_items = conn.zcard::<&str, u64>("test_key").await.unwrap();
+        let num_items = conn.zcard::<&str, u64>(&redis_key).await.unwrap();
         assert_eq!(num_items, 0);
 
         let mut items = Vec::new();
@@ -482,16 +619,14 @@ mod tests {
             });
         }
 
-        let res = state
-            .add_user_success_history_items("test_key", items)
-            .await;
+        let res = state.add_user_success_history_items(&key, items).await;
         assert!(res.is_ok());
 
-        let num_items = conn.zcard::<&str, u64>("test_key").await.unwrap();
+        let num_items = conn.zcard::<&str, u64>(&redis_key).await.unwrap();
         assert_eq!(num_items, MAX_SUCCESS_HISTORY_CACHE_LEN);
 
         let items = conn
-            .zrevrange_withscores::<&str, Vec<(MLFeedCacheHistoryItem, f64)>>("test_key", 0, 4)
+            .zrevrange_withscores::<&str, Vec<(MLFeedCacheHistoryItem, f64)>>(&redis_key, 0, 4)
             .await
             .unwrap();
         assert_eq!(items.len(), 5);
@@ -503,18 +638,19 @@ mod tests {
     }
 
     #[tokio::test]
+    #[ignore = "needs redis at ML_FEED_CACHE_REDIS_URL"]
+    #[allow(deprecated)]
     async fn test_add_user_buffer_items() {
         let state = MLFeedCacheState::new().await;
 
         let mut conn = state.redis_pool.get().await.unwrap();
 
-        let _res = conn.del::<&str, ()>("test_key").await;
+        let key = FeedKey::HotOrNotBuffer;
+        let redis_key = key.to_string();
+        let _res = conn.del::<&str, ()>(&redis_key).await;
         assert!(_res.is_ok());
 
-        let _res = conn.del::<&str, ()>(USER_HOTORNOT_BUFFER_KEY).await;
-        assert!(_res.is_ok());
-
-        let num_items = conn.zcard::<&str, u64>("test_key").await.unwrap();
+        let num_items = conn.zcard::<&str, u64>(&redis_key).await.unwrap();
         assert_eq!(num_items, 0);
 
         let mut items = Vec::new();
@@ -530,16 +666,14 @@ mod tests {
             });
         }
 
-        let res = state
-            .add_user_buffer_items_impl("test_key", items.clone())
-            .await;
+        let res = state.add_user_buffer_items_impl(&key, items.clone()).await;
         assert!(res.is_ok());
 
-        let num_items = conn.zcard::<&str, u64>("test_key").await.unwrap();
+        let num_items = conn.zcard::<&str, u64>(&redis_key).await.unwrap();
         assert_eq!(num_items, 100);
 
         let res_items = conn
-            .zrevrange_withscores::<&str, Vec<(BufferItem, u64)>>("test_key", 0, 4)
+            .zrevrange_withscores::<&str, Vec<(BufferItem, u64)>>(&redis_key, 0, 4)
             .await
             .unwrap();
         assert_eq!(res_items.len(), 5);
@@ -553,7 +687,7 @@ mod tests {
         let timestamp = items[4].timestamp;
         let timestamp_secs = timestamp.duration_since(UNIX_EPOCH).unwrap().as_secs();
         let items = state
-            .get_user_buffer_items_by_timestamp_impl("test_key", timestamp_secs)
+            .get_user_buffer_items_by_timestamp_impl(&key, timestamp_secs)
             .await
             .unwrap();
         assert_eq!(items.len(), 5);
@@ -565,12 +699,320 @@ mod tests {
 
         // remove the items
         let res = state
-            .remove_user_buffer_items_by_timestamp_impl("test_key", timestamp_secs)
+            .remove_user_buffer_items_by_timestamp_impl(&key, timestamp_secs)
             .await;
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), 5);
 
-        let num_items = conn.zcard::<&str, u64>("test_key").await.unwrap();
+        let num_items = conn.zcard::<&str, u64>(&redis_key).await.unwrap();
         assert_eq!(num_items, 95);
     }
+
+    #[tokio::test]
+    async fn test_buffer_rejects_other_keys() {
+        // never connects, keys are checked first
+        let manager = bb8_redis::RedisConnectionManager::new("redis://127.0.0.1:1").unwrap();
+        let state = MLFeedCacheState::with_pool(RedisPool::builder().build_unchecked(manager));
+        let key = FeedKey::GlobalCache {
+            variant: CacheVariant::Clean,
+        };
+
+        let res = state.claim_user_buffer_batch_impl(&key, 0, 1).await;
+        assert!(matches!(res, Err(FeedCacheError::UnexpectedKey(k)) if k == key));
+        let res = state.add_user_buffer_items_impl(&key, Vec::new()).await;
+        assert!(matches!(res, Err(FeedCacheError::UnexpectedKey(k)) if k == key));
+    }
+
+    #[tokio::test]
+    async fn test_try_new_invalid_url() {
+        let res = MLFeedCacheState::try_new(FeedCacheConfig::new("not a redis url")).await;
+        assert!(matches!(res, Err(FeedCacheError::Config(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs redis at ML_FEED_CACHE_REDIS_URL"]
+    async fn test_next_feed() {
+        let state = MLFeedCacheState::new().await;
+
+        let mut conn = state.redis_pool.get().await.unwrap();
+        let user = "test_feed_user";
+        let user_key = FeedKey::UserCache {
+            user: user.to_string(),
+            variant: CacheVariant::Clean,
+        };
+        let watched_key = FeedKey::PlainWatch {
+            user: user.to_string(),
+        }
+        .to_string();
+        let liked_key = FeedKey::PlainLike {
+            user: user.to_string(),
+        }
+        .to_string();
+        for key in [&user_key.to_string(), &watched_key, &liked_key] {
+            let _res = conn.del::<&str, ()>(key).await;
+            assert!(_res.is_ok());
+        }
+
+        let post = |post_id| PostItem {
+            canister_id: "test_canister_id".to_string(),
+            post_id,
+            video_id: format!("test_video_id{post_id}"),
+            nsfw_probability: 0.0,
+        };
+        let plain = |post_id| PlainPostItem {
+            canister_id: "test_canister_id".to_string(),
+            post_id,
+        };
+
+        state
+            .add_user_cache_items(&user_key, (0..5).map(post).collect())
+            .await
+            .unwrap();
+        conn.zadd::<_, _, _, ()>(&watched_key, plain(1), 1)
+            .await
+            .unwrap();
+        conn.zadd::<_, _, _, ()>(&liked_key, plain(2), 1)
+            .await
+            .unwrap();
+
+        // the shared global cache only tops up, so only the user's posts are asserted
+        let params = FeedParams {
+            variant: CacheVariant::Clean,
+            filter_results: vec![post(3)],
+            num_results: 2,
+        };
+        let res = state.next_feed(user, &params).await.unwrap();
+        assert_eq!(res.posts, [post(4), post(0)]);
+
+        let params = FeedParams {
+            num_results: 1,
+            ..params
+        };
+        let res = state.next_feed(user, &params).await.unwrap();
+        assert_eq!(res.posts, [post(4)]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs redis at ML_FEED_CACHE_REDIS_URL"]
+    async fn test_claim_user_buffer_batch() {
+        // the scripts parse both member encodings
+        for (encoding, key) in [
+            (MemberEncoding::Json, "test_buffer_key"),
+            (MemberEncoding::Compact, "test_compact_buffer_key"),
+        ] {
+            let state = MLFeedCacheState::new().await.with_member_encoding(encoding);
+
+            let mut conn = state.redis_pool.get().await.unwrap();
+            for suffix in [
+                "",
+                consts::BUFFER_IN_FLIGHT_SUFFIX,
+                consts::BUFFER_ATTEMPTS_SUFFIX,
+                consts::BUFFER_DEAD_LETTER_SUFFIX,
+            ] {
+                let _res = conn.del::<String, ()>(format!("{key}{suffix}")).await;
+                assert!(_res.is_ok());
+            }
+
+            let now = SystemTime::now();
+            let items = (0..10)
+                .map(|i| BufferItem {
+                    publisher_canister_id: "test_publisher_canister_id".to_string(),
+                    user_canister_id: "test_user_canister_id".to_string(),
+                    post_id: i,
+                    video_id: format!("test_video_id{i}"),
+                    item_type: "video_viewed".to_string(),
+                    timestamp: now - Duration::from_secs(100 - i),
+                    percent_watched: 50.0,
+                })
+                .collect::<Vec<_>>();
+            state.add_buffer_items_at(key, items.clone()).await.unwrap();
+            let post_ids =
+                |items: &[BufferItem]| items.iter().map(|item| item.post_id).collect::<Vec<_>>();
+
+            // oldest first, claimed items are not handed out twice
+            let cutoff = now.duration_since(UNIX_EPOCH).unwrap().as_secs();
+            let claimed = state.claim_buffer_batch_at(key, cutoff, 4).await.unwrap();
+            assert_eq!(post_ids(&claimed), [0, 1, 2, 3]);
+            let expired = state.claim_buffer_batch_at(key, cutoff, 4).await.unwrap();
+            assert_eq!(post_ids(&expired), [4, 5, 6, 7]);
+
+            let acked = state.ack_buffer_items_at(key, &claimed).await.unwrap();
+            assert_eq!(acked, 4);
+            let acked = state.ack_buffer_items_at(key, &claimed).await.unwrap();
+            assert_eq!(acked, 0);
+
+            let rest = state.claim_buffer_batch_at(key, cutoff, 10).await.unwrap();
+            assert_eq!(post_ids(&rest), [8, 9]);
+            state.ack_buffer_items_at(key, &rest).await.unwrap();
+
+            // never acked claims go back with their own timestamps
+            let dead = state
+                .requeue_expired_buffer_claims_at(key, now_secs() + 1)
+                .await
+                .unwrap();
+            assert_eq!(dead, 0);
+            let expired_cutoff = items[7].timestamp.duration_since(UNIX_EPOCH).unwrap();
+            let mut failing = state
+                .claim_buffer_batch_at(key, expired_cutoff.as_secs(), 10)
+                .await
+                .unwrap();
+            assert_eq!(post_ids(&failing), [4, 5, 6, 7]);
+
+            // failing repeatedly ends in the dead-letter set
+            for attempt in 2..=consts::MAX_BUFFER_ITEM_ATTEMPTS {
+                let dead = state.requeue_buffer_items_at(key, &failing).await.unwrap();
+                if attempt == consts::MAX_BUFFER_ITEM_ATTEMPTS {
+                    assert_eq!(dead, 4);
+                    break;
+                }
+                assert_eq!(dead, 0);
+                failing = state.claim_buffer_batch_at(key, cutoff, 10).await.unwrap();
+                assert_eq!(post_ids(&failing), [4, 5, 6, 7]);
+            }
+
+            let mut dead_letter = state.get_buffer_dead_letter_items_at(key).await.unwrap();
+            dead_letter.sort_by_key(|item| item.post_id);
+            assert_eq!(post_ids(&dead_letter), [4, 5, 6, 7]);
+            let buffered = conn.zcard::<_, u64>(key).await.unwrap();
+            assert_eq!(buffered, 0);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs redis at ML_FEED_CACHE_REDIS_URL"]
+    async fn test_evict_inactive_users() {
+        let state = MLFeedCacheState::new().await;
+
+        let mut conn = state.redis_pool.get().await.unwrap();
+        let user = "test_inactive_user";
+        let key = FeedKey::UserCache {
+            user: user.to_string(),
+            variant: CacheVariant::Clean,
+        };
+        let cache_key = key.to_string();
+        let _res = conn.del::<&str, ()>(&cache_key).await;
+        assert!(_res.is_ok());
+
+        let item = PostItem {
+            canister_id: "test_canister_id".to_string(),
+            post_id: 0,
+            video_id: "test_video_id0".to_string(),
+            nsfw_probability: 0.0,
+        };
+        state.add_user_cache_items(&key, vec![item]).await.unwrap();
+
+        // expiry is refreshed on write
+        let ttl = conn.ttl::<&str, i64>(&cache_key).await.unwrap();
+        assert!(ttl > 0);
+
+        // recently active users are kept
+        let day = Duration::from_secs(24 * 60 * 60);
+        state.evict_inactive_users(day).await.unwrap();
+        let exists = conn.exists::<&str, bool>(&cache_key).await.unwrap();
+        assert!(exists);
+
+        // pretend the last write was long ago
+        conn.zadd::<_, _, _, ()>(USER_LAST_ACTIVE_KEY, user, 0)
+            .await
+            .unwrap();
+        let evicted = state.evict_inactive_users(day).await.unwrap();
+        assert!(evicted >= 1);
+        let exists = conn.exists::<&str, bool>(&cache_key).await.unwrap();
+        assert!(!exists);
+        let last_active = conn
+            .zscore::<_, _, Option<f64>>(USER_LAST_ACTIVE_KEY, user)
+            .await
+            .unwrap();
+        assert!(last_active.is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs redis at ML_FEED_CACHE_REDIS_URL"]
+    async fn test_compact_member_encoding() {
+        let state = MLFeedCacheState::new()
+            .await
+            .with_member_encoding(MemberEncoding::Compact);
+
+        let mut conn = state.redis_pool.get().await.unwrap();
+        let feed_key = FeedKey::UserCache {
+            user: "test_compact_user".to_string(),
+            variant: CacheVariant::Clean,
+        };
+        let key = &feed_key.to_string();
+        let _res = conn
+            .del::<_, ()>(&[key.to_string(), encoding::meta_key(key)])
+            .await;
+        assert!(_res.is_ok());
+
+        let post = |post_id| PostItem {
+            canister_id: "ryjl3-tyaaa-aaaaa-aaaba-cai".to_string(),
+            post_id,
+            video_id: format!("test_video_id{post_id}"),
+            nsfw_probability: 0.5,
+        };
+        let video_ids = |items: Vec<PostItem>| {
+            let mut ids = items
+                .into_iter()
+                .map(|item| item.video_id)
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        let expected = video_ids((0..5).map(post).collect());
+
+        // written before switching to the compact encoding
+        conn.zadd::<_, _, _, ()>(key, post(0), 0).await.unwrap();
+        state
+            .add_user_cache_items(&feed_key, (1..5).map(post).collect())
+            .await
+            .unwrap();
+
+        // both encodings are read back
+        let items = state.get_cache_items(&feed_key, 0, 10).await.unwrap();
+        assert_eq!(video_ids(items), expected);
+
+        let migrated = migration::migrate_key(
+            &mut conn,
+            KeyFamily::UserCache,
+            key,
+            MemberEncoding::Compact,
+        )
+        .await
+        .unwrap();
+        assert_eq!(migrated, Some(5));
+        let members = conn.zrange::<_, Vec<Vec<u8>>>(key, 0, -1).await.unwrap();
+        assert!(members.iter().all(|member| encoding::is_compact(member)));
+        let items = state.get_cache_items(&feed_key, 0, 10).await.unwrap();
+        assert_eq!(video_ids(items), expected);
+
+        // already migrated keys are skipped
+        let migrated = migration::migrate_key(
+            &mut conn,
+            KeyFamily::UserCache,
+            key,
+            MemberEncoding::Compact,
+        )
+        .await
+        .unwrap();
+        assert_eq!(migrated, Some(0));
+
+        // and back to json
+        let migrated =
+            migration::migrate_key(&mut conn, KeyFamily::UserCache, key, MemberEncoding::Json)
+                .await
+                .unwrap();
+        assert_eq!(migrated, Some(5));
+        let meta_exists = conn
+            .exists::<_, bool>(encoding::meta_key(key))
+            .await
+            .unwrap();
+        assert!(!meta_exists);
+        let items = state
+            .clone()
+            .with_member_encoding(MemberEncoding::Json)
+            .get_cache_items(&feed_key, 0, 10)
+            .await
+            .unwrap();
+        assert_eq!(video_ids(items), expected);
+    }
 }
@@ -0,0 +1,189 @@
+//! Keyspace reporting and cleanup
+//!
+//! These SCAN the whole keyspace, run them from background jobs
+//! rather than request handlers
+
+use std::time::Duration;
+
+use redis::Script;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    buffer::now_secs,
+    consts::{MEMBER_META_SUFFIX, USER_LAST_ACTIVE_KEY},
+    keys::KeyFamily,
+    FeedCacheError, MLFeedCacheState, RedisConnection,
+};
+
+/// Keys fetched per SCAN call
+const SCAN_COUNT: usize = 1000;
+/// Users evicted per script call, bounds how long redis is blocked
+const EVICT_BATCH: usize = 500;
+
+/// KEYS: last_active
+/// ARGV: inactive_before, limit, meta suffix, user key suffixes...
+///
+/// per-user keys are built in the script so a user becoming active
+/// concurrently is never evicted
+const EVICT_SCRIPT: &str = r"
+local users = redis.call('ZRANGEBYSCORE', KEYS[1], 0, ARGV[1], 'LIMIT', 0, ARGV[2])
+for _, user in ipairs(users) do
+    for i = 4, #ARGV do
+        redis.call('UNLINK', user .. ARGV[i], user .. ARGV[i] .. ARGV[3])
+    end
+    redis.call('ZREM', KEYS[1], user)
+end
+return #users
+";
+
+#[derive(Serialize, Deserialize, Clone, ToSchema, Debug, PartialEq, Eq)]
+pub struct FamilyStats {
+    pub family: KeyFamily,
+    pub keys: u64,
+    /// Sum of `MEMORY USAGE` of the keys, approximate for large sets
+    pub memory_bytes: u64,
+}
+
+/// One SCAN page of keys belonging to a [`KeyFamily`]
+pub(crate) async fn scan_family_keys(
+    conn: &mut RedisConnection<'_>,
+    cursor: u64,
+) -> Result<(u64, Vec<(KeyFamily, String)>), FeedCacheError> {
+    let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+        .arg(cursor)
+        .arg("COUNT")
+        .arg(SCAN_COUNT)
+        .query_async(&mut **conn)
+        .await?;
+
+    let keys = keys
+        .into_iter()
+        .filter_map(|key| Some((KeyFamily::parse(&key)?.0, key)))
+        .collect();
+    Ok((next, keys))
+}
+
+impl MLFeedCacheState {
+    /// Number of keys and memory used by each [`KeyFamily`]
+    pub async fn key_family_stats(&self) -> Result<Vec<FamilyStats>, FeedCacheError> {
+        let mut conn = self.conn().await?;
+        let mut stats = KeyFamily::ALL.map(|family| FamilyStats {
+            family,
+            keys: 0,
+            memory_bytes: 0,
+        });
+
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = scan_family_keys(&mut conn, cursor).await?;
+            if !keys.is_empty() {
+                let mut pipe = redis::pipe();
+                for (_, key) in &keys {
+                    pipe.cmd("MEMORY").arg("USAGE").arg(key);
+                }
+                let usage: Vec<Option<u64>> = pipe.query_async(&mut *conn).await?;
+
+                for ((family, _), bytes) in keys.iter().zip(usage) {
+                    // deleted since the scan
+                    let Some(bytes) = bytes else {
+                        continue;
+                    };
+                    if let Some(stats) = stats.iter_mut().find(|s| s.family == *family) {
+                        stats.keys += 1;
+                        stats.memory_bytes += bytes;
+                    }
+                }
+            }
+
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        Ok(stats.into())
+    }
+
+    /// Set the configured ttl on family keys without one, e.g written before
+    /// ttls were configured. Returns the number of keys updated
+    pub async fn apply_family_ttls(&self) -> Result<u64, FeedCacheError> {
+        let mut conn = self.conn().await?;
+
+        let mut updated = 0;
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = scan_family_keys(&mut conn, cursor).await?;
+            let keys = keys
+                .into_iter()
+                .filter_map(|(family, key)| Some((self.ttls.get(family)?, key)))
+                .collect::<Vec<_>>();
+            if !keys.is_empty() {
+                let mut pipe = redis::pipe();
+                for (_, key) in &keys {
+                    pipe.ttl(key);
+                }
+                let remaining: Vec<i64> = pipe.query_async(&mut *conn).await?;
+
+                // -1: exists without expiry. A write in between sets the same ttl,
+                // so there is no need for a transaction
+                let missing = keys
+                    .iter()
+                    .zip(remaining)
+                    .filter(|(_, remaining)| *remaining == -1)
+                    .map(|(key, _)| key)
+                    .collect::<Vec<_>>();
+                if !missing.is_empty() {
+                    let mut pipe = redis::pipe();
+                    for (ttl, key) in &missing {
+                        pipe.expire(key, ttl.as_secs() as i64).ignore();
+                    }
+                    pipe.query_async::<()>(&mut *conn).await?;
+                    updated += missing.len() as u64;
+                }
+            }
+
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        Ok(updated)
+    }
+
+    /// Delete every per-user key of users without writes for `inactive_for`,
+    /// returns the number of users evicted
+    ///
+    /// activity is tracked in [`USER_LAST_ACTIVE_KEY`] on every per-user write,
+    /// users last written before it existed are not tracked, their keys are
+    /// only removed by expiry (see [`Self::apply_family_ttls`])
+    pub async fn evict_inactive_users(
+        &self,
+        inactive_for: Duration,
+    ) -> Result<u64, FeedCacheError> {
+        let mut conn = self.conn().await?;
+        let inactive_before = now_secs().saturating_sub(inactive_for.as_secs());
+        let script = Script::new(EVICT_SCRIPT);
+
+        let mut evicted = 0;
+        loop {
+            let mut invocation = script.key(USER_LAST_ACTIVE_KEY);
+            invocation
+                .arg(inactive_before)
+                .arg(EVICT_BATCH)
+                .arg(MEMBER_META_SUFFIX);
+            for suffix in KeyFamily::all_user_suffixes() {
+                invocation.arg(suffix);
+            }
+            let batch: u64 = invocation.invoke_async(&mut *conn).await?;
+
+            evicted += batch;
+            if batch < EVICT_BATCH as u64 {
+                break;
+            }
+        }
+
+        Ok(evicted)
+    }
+}
@@ -0,0 +1,838 @@
+//! In-memory [`FeedCache`], lets tests run without redis
+//!
+//! Sets follow redis sorted set semantics: members are compared by their redis
+//! encoding, ordered by score then member bytes, and each family is trimmed to
+//! its capacity by evicting the lowest scores
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Instant,
+};
+
+use redis::{FromRedisValue, ToRedisArgs, Value};
+
+use crate::{
+    buffer::{buffer_keys, item_score, now_secs},
+    consts::{
+        GLOBAL_CACHE, HISTORY_PLAIN_POST_ITEM_CACHE, MAX_BUFFER_CLAIM_BATCH,
+        MAX_BUFFER_ITEM_ATTEMPTS, SUCCESS_HISTORY_CACHE, USER_CACHE, USER_HOTORNOT_BUFFER_KEY,
+        WATCH_HISTORY_CACHE,
+    },
+    feed::{history_keys, sources, MIN_CANDIDATE_PAGE},
+    keys::{FeedKey, KeyFamily},
+    plain_items, score_items,
+    scoring::{DefaultScorer, HistoryScorer},
+    sorted_set::BoundedSortedSet,
+    types::{
+        BufferItem, FeedParams, FeedResponse, MLFeedCacheHistoryItem, PlainPostItem, PostItem,
+    },
+    FeedCache, FeedCacheError, KeyTtls, CACHE_FAMILIES, HISTORY_FAMILIES,
+};
+
+fn encode<T: ToRedisArgs>(item: &T) -> Vec<u8> {
+    item.to_redis_args().concat()
+}
+
+fn decode<T: FromRedisValue>(member: &[u8]) -> Result<T, FeedCacheError> {
+    Ok(T::from_redis_value(&Value::BulkString(member.to_vec()))?)
+}
+
+fn decode_all<'a, T: FromRedisValue>(
+    members: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<Vec<T>, FeedCacheError> {
+    members.into_iter().map(decode).collect()
+}
+
+/// f64 with a total order, like redis scores
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Score(f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[derive(Default)]
+struct SortedSet {
+    scores: HashMap<Vec<u8>, f64>,
+    ranked: BTreeSet<(Score, Vec<u8>)>,
+    expires_at: Option<Instant>,
+}
+
+impl SortedSet {
+    fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= Instant::now())
+    }
+
+    fn len(&self) -> u64 {
+        self.scores.len() as u64
+    }
+
+    fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    fn insert(&mut self, score: f64, member: Vec<u8>) {
+        if let Some(prev) = self.scores.insert(member.clone(), score) {
+            self.ranked.remove(&(Score(prev), member.clone()));
+        }
+        self.ranked.insert((Score(score), member));
+    }
+
+    fn remove(&mut self, member: &[u8]) -> bool {
+        let Some(score) = self.scores.remove(member) else {
+            return false;
+        };
+        self.ranked.remove(&(Score(score), member.to_vec()));
+        true
+    }
+
+    /// Keep the `capacity` highest scored members
+    fn trim(&mut self, capacity: u64) {
+        while self.len() > capacity {
+            let Some((_, member)) = self.ranked.pop_first() else {
+                break;
+            };
+            self.scores.remove(&member);
+        }
+    }
+
+    /// Members from rank `start` to `end` (inclusive), highest score first
+    fn range_rev(&self, start: u64, end: u64) -> impl Iterator<Item = &[u8]> {
+        let count = if end < start {
+            0
+        } else {
+            (end - start).saturating_add(1) as usize
+        };
+        self.ranked
+            .iter()
+            .rev()
+            .skip(start as usize)
+            .take(count)
+            .map(|(_, member)| member.as_slice())
+    }
+
+    /// Members scored between `min` and `max` (inclusive), lowest score first
+    fn range_by_score(&self, min: f64, max: f64) -> impl Iterator<Item = &[u8]> {
+        self.ranked
+            .iter()
+            .skip_while(move |(score, _)| score.0 < min)
+            .take_while(move |(score, _)| score.0 <= max)
+            .map(|(_, member)| member.as_slice())
+    }
+}
+
+#[derive(Default)]
+struct Store {
+    sets: HashMap<String, SortedSet>,
+    /// Buffer item attempt counters, by attempts key
+    attempts: HashMap<String, HashMap<Vec<u8>, u32>>,
+}
+
+impl Store {
+    fn set(&self, key: &str) -> Option<&SortedSet> {
+        self.sets.get(key).filter(|set| !set.is_expired())
+    }
+
+    fn set_mut(&mut self, key: &str) -> &mut SortedSet {
+        if self.set(key).is_none() {
+            self.sets.remove(key);
+        }
+        self.sets.entry(key.to_string()).or_default()
+    }
+
+    fn len(&self, key: &str) -> u64 {
+        self.set(key).map_or(0, SortedSet::len)
+    }
+
+    fn contains(&self, key: &str, member: &[u8]) -> bool {
+        self.set(key).and_then(|set| set.score(member)).is_some()
+    }
+
+    fn range_rev<T: FromRedisValue>(
+        &self,
+        key: &str,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<T>, FeedCacheError> {
+        self.set(key)
+            .map_or(Ok(Vec::new()), |set| decode_all(set.range_rev(start, end)))
+    }
+
+    fn range_by_score(&self, key: &str, min: f64, max: f64) -> Vec<Vec<u8>> {
+        self.set(key).map_or(Vec::new(), |set| {
+            set.range_by_score(min, max).map(<[u8]>::to_vec).collect()
+        })
+    }
+
+    /// Remove `member`, empty sets are deleted like in redis
+    fn remove(&mut self, key: &str, member: &[u8]) -> bool {
+        let Some(set) = self.sets.get_mut(key).filter(|set| !set.is_expired()) else {
+            return false;
+        };
+        let removed = set.remove(member);
+        if set.len() == 0 {
+            self.sets.remove(key);
+        }
+        removed
+    }
+
+    fn add_scored<T: ToRedisArgs>(
+        &mut self,
+        family: &BoundedSortedSet<T>,
+        key: &str,
+        items: &[(f64, T)],
+    ) {
+        if items.is_empty() {
+            return;
+        }
+        let set = self.set_mut(key);
+        for (score, item) in items {
+            set.insert(*score, encode(item));
+        }
+        set.trim(family.capacity);
+        if let Some(ttl) = family.ttl {
+            set.expires_at = Some(Instant::now() + ttl);
+        }
+    }
+
+    fn add<T: ToRedisArgs>(&mut self, family: &BoundedSortedSet<T>, key: &str, items: Vec<T>) {
+        let items = items
+            .into_iter()
+            .map(|item| ((family.score)(&item), item))
+            .collect::<Vec<_>>();
+        self.add_scored(family, key, &items);
+    }
+
+    fn clear_attempts(&mut self, key: &str, member: &[u8]) {
+        if let Some(attempts) = self.attempts.get_mut(key) {
+            attempts.remove(member);
+            if attempts.is_empty() {
+                self.attempts.remove(key);
+            }
+        }
+    }
+
+    /// Move a failed in-flight item back to the buffer or to the dead-letter set,
+    /// returns whether it was dead lettered
+    fn fail_item(&mut self, keys: &[String; 4], member: Vec<u8>, score: f64, now: f64) -> bool {
+        let [buffer, in_flight, attempts, dead_letter] = keys;
+        if !self.remove(in_flight, &member) {
+            return false;
+        }
+        let attempt = {
+            let counter = self
+                .attempts
+                .entry(attempts.clone())
+                .or_default()
+                .entry(member.clone())
+                .or_default();
+            *counter += 1;
+            *counter
+        };
+        if attempt >= MAX_BUFFER_ITEM_ATTEMPTS {
+            self.clear_attempts(attempts, &member);
+            self.set_mut(dead_letter).insert(now, member);
+            return true;
+        }
+        self.set_mut(buffer).insert(score, member);
+        false
+    }
+}
+
+/// [`FeedCache`] backed by in-process sorted sets
+///
+/// Clones share the same data. Members are always JSON, [`crate::MemberEncoding`]
+/// only affects how redis stores them
+#[derive(Clone)]
+pub struct InMemoryFeedCache {
+    store: Arc<Mutex<Store>>,
+    ttls: KeyTtls,
+    watch_history_scorer: Arc<dyn HistoryScorer>,
+    success_history_scorer: Arc<dyn HistoryScorer>,
+}
+
+impl Default for InMemoryFeedCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryFeedCache {
+    pub fn new() -> Self {
+        Self {
+            store: Arc::default(),
+            ttls: KeyTtls::default(),
+            watch_history_scorer: Arc::new(DefaultScorer),
+            success_history_scorer: Arc::new(DefaultScorer),
+        }
+    }
+
+    pub fn with_ttls(mut self, ttls: KeyTtls) -> Self {
+        self.ttls = ttls;
+        self
+    }
+
+    /// Same as [`crate::MLFeedCacheState::with_watch_history_scorer`]
+    pub fn with_watch_history_scorer(mut self, scorer: impl HistoryScorer + 'static) -> Self {
+        self.watch_history_scorer = Arc::new(scorer);
+        self
+    }
+
+    /// Same as [`crate::MLFeedCacheState::with_success_history_scorer`]
+    pub fn with_success_history_scorer(mut self, scorer: impl HistoryScorer + 'static) -> Self {
+        self.success_history_scorer = Arc::new(scorer);
+        self
+    }
+
+    fn store(&self) -> MutexGuard<'_, Store> {
+        // the store is never left half updated, a panicking test
+        // must not poison it for the others
+        self.store.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl FeedCache for InMemoryFeedCache {
+    async fn add_user_watch_history_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<MLFeedCacheHistoryItem>,
+    ) -> Result<(), FeedCacheError> {
+        let redis_key = key.expect(&[KeyFamily::WatchHistory])?;
+        let items = score_items(&*self.watch_history_scorer, items);
+        let family = key
+            .bound(WATCH_HISTORY_CACHE)
+            .with_optional_ttl(self.ttls.watch_history);
+        self.store().add_scored(&family, &redis_key, &items);
+        Ok(())
+    }
+
+    async fn add_user_success_history_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<MLFeedCacheHistoryItem>,
+    ) -> Result<(), FeedCacheError> {
+        let redis_key = key.expect(&[KeyFamily::SuccessHistory])?;
+        let items = score_items(&*self.success_history_scorer, items);
+        let family = key
+            .bound(SUCCESS_HISTORY_CACHE)
+            .with_optional_ttl(self.ttls.success_history);
+        self.store().add_scored(&family, &redis_key, &items);
+        Ok(())
+    }
+
+    async fn get_history_items(
+        &self,
+        key: &FeedKey,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<MLFeedCacheHistoryItem>, FeedCacheError> {
+        let redis_key = key.expect(HISTORY_FAMILIES)?;
+        self.store().range_rev(&redis_key, start, end)
+    }
+
+    async fn get_history_items_len(&self, key: &FeedKey) -> Result<u64, FeedCacheError> {
+        let redis_key = key.expect(HISTORY_FAMILIES)?;
+        Ok(self.store().len(&redis_key))
+    }
+
+    async fn add_user_history_plain_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<MLFeedCacheHistoryItem>,
+    ) -> Result<(), FeedCacheError> {
+        let redis_key = key.expect(&[KeyFamily::PlainHistory])?;
+        let items = plain_items(&items);
+        let family = key
+            .bound(HISTORY_PLAIN_POST_ITEM_CACHE)
+            .with_optional_ttl(self.ttls.plain_history);
+        self.store().add_scored(&family, &redis_key, &items);
+        Ok(())
+    }
+
+    async fn is_user_history_plain_item_exists(
+        &self,
+        key: &FeedKey,
+        item: PlainPostItem,
+    ) -> Result<bool, FeedCacheError> {
+        let redis_key = key.expect(&[KeyFamily::PlainHistory])?;
+        Ok(self.store().contains(&redis_key, &encode(&item)))
+    }
+
+    async fn add_user_cache_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<PostItem>,
+    ) -> Result<(), FeedCacheError> {
+        let redis_key = key.expect(&[KeyFamily::UserCache])?;
+        let family = key
+            .bound(USER_CACHE)
+            .with_optional_ttl(self.ttls.user_cache);
+        self.store().add(&family, &redis_key, items);
+        Ok(())
+    }
+
+    async fn add_global_cache_items(
+        &self,
+        key: &FeedKey,
+        items: Vec<PostItem>,
+    ) -> Result<(), FeedCacheError> {
+        let redis_key = key.expect(&[KeyFamily::GlobalCache])?;
+        let family = key
+            .bound(GLOBAL_CACHE)
+            .with_optional_ttl(self.ttls.global_cache);
+        self.store().add(&family, &redis_key, items);
+        Ok(())
+    }
+
+    async fn get_cache_items(
+        &self,
+        key: &FeedKey,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<PostItem>, FeedCacheError> {
+        let redis_key = key.expect(CACHE_FAMILIES)?;
+        self.store().range_rev(&redis_key, start, end)
+    }
+
+    async fn get_cache_items_len(&self, key: &FeedKey) -> Result<u64, FeedCacheError> {
+        let redis_key = key.expect(CACHE_FAMILIES)?;
+        Ok(self.store().len(&redis_key))
+    }
+
+    async fn next_feed(
+        &self,
+        user: &str,
+        params: &FeedParams,
+    ) -> Result<FeedResponse, FeedCacheError> {
+        let store = self.store();
+
+        // same paging as the redis implementation, so both return the same posts
+        let num_results = params.num_results as usize;
+        let page = (params.num_results as u64 * 2).max(MIN_CANDIDATE_PAGE);
+        let history_keys = history_keys(user);
+
+        let mut sources = sources(user, params.variant);
+        let mut seen = params
+            .filter_results
+            .iter()
+            .cloned()
+            .collect::<HashSet<_>>();
+        let mut posts = Vec::with_capacity(num_results);
+
+        while posts.len() < num_results && sources.iter().any(|s| !s.exhausted) {
+            let mut candidates = Vec::new();
+            for source in sources.iter_mut().filter(|s| !s.exhausted) {
+                let items: Vec<PostItem> =
+                    store.range_rev(&source.key, source.offset, source.offset + page - 1)?;
+                source.exhausted = (items.len() as u64) < page;
+                source.offset += page;
+                candidates.extend(items.into_iter().filter(|item| seen.insert(item.clone())));
+            }
+
+            let remaining = num_results - posts.len();
+            posts.extend(
+                candidates
+                    .into_iter()
+                    .filter(|item| {
+                        let plain = encode(&PlainPostItem::from(item));
+                        history_keys.iter().all(|key| !store.contains(key, &plain))
+                    })
+                    .take(remaining),
+            );
+        }
+
+        Ok(FeedResponse { posts })
+    }
+
+    async fn add_user_buffer_items(&self, items: Vec<BufferItem>) -> Result<(), FeedCacheError> {
+        if items.is_empty() {
+            return Ok(());
+        }
+        let mut store = self.store();
+        let set = store.set_mut(USER_HOTORNOT_BUFFER_KEY);
+        for item in items {
+            set.insert(item_score(&item) as f64, encode(&item));
+        }
+        Ok(())
+    }
+
+    async fn get_user_buffer_items_by_timestamp(
+        &self,
+        timestamp_secs: u64,
+    ) -> Result<Vec<BufferItem>, FeedCacheError> {
+        let members =
+            self.store()
+                .range_by_score(USER_HOTORNOT_BUFFER_KEY, 0.0, timestamp_secs as f64);
+        decode_all(members.iter().map(Vec::as_slice))
+    }
+
+    async fn remove_user_buffer_items_by_timestamp(
+        &self,
+        timestamp_secs: u64,
+    ) -> Result<u64, FeedCacheError> {
+        let mut store = self.store();
+        let members = store.range_by_score(USER_HOTORNOT_BUFFER_KEY, 0.0, timestamp_secs as f64);
+        for member in &members {
+            store.remove(USER_HOTORNOT_BUFFER_KEY, member);
+        }
+        Ok(members.len() as u64)
+    }
+
+    async fn claim_user_buffer_batch(
+        &self,
+        cutoff_secs: u64,
+        limit: usize,
+    ) -> Result<Vec<BufferItem>, FeedCacheError> {
+        let [buffer, in_flight, ..] = buffer_keys(USER_HOTORNOT_BUFFER_KEY);
+        let mut store = self.store();
+
+        let mut members = store.range_by_score(&buffer, 0.0, cutoff_secs as f64);
+        members.truncate(limit.min(MAX_BUFFER_CLAIM_BATCH));
+        let now = now_secs() as f64;
+        for member in &members {
+            store.remove(&buffer, member);
+            store.set_mut(&in_flight).insert(now, member.clone());
+        }
+
+        decode_all(members.iter().map(Vec::as_slice))
+    }
+
+    async fn ack_user_buffer_items(&self, items: &[BufferItem]) -> Result<u64, FeedCacheError> {
+        let [_, in_flight, attempts, _] = buffer_keys(USER_HOTORNOT_BUFFER_KEY);
+        let mut store = self.store();
+
+        let mut acked = 0;
+        for item in items {
+            let member = encode(item);
+            if store.remove(&in_flight, &member) {
+                acked += 1;
+            }
+            store.clear_attempts(&attempts, &member);
+        }
+        Ok(acked)
+    }
+
+    async fn requeue_user_buffer_items(&self, items: &[BufferItem]) -> Result<u64, FeedCacheError> {
+        let keys = buffer_keys(USER_HOTORNOT_BUFFER_KEY);
+        let mut store = self.store();
+
+        let now = now_secs() as f64;
+        let dead = items
+            .iter()
+            .filter(|item| store.fail_item(&keys, encode(*item), item_score(item) as f64, now))
+            .count();
+        Ok(dead as u64)
+    }
+
+    async fn requeue_expired_user_buffer_claims(
+        &self,
+        claimed_before_secs: u64,
+    ) -> Result<u64, FeedCacheError> {
+        let keys = buffer_keys(USER_HOTORNOT_BUFFER_KEY);
+        let mut store = self.store();
+
+        let now = now_secs() as f64;
+        let members = store.range_by_score(&keys[1], 0.0, claimed_before_secs as f64);
+        let mut dead = 0;
+        for member in members {
+            // requeue with the item's own timestamp, falling back to now
+            let score = decode::<BufferItem>(&member).map_or(now, |item| item_score(&item) as f64);
+            if store.fail_item(&keys, member, score, now) {
+                dead += 1;
+            }
+        }
+        Ok(dead)
+    }
+
+    async fn get_user_buffer_dead_letter_items(&self) -> Result<Vec<BufferItem>, FeedCacheError> {
+        let [.., dead_letter] = buffer_keys(USER_HOTORNOT_BUFFER_KEY);
+        let members = self
+            .store()
+            .range_by_score(&dead_letter, f64::NEG_INFINITY, f64::INFINITY);
+        decode_all(members.iter().map(Vec::as_slice))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::{
+        consts::{
+            MAX_HISTORY_PLAIN_POST_ITEM_CACHE_LEN, MAX_SUCCESS_HISTORY_CACHE_LEN,
+            MAX_WATCH_HISTORY_CACHE_LEN,
+        },
+        types::CacheVariant,
+    };
+
+    fn history_item(i: u64) -> MLFeedCacheHistoryItem {
+        MLFeedCacheHistoryItem {
+            video_id: format!("test_video_id{i}"),
+            item_type: "video_viewed".to_string(),
+            canister_id: "test_canister_id".to_string(),
+            post_id: i,
+            nsfw_probability: 0.0,
+            timestamp: SystemTime::now() + Duration::from_secs(i * 100),
+            percent_watched: 0.0,
+        }
+    }
+
+    fn post(post_id: u64) -> PostItem {
+        PostItem {
+            canister_id: "test_canister_id".to_string(),
+            post_id,
+            video_id: format!("test_video_id{post_id}"),
+            nsfw_probability: 0.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_history_trimmed_to_capacity() {
+        let cache = InMemoryFeedCache::new();
+        let key = FeedKey::UserWatchHistory {
+            user: "test_user".to_string(),
+            nsfw: false,
+        };
+
+        let items = (0..MAX_WATCH_HISTORY_CACHE_LEN + 10)
+            .map(history_item)
+            .collect::<Vec<_>>();
+        cache
+            .add_user_watch_history_items(&key, items)
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.get_history_items_len(&key).await.unwrap(),
+            MAX_WATCH_HISTORY_CACHE_LEN
+        );
+
+        // newest first, the 10 oldest evicted
+        let items = cache.get_history_items(&key, 0, 4).await.unwrap();
+        let post_ids = items.iter().map(|item| item.post_id).collect::<Vec<_>>();
+        let newest = MAX_WATCH_HISTORY_CACHE_LEN + 9;
+        assert_eq!(post_ids, (newest - 4..=newest).rev().collect::<Vec<_>>());
+
+        let oldest = cache
+            .get_history_items(
+                &key,
+                MAX_WATCH_HISTORY_CACHE_LEN - 1,
+                MAX_WATCH_HISTORY_CACHE_LEN - 1,
+            )
+            .await
+            .unwrap();
+        assert_eq!(oldest[0].post_id, 10);
+    }
+
+    #[tokio::test]
+    async fn test_success_history_trimmed_to_capacity() {
+        let cache = InMemoryFeedCache::new();
+        let key = FeedKey::UserSuccessHistory {
+            user: "test_user".to_string(),
+            nsfw: false,
+        };
+
+        let items = (0..MAX_SUCCESS_HISTORY_CACHE_LEN + 100)
+            .map(|i| MLFeedCacheHistoryItem {
+                item_type: "like_video".to_string(),
+                ..history_item(i)
+            })
+            .collect::<Vec<_>>();
+        cache
+            .add_user_success_history_items(&key, items)
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.get_history_items_len(&key).await.unwrap(),
+            MAX_SUCCESS_HISTORY_CACHE_LEN
+        );
+
+        let items = cache.get_history_items(&key, 0, 4).await.unwrap();
+        let post_ids = items.iter().map(|item| item.post_id).collect::<Vec<_>>();
+        let newest = MAX_SUCCESS_HISTORY_CACHE_LEN + 99;
+        assert_eq!(post_ids, (newest - 4..=newest).rev().collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_plain_history_item_exists() {
+        let cache = InMemoryFeedCache::new();
+        let key = FeedKey::PlainWatch {
+            user: "test_user".to_string(),
+        };
+        let plain = |post_id| PlainPostItem {
+            canister_id: "test_canister_id".to_string(),
+            post_id,
+        };
+
+        let items = (0..MAX_HISTORY_PLAIN_POST_ITEM_CACHE_LEN + 10)
+            .map(history_item)
+            .collect::<Vec<_>>();
+        cache
+            .add_user_history_plain_items(&key, items)
+            .await
+            .unwrap();
+
+        let newest = MAX_HISTORY_PLAIN_POST_ITEM_CACHE_LEN + 9;
+        for (post_id, exists) in [(newest, true), (10, true), (9, false), (newest + 1, false)] {
+            assert_eq!(
+                cache
+                    .is_user_history_plain_item_exists(&key, plain(post_id))
+                    .await
+                    .unwrap(),
+                exists,
+                "post {post_id}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_key() {
+        let cache = InMemoryFeedCache::new();
+        let key = FeedKey::PlainWatch {
+            user: "test_user".to_string(),
+        };
+
+        let res = cache
+            .add_user_success_history_items(&key, vec![history_item(0)])
+            .await;
+        assert!(matches!(res, Err(FeedCacheError::UnexpectedKey(k)) if k == key));
+    }
+
+    #[tokio::test]
+    async fn test_next_feed() {
+        let cache = InMemoryFeedCache::new();
+        let user = "test_feed_user";
+        let post_ids = |res: FeedResponse| {
+            res.posts
+                .iter()
+                .map(|post| post.post_id)
+                .collect::<Vec<_>>()
+        };
+
+        cache
+            .add_user_cache_items(
+                &FeedKey::UserCache {
+                    user: user.to_string(),
+                    variant: CacheVariant::Clean,
+                },
+                (0..5).map(post).collect(),
+            )
+            .await
+            .unwrap();
+        // post 4 is in both caches
+        cache
+            .add_global_cache_items(
+                &FeedKey::GlobalCache {
+                    variant: CacheVariant::Clean,
+                },
+                [4, 10, 11, 12].map(post).to_vec(),
+            )
+            .await
+            .unwrap();
+        cache
+            .add_user_history_plain_items(
+                &FeedKey::PlainWatch {
+                    user: user.to_string(),
+                },
+                vec![history_item(1)],
+            )
+            .await
+            .unwrap();
+        cache
+            .add_user_history_plain_items(
+                &FeedKey::PlainLike {
+                    user: user.to_string(),
+                },
+                vec![history_item(2)],
+            )
+            .await
+            .unwrap();
+
+        // newest first, the user's cache before the global one
+        let params = FeedParams {
+            variant: CacheVariant::Clean,
+            filter_results: vec![post(3)],
+            num_results: 20,
+        };
+        let res = cache.next_feed(user, &params).await.unwrap();
+        assert_eq!(post_ids(res), [4, 0, 12, 11, 10]);
+
+        let params = FeedParams {
+            num_results: 3,
+            ..params
+        };
+        let res = cache.next_feed(user, &params).await.unwrap();
+        assert_eq!(post_ids(res), [4, 0, 12]);
+
+        // other variants have their own caches
+        let params = FeedParams {
+            variant: CacheVariant::Nsfw,
+            ..params
+        };
+        let res = cache.next_feed(user, &params).await.unwrap();
+        assert!(res.posts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_claim_user_buffer_batch() {
+        let cache = InMemoryFeedCache::new();
+        let now = SystemTime::now();
+        let items = (0..10)
+            .map(|i| BufferItem {
+                publisher_canister_id: "test_publisher_canister_id".to_string(),
+                user_canister_id: "test_user_canister_id".to_string(),
+                post_id: i,
+                video_id: format!("test_video_id{i}"),
+                item_type: "video_viewed".to_string(),
+                timestamp: now - Duration::from_secs(100 - i),
+                percent_watched: 50.0,
+            })
+            .collect::<Vec<_>>();
+        cache.add_user_buffer_items(items).await.unwrap();
+
+        let cutoff = now_secs();
+        let claimed = cache.claim_user_buffer_batch(cutoff, 4).await.unwrap();
+        let post_ids = claimed.iter().map(|item| item.post_id).collect::<Vec<_>>();
+        assert_eq!(post_ids, [0, 1, 2, 3]);
+
+        assert_eq!(cache.ack_user_buffer_items(&claimed).await.unwrap(), 4);
+        // acking twice is a no-op
+        assert_eq!(cache.ack_user_buffer_items(&claimed).await.unwrap(), 0);
+
+        let mut failing = cache.claim_user_buffer_batch(cutoff, 10).await.unwrap();
+        assert_eq!(failing.len(), 6);
+        for _ in 1..MAX_BUFFER_ITEM_ATTEMPTS {
+            assert_eq!(cache.requeue_user_buffer_items(&failing).await.unwrap(), 0);
+            failing = cache.claim_user_buffer_batch(cutoff, 10).await.unwrap();
+            assert_eq!(failing.len(), 6);
+        }
+
+        // unacked claims expire into the dead-letter set on the last attempt
+        assert_eq!(
+            cache
+                .requeue_expired_user_buffer_claims(now_secs())
+                .await
+                .unwrap(),
+            6
+        );
+        let dead_letter = cache.get_user_buffer_dead_letter_items().await.unwrap();
+        assert_eq!(dead_letter.len(), 6);
+        assert!(cache
+            .claim_user_buffer_batch(cutoff, 10)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+//! Rewriting existing keys into another [`MemberEncoding`]
+//!
+//! Each key is rewritten in a WATCH / MULTI transaction, so writes made while
+//! a key is migrated are never lost, the key is retried instead
+
+use std::collections::HashMap;
+
+use redis::{FromRedisValue, ToRedisArgs};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    consts::{BUFFER_ATTEMPTS_SUFFIX, MEMBER_META_SUFFIX},
+    encoding::{decode, encode, encode_member, is_compact, meta_key, CompactMember},
+    keys::KeyFamily,
+    maintenance::scan_family_keys,
+    types::{BufferItem, MLFeedCacheHistoryItem, PlainPostItem, PostItem},
+    FeedCacheError, MLFeedCacheState, MemberEncoding, RedisConnection,
+};
+
+/// Attempts at rewriting a key written to concurrently
+const MIGRATION_RETRIES: usize = 3;
+/// Members sent per ZADD / HSET
+const WRITE_CHUNK_SIZE: usize = 1000;
+
+/// Metadata hash of a compact set, keyed by member
+type MemberMetas = HashMap<Vec<u8>, Vec<u8>>;
+
+#[derive(Serialize, Deserialize, Clone, ToSchema, Debug, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Keys rewritten, keys already in the target encoding are not counted
+    pub keys: u64,
+    /// Members (or attempt counters) rewritten
+    pub members: u64,
+}
+
+/// Send UNWATCH if a transaction was abandoned on an error
+///
+/// the connection goes back to the pool, watched keys left on it would make
+/// the next transaction run on it fail
+async fn unwatch_on_error<T>(
+    conn: &mut RedisConnection<'_>,
+    res: Result<T, FeedCacheError>,
+) -> Result<T, FeedCacheError> {
+    if res.is_err() {
+        // the original error is the one worth reporting
+        let _ = redis::cmd("UNWATCH").query_async::<()>(&mut **conn).await;
+    }
+    res
+}
+
+/// Rewrite the sorted set at `key`, `None` if it changed before the rewrite
+async fn migrate_sorted_set<T>(
+    conn: &mut RedisConnection<'_>,
+    key: &str,
+    to: MemberEncoding,
+) -> Result<Option<u64>, FeedCacheError>
+where
+    T: CompactMember + ToRedisArgs + FromRedisValue,
+{
+    let meta = meta_key(key);
+    redis::cmd("WATCH")
+        .arg(key)
+        .arg(&meta)
+        .query_async::<()>(&mut **conn)
+        .await?;
+
+    let res = rewrite_sorted_set::<T>(conn, key, &meta, to).await;
+    unwatch_on_error(conn, res).await
+}
+
+/// [`migrate_sorted_set`] once `key` and its metadata `meta` are watched
+async fn rewrite_sorted_set<T>(
+    conn: &mut RedisConnection<'_>,
+    key: &str,
+    meta: &str,
+    to: MemberEncoding,
+) -> Result<Option<u64>, FeedCacheError>
+where
+    T: CompactMember + ToRedisArgs + FromRedisValue,
+{
+    // ascending, so the highest score wins when members collapse
+    let (members, metas, pttl): (Vec<(Vec<u8>, f64)>, MemberMetas, i64) = redis::pipe()
+        .zrange_withscores(key, 0, -1)
+        .hgetall(meta)
+        .pttl(key)
+        .query_async(&mut **conn)
+        .await?;
+
+    let migrated = members
+        .iter()
+        .all(|(member, _)| is_compact(member) == (to == MemberEncoding::Compact));
+    if migrated && (to == MemberEncoding::Compact || metas.is_empty()) {
+        redis::cmd("UNWATCH").query_async::<()>(&mut **conn).await?;
+        return Ok(Some(0));
+    }
+
+    let mut scored = Vec::with_capacity(members.len());
+    let mut new_metas = Vec::new();
+    for (member, score) in &members {
+        let item: T = decode(member, metas.get(member).map_or(&[], Vec::as_slice))?;
+        let (member, meta) = encode(to, &item);
+        if !meta.is_empty() {
+            new_metas.push((member.clone(), meta));
+        }
+        scored.push((*score, member));
+    }
+
+    let mut pipe = redis::pipe();
+    pipe.atomic().del(&[key, meta]).ignore();
+    for chunk in scored.chunks(WRITE_CHUNK_SIZE) {
+        pipe.zadd_multiple(key, chunk).ignore();
+    }
+    for chunk in new_metas.chunks(WRITE_CHUNK_SIZE) {
+        pipe.hset_multiple(meta, chunk).ignore();
+    }
+    if pttl > 0 {
+        pipe.pexpire(key, pttl).ignore();
+        if !new_metas.is_empty() {
+            pipe.pexpire(meta, pttl).ignore();
+        }
+    }
+
+    let res: Option<()> = pipe.query_async(&mut **conn).await?;
+    Ok(res.map(|()| members.len() as u64))
+}
+
+/// Rewrite the fields of the buffer attempts hash at `key`
+async fn migrate_attempts(
+    conn: &mut RedisConnection<'_>,
+    key: &str,
+    to: MemberEncoding,
+) -> Result<Option<u64>, FeedCacheError> {
+    redis::cmd("WATCH")
+        .arg(key)
+        .query_async::<()>(&mut **conn)
+        .await?;
+
+    let res = rewrite_attempts(conn, key, to).await;
+    unwatch_on_error(conn, res).await
+}
+
+/// [`migrate_attempts`] once `key` is watched
+async fn rewrite_attempts(
+    conn: &mut RedisConnection<'_>,
+    key: &str,
+    to: MemberEncoding,
+) -> Result<Option<u64>, FeedCacheError> {
+    let fields: HashMap<Vec<u8>, u64> = redis::cmd("HGETALL")
+        .arg(key)
+        .query_async(&mut **conn)
+        .await?;
+    if fields
+        .iter()
+        .all(|(field, _)| is_compact(field) == (to == MemberEncoding::Compact))
+    {
+        redis::cmd("UNWATCH").query_async::<()>(&mut **conn).await?;
+        return Ok(Some(0));
+    }
+
+    let mut attempts = HashMap::new();
+    for (field, count) in &fields {
+        let item: BufferItem = decode(field, &[])?;
+        let entry = attempts.entry(encode_member(to, &item)).or_insert(0);
+        *entry = (*entry).max(*count);
+    }
+    let attempts = attempts.into_iter().collect::<Vec<_>>();
+
+    let mut pipe = redis::pipe();
+    pipe.atomic().del(key).ignore();
+    for chunk in attempts.chunks(WRITE_CHUNK_SIZE) {
+        pipe.hset_multiple(key, chunk).ignore();
+    }
+
+    let res: Option<()> = pipe.query_async(&mut **conn).await?;
+    Ok(res.map(|()| fields.len() as u64))
+}
+
+pub(crate) async fn migrate_key(
+    conn: &mut RedisConnection<'_>,
+    family: KeyFamily,
+    key: &str,
+    to: MemberEncoding,
+) -> Result<Option<u64>, FeedCacheError> {
+    match family {
+        KeyFamily::UserCache | KeyFamily::GlobalCache => {
+            migrate_sorted_set::<PostItem>(conn, key, to).await
+        }
+        KeyFamily::WatchHistory | KeyFamily::SuccessHistory => {
+            migrate_sorted_set::<MLFeedCacheHistoryItem>(conn, key, to).await
+        }
+        KeyFamily::PlainHistory => migrate_sorted_set::<PlainPostItem>(conn, key, to).await,
+        KeyFamily::HotOrNotBuffer if key.ends_with(BUFFER_ATTEMPTS_SUFFIX) => {
+            migrate_attempts(conn, key, to).await
+        }
+        KeyFamily::HotOrNotBuffer => migrate_sorted_set::<BufferItem>(conn, key, to).await,
+    }
+}
+
+impl MLFeedCacheState {
+    /// Rewrite the members of every family key into `to`
+    ///
+    /// safe to run while serving, as long as reads can decode both encodings,
+    /// i.e the state runs with [`MemberEncoding::Compact`]. Switch the encoding
+    /// first when migrating to compact and only after the migration when going
+    /// back to JSON, otherwise writes made in between are left in the old encoding.
+    /// Items claimed from the buffer while it is migrated may lose their attempt count
+    pub async fn migrate_member_encoding(
+        &self,
+        to: MemberEncoding,
+    ) -> Result<MigrationReport, FeedCacheError> {
+        let mut conn = self.conn().await?;
+        let mut report = MigrationReport::default();
+
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = scan_family_keys(&mut conn, cursor).await?;
+            // metadata is rewritten along with its set
+            for (family, key) in keys
+                .iter()
+                .filter(|(_, key)| !key.ends_with(MEMBER_META_SUFFIX))
+            {
+                let mut attempt = 0;
+                let members = loop {
+                    if let Some(members) = migrate_key(&mut conn, *family, key, to).await? {
+                        break members;
+                    }
+                    attempt += 1;
+                    if attempt == MIGRATION_RETRIES {
+                        return Err(FeedCacheError::MigrationConflict(key.clone()));
+                    }
+                };
+                if members > 0 {
+                    report.keys += 1;
+                    report.members += members;
+                }
+            }
+
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,153 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::types::MLFeedCacheHistoryItem;
+
+/// Ranks history items within their sorted set, higher scores are kept longer
+/// and returned first
+///
+/// Scores are computed once on insert, so they must not depend on the current time
+pub trait HistoryScorer: Send + Sync {
+    fn score(&self, item: &MLFeedCacheHistoryItem) -> f64;
+}
+
+fn timestamp_secs(item: &MLFeedCacheHistoryItem) -> f64 {
+    item.timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as f64
+}
+
+/// [`crate::types::get_history_item_score`], timestamp with small boosts for
+/// likes and watch time
+///
+/// same as [`WeightedEventScorer::default`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultScorer;
+
+impl HistoryScorer for DefaultScorer {
+    fn score(&self, item: &MLFeedCacheHistoryItem) -> f64 {
+        WeightedEventScorer::default().score(item)
+    }
+}
+
+/// Timestamp plus a weight per event type and for watch completion
+#[derive(Clone, Copy, Debug)]
+pub struct WeightedEventScorer {
+    pub like: f64,
+    pub share: f64,
+    /// Multiplied by `percent_watched` in `f32`, as scores stored before
+    /// scorers existed were
+    pub watch_completion: f64,
+}
+
+impl Default for WeightedEventScorer {
+    /// Same weights as [`DefaultScorer`]
+    fn default() -> Self {
+        Self {
+            like: 100.0,
+            share: 0.0,
+            watch_completion: 100.0,
+        }
+    }
+}
+
+impl WeightedEventScorer {
+    fn event(&self, item: &MLFeedCacheHistoryItem) -> f64 {
+        match item.item_type.as_str() {
+            "like_video" => self.like,
+            "share_video" => self.share,
+            _ => 0.0,
+        }
+    }
+
+    fn watch(&self, item: &MLFeedCacheHistoryItem) -> f64 {
+        (item.percent_watched * self.watch_completion as f32) as f64
+    }
+
+    fn event_weight(&self, item: &MLFeedCacheHistoryItem) -> f64 {
+        self.event(item) + self.watch(item)
+    }
+}
+
+impl HistoryScorer for WeightedEventScorer {
+    fn score(&self, item: &MLFeedCacheHistoryItem) -> f64 {
+        // summed in this order so default weights reproduce stored scores bit for bit
+        timestamp_secs(item) + self.event(item) + self.watch(item)
+    }
+}
+
+/// Engagement weight decaying exponentially with age
+///
+/// Scores are stored in log space (`ln(weight) + age_in_half_lives * ln 2`),
+/// which orders items the same as `weight * 2^(-age / half_life)` at any point in time
+#[derive(Clone, Copy, Debug)]
+pub struct TimeDecayScorer {
+    pub half_life: Duration,
+    pub weights: WeightedEventScorer,
+}
+
+impl Default for TimeDecayScorer {
+    fn default() -> Self {
+        Self {
+            half_life: Duration::from_secs(24 * 60 * 60),
+            weights: WeightedEventScorer::default(),
+        }
+    }
+}
+
+impl HistoryScorer for TimeDecayScorer {
+    fn score(&self, item: &MLFeedCacheHistoryItem) -> f64 {
+        let half_lives = timestamp_secs(item) / self.half_life.as_secs_f64();
+        let weight = 1.0 + self.weights.event_weight(item).max(0.0);
+        weight.ln() + half_lives * std::f64::consts::LN_2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::types::get_history_item_score;
+
+    fn item(item_type: &str, age: Duration, percent_watched: f32) -> MLFeedCacheHistoryItem {
+        MLFeedCacheHistoryItem {
+            canister_id: "test_canister_id".to_string(),
+            post_id: 0,
+            video_id: "test_video_id".to_string(),
+            nsfw_probability: 0.0,
+            item_type: item_type.to_string(),
+            timestamp: SystemTime::now() - age,
+            percent_watched,
+        }
+    }
+
+    #[test]
+    fn test_weighted_event_scorer_matches_default() {
+        let scorer = WeightedEventScorer::default();
+        for item in [
+            item("like_video", Duration::ZERO, 0.5),
+            item("video_viewed", Duration::from_secs(60), 0.9),
+            item("share_video", Duration::from_secs(3600), 0.33),
+        ] {
+            assert_eq!(scorer.score(&item), get_history_item_score(&item));
+            assert_eq!(scorer.score(&item), DefaultScorer.score(&item));
+        }
+    }
+
+    #[test]
+    fn test_time_decay_scorer() {
+        let scorer = TimeDecayScorer::default();
+        let day = Duration::from_secs(24 * 60 * 60);
+
+        // a fresh view beats a week old like
+        let old_like = item("like_video", day * 7, 1.0);
+        let new_view = item("video_viewed", Duration::ZERO, 0.5);
+        assert!(scorer.score(&new_view) > scorer.score(&old_like));
+
+        // at equal age more engagement wins
+        let like = item("like_video", day, 1.0);
+        let view = item("video_viewed", day, 1.0);
+        assert!(scorer.score(&like) > scorer.score(&view));
+    }
+}
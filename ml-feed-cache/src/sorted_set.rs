@@ -0,0 +1,336 @@
+use std::{
+    marker::PhantomData,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use redis::{
+    aio::ConnectionLike, AsyncCommands, ErrorKind, FromRedisValue, Pipeline, RedisResult, Script,
+    ToRedisArgs,
+};
+
+use crate::{
+    encoding::{decode, encode, meta_key, CompactMember},
+    MemberEncoding,
+};
+
+/// Max members sent in a single ZADD
+const ZADD_CHUNK_SIZE: usize = 1000;
+
+/// KEYS: set, meta
+/// ARGV: capacity, ttl_secs (0: no expiry), (score, member, meta)...
+const ADD_COMPACT_SCRIPT: &str = r"
+local capacity = tonumber(ARGV[1])
+local ttl = tonumber(ARGV[2])
+for i = 3, #ARGV, 3 do
+    redis.call('ZADD', KEYS[1], ARGV[i], ARGV[i + 1])
+    if ARGV[i + 2] ~= '' then
+        redis.call('HSET', KEYS[2], ARGV[i + 1], ARGV[i + 2])
+    end
+end
+-- evict the lowest scored members along with their metadata
+local evicted = redis.call('ZRANGE', KEYS[1], 0, -capacity - 1)
+if #evicted > 0 then
+    redis.call('ZREMRANGEBYRANK', KEYS[1], 0, -capacity - 1)
+    for i = 1, #evicted, 1000 do
+        redis.call('HDEL', KEYS[2], unpack(evicted, i, math.min(i + 999, #evicted)))
+    end
+end
+if ttl > 0 then
+    redis.call('EXPIRE', KEYS[1], ttl)
+    redis.call('EXPIRE', KEYS[2], ttl)
+end
+";
+
+/// KEYS: set, meta
+/// ARGV: start, stop
+/// Returns member, meta, member, meta, ... highest score first, meta is empty when missing
+const RANGE_REV_COMPACT_SCRIPT: &str = r"
+local members = redis.call('ZREVRANGE', KEYS[1], ARGV[1], ARGV[2])
+local res = {}
+for i = 1, #members, 1000 do
+    local chunk = {unpack(members, i, math.min(i + 999, #members))}
+    local metas = redis.call('HMGET', KEYS[2], unpack(chunk))
+    for j, member in ipairs(chunk) do
+        res[#res + 1] = member
+        res[#res + 1] = metas[j] or ''
+    end
+end
+return res
+";
+
+/// Decode the reply of [`BoundedSortedSet::pipe_range_rev_compact`]
+pub(crate) fn decode_compact_range<T>(reply: &[Vec<u8>]) -> RedisResult<Vec<T>>
+where
+    T: CompactMember + FromRedisValue,
+{
+    reply
+        .chunks(2)
+        .map(|pair| match pair {
+            [member, meta] => decode(member, meta),
+            _ => Err((
+                ErrorKind::TypeError,
+                "odd number of elements in range reply",
+            )
+                .into()),
+        })
+        .collect()
+}
+
+/// A family of redis sorted sets holding at most `capacity` members of `T`
+///
+/// Members with the lowest scores are evicted first
+pub struct BoundedSortedSet<T> {
+    pub capacity: u64,
+    pub score: fn(&T) -> f64,
+    /// Expire the whole set after this long without writes
+    pub ttl: Option<Duration>,
+    _member: PhantomData<fn(T) -> T>,
+}
+
+impl<T> Clone for BoundedSortedSet<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for BoundedSortedSet<T> {}
+
+impl<T> BoundedSortedSet<T> {
+    pub const fn new(capacity: u64, score: fn(&T) -> f64) -> Self {
+        Self {
+            capacity,
+            score,
+            ttl: None,
+            _member: PhantomData,
+        }
+    }
+
+    pub const fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub const fn with_capacity(mut self, capacity: u64) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Replace [`Self::ttl`], `None` never expires
+    pub const fn with_optional_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+}
+
+impl<T: ToRedisArgs + FromRedisValue + Send + Sync> BoundedSortedSet<T> {
+    /// Add `items` scored by [`Self::score`], then trim to capacity
+    pub async fn add<C>(&self, conn: &mut C, key: &str, items: &[T]) -> RedisResult<()>
+    where
+        C: ConnectionLike + Send,
+    {
+        let items = items
+            .iter()
+            .map(|item| ((self.score)(item), item))
+            .collect::<Vec<_>>();
+
+        self.zadd_and_trim(conn, key, &items).await
+    }
+
+    /// Add already scored `items`, then trim to capacity
+    pub async fn add_scored<C>(
+        &self,
+        conn: &mut C,
+        key: &str,
+        items: &[(f64, T)],
+    ) -> RedisResult<()>
+    where
+        C: ConnectionLike + Send,
+    {
+        self.zadd_and_trim(conn, key, items).await
+    }
+
+    /// Queue adding `items` scored by [`Self::score`] and trimming to capacity on `pipe`,
+    /// for writes that must happen in the same transaction
+    pub fn pipe_add(&self, pipe: &mut Pipeline, key: &str, items: &[T]) {
+        let items = items
+            .iter()
+            .map(|item| ((self.score)(item), item))
+            .collect::<Vec<_>>();
+
+        self.queue_zadd_and_trim(pipe, key, &items);
+    }
+
+    /// Same as [`Self::pipe_add`] for already scored `items`
+    pub fn pipe_add_scored(&self, pipe: &mut Pipeline, key: &str, items: &[(f64, T)]) {
+        self.queue_zadd_and_trim(pipe, key, items);
+    }
+
+    async fn zadd_and_trim<C, M>(
+        &self,
+        conn: &mut C,
+        key: &str,
+        items: &[(f64, M)],
+    ) -> RedisResult<()>
+    where
+        C: ConnectionLike + Send,
+        M: ToRedisArgs + Send + Sync,
+    {
+        // a single MULTI/EXEC round trip, concurrent writers never
+        // observe the set between the add and the trim
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        self.queue_zadd_and_trim(&mut pipe, key, items);
+
+        pipe.query_async(conn).await
+    }
+
+    fn queue_zadd_and_trim<M: ToRedisArgs>(
+        &self,
+        pipe: &mut Pipeline,
+        key: &str,
+        items: &[(f64, M)],
+    ) {
+        for chunk in items.chunks(ZADD_CHUNK_SIZE) {
+            pipe.zadd_multiple(key, chunk).ignore();
+        }
+        // keep the `capacity` highest scored members
+        pipe.zremrangebyrank(key, 0, -(self.capacity as isize) - 1)
+            .ignore();
+        // refreshed on every write, so only sets left untouched expire
+        if let Some(ttl) = self.ttl {
+            pipe.expire(key, ttl.as_secs() as i64).ignore();
+        }
+    }
+
+    /// Members from rank `start` to `end` (inclusive), highest score first
+    pub async fn range_rev<C>(
+        &self,
+        conn: &mut C,
+        key: &str,
+        start: u64,
+        end: u64,
+    ) -> RedisResult<Vec<T>>
+    where
+        C: ConnectionLike + Send + Sync,
+    {
+        conn.zrevrange(key, start as isize, end as isize).await
+    }
+
+    pub async fn len<C>(&self, conn: &mut C, key: &str) -> RedisResult<u64>
+    where
+        C: ConnectionLike + Send + Sync,
+    {
+        conn.zcard(key).await
+    }
+
+    pub async fn contains<C>(&self, conn: &mut C, key: &str, item: &T) -> RedisResult<bool>
+    where
+        C: ConnectionLike + Send + Sync,
+    {
+        let score: Option<f64> = conn.zscore(key, item).await?;
+        Ok(score.is_some())
+    }
+}
+
+/// Operations on sets using [`MemberEncoding::Compact`], the metadata of
+/// members lives in a hash next to the set (see [`meta_key`])
+///
+/// `CompactMember` bounds the methods rather than the impl, it is crate private
+impl<T> BoundedSortedSet<T>
+where
+    T: ToRedisArgs + FromRedisValue + Send + Sync,
+{
+    /// Same as [`Self::pipe_add`] for compact members
+    pub(crate) fn pipe_add_compact(&self, pipe: &mut Pipeline, key: &str, items: &[T])
+    where
+        T: CompactMember,
+    {
+        let items = items
+            .iter()
+            .map(|item| ((self.score)(item), item))
+            .collect::<Vec<_>>();
+
+        self.queue_add_compact(pipe, key, &items);
+    }
+
+    /// Same as [`Self::pipe_add_scored`] for compact members
+    pub(crate) fn pipe_add_compact_scored(&self, pipe: &mut Pipeline, key: &str, items: &[(f64, T)])
+    where
+        T: CompactMember,
+    {
+        let items = items
+            .iter()
+            .map(|(score, item)| (*score, item))
+            .collect::<Vec<_>>();
+
+        self.queue_add_compact(pipe, key, &items);
+    }
+
+    fn queue_add_compact(&self, pipe: &mut Pipeline, key: &str, items: &[(f64, &T)])
+    where
+        T: CompactMember,
+    {
+        // EVAL rather than EVALSHA, the script may not be loaded inside a transaction
+        pipe.cmd("EVAL")
+            .arg(ADD_COMPACT_SCRIPT)
+            .arg(2)
+            .arg(key)
+            .arg(meta_key(key))
+            .arg(self.capacity)
+            .arg(self.ttl.map_or(0, |ttl| ttl.as_secs()));
+        for (score, item) in items {
+            let (member, meta) = encode(MemberEncoding::Compact, *item);
+            pipe.arg(*score).arg(member).arg(meta);
+        }
+        pipe.ignore();
+    }
+
+    /// Same as [`Self::range_rev`], also decodes JSON members
+    pub(crate) async fn range_rev_compact<C>(
+        &self,
+        conn: &mut C,
+        key: &str,
+        start: u64,
+        end: u64,
+    ) -> RedisResult<Vec<T>>
+    where
+        T: CompactMember,
+        C: ConnectionLike + Send,
+    {
+        let reply: Vec<Vec<u8>> = Script::new(RANGE_REV_COMPACT_SCRIPT)
+            .key(key)
+            .key(meta_key(key))
+            .arg(start)
+            .arg(end)
+            .invoke_async(conn)
+            .await?;
+
+        decode_compact_range(&reply)
+    }
+
+    /// Queue [`Self::range_rev_compact`] on `pipe`, decode the reply
+    /// with [`decode_compact_range`]
+    pub(crate) fn pipe_range_rev_compact(
+        &self,
+        pipe: &mut Pipeline,
+        key: &str,
+        start: u64,
+        end: u64,
+    ) {
+        pipe.cmd("EVAL")
+            .arg(RANGE_REV_COMPACT_SCRIPT)
+            .arg(2)
+            .arg(key)
+            .arg(meta_key(key))
+            .arg(start)
+            .arg(end);
+    }
+}
+
+/// Score members by the current unix time in seconds
+pub fn now_score<T>(_: &T) -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as f64
+}
@@ -55,6 +55,15 @@ impl PartialEq for PostItem {
     }
 }
 
+impl From<&PostItem> for PlainPostItem {
+    fn from(item: &PostItem) -> Self {
+        Self {
+            canister_id: item.canister_id.clone(),
+            post_id: item.post_id,
+        }
+    }
+}
+
 impl Hash for PostItem {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.canister_id.hash(state);
@@ -90,6 +99,33 @@ pub struct FeedRequest {
     pub num_results: u32,
 }
 
+/// Content filter of a feed cache
+#[derive(Serialize, Deserialize, Clone, Copy, ToSchema, Debug, PartialEq, Eq, Hash)]
+pub enum CacheVariant {
+    Clean,
+    Nsfw,
+    Mixed,
+}
+
+/// Parameters for [`crate::MLFeedCacheState::next_feed`]
+#[derive(Serialize, Deserialize, Clone, ToSchema, Debug)]
+pub struct FeedParams {
+    pub variant: CacheVariant,
+    /// Posts the client already has, never returned
+    pub filter_results: Vec<PostItem>,
+    pub num_results: u32,
+}
+
+impl FeedParams {
+    pub fn from_request(request: FeedRequest, variant: CacheVariant) -> Self {
+        Self {
+            variant,
+            filter_results: request.filter_results,
+            num_results: request.num_results,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, ToSchema, Debug)]
 pub struct FeedResponse {
     pub posts: Vec<PostItem>,
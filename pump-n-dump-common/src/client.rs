@@ -0,0 +1,337 @@
+use candid::{Nat, Principal};
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+use url::Url;
+
+use crate::rest::{
+    BalanceInfoResponse, ClaimReq, CompletedGameInfo, UncommittedGamesRes, UserBetsResponse,
+};
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("network error when accessing worker: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error("invalid worker url: {0}")]
+    Url(#[from] url::ParseError),
+    #[error("failed to sign request: {0}")]
+    Signing(#[from] yral_identity::Error),
+    #[error("worker responded with status {status}: {body}")]
+    Status { status: u16, body: String },
+}
+
+impl ClientError {
+    /// HTTP status returned by the worker, if it responded
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Self::Network(e) => e.status().map(|s| s.as_u16()),
+            Self::Status { status, .. } => Some(*status),
+            Self::Url(_) | Self::Signing(_) => None,
+        }
+    }
+}
+
+/// Client for the pump-n-dump worker's REST endpoints
+///
+/// routes are resolved relative to `base_url`, so a path prefix
+/// (e.g `https://example.com/pnd`) is kept
+#[derive(Clone, Debug)]
+pub struct PumpNDumpClient {
+    base_url: Url,
+    client: reqwest::Client,
+}
+
+impl PumpNDumpClient {
+    pub fn new(mut base_url: Url, client: reqwest::Client) -> Self {
+        // without the trailing slash joins would replace the last path segment
+        if !base_url.path().ends_with('/') {
+            let path = format!("{}/", base_url.path());
+            base_url.set_path(&path);
+        }
+        Self { base_url, client }
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    pub async fn balance(
+        &self,
+        user_canister: Principal,
+    ) -> Result<BalanceInfoResponse, ClientError> {
+        self.get_json(&format!("balance/{user_canister}")).await
+    }
+
+    /// Convert `amount` of the user's GDOLLR to DOLLR, signed by `sender`
+    pub async fn claim(
+        &self,
+        sender: &impl ic_agent::Identity,
+        amount: Nat,
+    ) -> Result<(), ClientError> {
+        let req = ClaimReq::new(sender, amount)?;
+        let url = self.base_url.join("claim_gdollr")?;
+        let res = self.client.post(url).json(&req).send().await?;
+        check_status(res).await?;
+
+        Ok(())
+    }
+
+    /// Games the user has played whose results are not yet committed to their canister
+    pub async fn uncommitted_games(
+        &self,
+        user_canister: Principal,
+    ) -> Result<UncommittedGamesRes, ClientError> {
+        self.get_json(&format!("uncommitted_games/{user_canister}"))
+            .await
+    }
+
+    /// Pumps and dumps placed by the user in the current round of `token_root`'s game
+    pub async fn user_bets(
+        &self,
+        user_canister: Principal,
+        token_root: Principal,
+    ) -> Result<UserBetsResponse, ClientError> {
+        self.get_json(&format!("bets/{user_canister}/{token_root}"))
+            .await
+    }
+
+    /// Completed games of the user, most recent first
+    pub async fn game_history(
+        &self,
+        user_canister: Principal,
+    ) -> Result<Vec<CompletedGameInfo>, ClientError> {
+        self.get_json(&format!("game_history/{user_canister}"))
+            .await
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T, ClientError> {
+        let url = self.base_url.join(path)?;
+        let res = self.client.get(url).send().await?;
+        let res = check_status(res).await?;
+
+        Ok(res.json().await?)
+    }
+}
+
+/// The worker responds with a plain text reason on errors
+async fn check_status(res: reqwest::Response) -> Result<reqwest::Response, ClientError> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    let body = res.text().await?;
+    Err(ClientError::Status {
+        status: status.as_u16(),
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::Nat;
+    use ic_agent::{identity::Secp256k1Identity, Identity};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+
+    use super::*;
+    use crate::{
+        rest::UncommittedGameInfo,
+        verify::{verify_claim_request, ReplayWindow},
+        GameDirection,
+    };
+
+    /// Answers a single request with `status` and `body`, resolves to the raw request
+    async fn serve_once(status: u16, body: String) -> (Url, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/pnd", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut req = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                req.extend_from_slice(&buf[..n]);
+                if n == 0 || request_complete(&req) {
+                    break;
+                }
+            }
+
+            let res = format!(
+                "HTTP/1.1 {status} STUB\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(res.as_bytes()).await.unwrap();
+            String::from_utf8(req).unwrap()
+        });
+        (url.parse().unwrap(), server)
+    }
+
+    fn request_complete(req: &[u8]) -> bool {
+        let req = String::from_utf8_lossy(req);
+        let Some((head, body)) = req.split_once("\r\n\r\n") else {
+            return false;
+        };
+        let len = head
+            .lines()
+            .find_map(|line| {
+                line.to_lowercase()
+                    .strip_prefix("content-length: ")
+                    .map(str::to_string)
+            })
+            .map_or(0, |len| len.trim().parse().unwrap());
+        body.len() >= len
+    }
+
+    fn completed_game(token_root: Principal) -> CompletedGameInfo {
+        CompletedGameInfo {
+            pumps: 2,
+            dumps: 5,
+            reward: Nat::from(50u64),
+            token_root,
+            outcome: GameDirection::Dump,
+        }
+    }
+
+    fn client(base_url: Url) -> PumpNDumpClient {
+        PumpNDumpClient::new(base_url, reqwest::Client::new())
+    }
+
+    #[tokio::test]
+    async fn test_balance() {
+        let balance = BalanceInfoResponse {
+            net_airdrop_reward: Nat::from(1u64),
+            balance: Nat::from(2000u64),
+            withdrawable: Nat::from(300u64),
+        };
+        let (url, server) = serve_once(200, serde_json::to_string(&balance).unwrap()).await;
+        let user_canister = Principal::anonymous();
+
+        let res = client(url).balance(user_canister).await.unwrap();
+
+        let req = server.await.unwrap();
+        assert!(
+            req.starts_with(&format!("GET /pnd/balance/{user_canister} HTTP/1.1\r\n")),
+            "{req}"
+        );
+        assert_eq!(res.net_airdrop_reward, balance.net_airdrop_reward);
+        assert_eq!(res.balance, balance.balance);
+        assert_eq!(res.withdrawable, balance.withdrawable);
+    }
+
+    #[tokio::test]
+    async fn test_uncommitted_games() {
+        let token_root = Principal::management_canister();
+        let games: UncommittedGamesRes = vec![
+            UncommittedGameInfo::Completed(completed_game(token_root)),
+            UncommittedGameInfo::Pending { token_root },
+        ];
+        let (url, server) = serve_once(200, serde_json::to_string(&games).unwrap()).await;
+        let user_canister = Principal::anonymous();
+
+        let res = client(url).uncommitted_games(user_canister).await.unwrap();
+
+        let req = server.await.unwrap();
+        assert!(
+            req.starts_with(&format!(
+                "GET /pnd/uncommitted_games/{user_canister} HTTP/1.1\r\n"
+            )),
+            "{req}"
+        );
+        let [UncommittedGameInfo::Completed(completed), UncommittedGameInfo::Pending { .. }] =
+            &res[..]
+        else {
+            panic!("games should keep their order and state");
+        };
+        assert_eq!(completed.reward, Nat::from(50u64));
+        assert!(res.iter().all(|game| game.token_root() == token_root));
+    }
+
+    #[tokio::test]
+    async fn test_user_bets() {
+        let bets = UserBetsResponse { pumps: 3, dumps: 1 };
+        let (url, server) = serve_once(200, serde_json::to_string(&bets).unwrap()).await;
+        let user_canister = Principal::anonymous();
+        let token_root = Principal::management_canister();
+
+        let res = client(url)
+            .user_bets(user_canister, token_root)
+            .await
+            .unwrap();
+
+        let req = server.await.unwrap();
+        assert!(
+            req.starts_with(&format!(
+                "GET /pnd/bets/{user_canister}/{token_root} HTTP/1.1\r\n"
+            )),
+            "{req}"
+        );
+        assert_eq!((res.pumps, res.dumps), (3, 1));
+    }
+
+    #[tokio::test]
+    async fn test_game_history() {
+        let token_root = Principal::management_canister();
+        let history = vec![completed_game(token_root)];
+        let (url, server) = serve_once(200, serde_json::to_string(&history).unwrap()).await;
+        let user_canister = Principal::anonymous();
+
+        let res = client(url).game_history(user_canister).await.unwrap();
+
+        let req = server.await.unwrap();
+        assert!(
+            req.starts_with(&format!(
+                "GET /pnd/game_history/{user_canister} HTTP/1.1\r\n"
+            )),
+            "{req}"
+        );
+        let [game] = &res[..] else {
+            panic!("one game should be returned");
+        };
+        assert_eq!(game.token_root, token_root);
+        assert_eq!((game.pumps, game.dumps), (2, 5));
+        assert_eq!(game.reward, Nat::from(50u64));
+        assert!(matches!(game.outcome, GameDirection::Dump));
+    }
+
+    #[tokio::test]
+    async fn test_claim() {
+        let (url, server) = serve_once(200, String::new()).await;
+        let sender =
+            Secp256k1Identity::from_private_key(k256::SecretKey::from_slice(&[1; 32]).unwrap());
+
+        client(url).claim(&sender, Nat::from(100u64)).await.unwrap();
+
+        let req = server.await.unwrap();
+        assert!(
+            req.starts_with("POST /pnd/claim_gdollr HTTP/1.1\r\n"),
+            "{req}"
+        );
+        let (_, body) = req.split_once("\r\n\r\n").unwrap();
+        let claim: ClaimReq = serde_json::from_str(body).unwrap();
+        assert_eq!(claim.amount, Nat::from(100u64));
+        let verified = verify_claim_request(&claim, &ReplayWindow::default(), &()).unwrap();
+        assert_eq!(verified, sender.sender().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_error_status() {
+        let (url, server) = serve_once(400, "insufficient balance".to_string()).await;
+
+        let err = client(url)
+            .balance(Principal::anonymous())
+            .await
+            .err()
+            .unwrap();
+
+        server.await.unwrap();
+        assert_eq!(err.status(), Some(400));
+        assert!(
+            matches!(&err, ClientError::Status { body, .. } if body == "insufficient balance"),
+            "{err}"
+        );
+    }
+}
@@ -1,4 +1,7 @@
+#[cfg(feature = "client")]
+pub mod client;
 pub mod rest;
+pub mod verify;
 pub mod ws;
 
 use candid::Nat;
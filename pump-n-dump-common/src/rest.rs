@@ -3,6 +3,8 @@ use canisters_client::individual_user_template::ParticipatedGameInfo;
 use serde::{Deserialize, Serialize};
 use yral_identity::{msg_builder::Message, Signature};
 
+pub use signed_request::RequestMeta;
+
 use crate::GameDirection;
 
 /// Request for converting GDOLLR to DOLLR
@@ -12,27 +14,37 @@ pub struct ClaimReq {
     pub sender: Principal,
     // amount of DOLLR
     pub amount: Nat,
+    #[serde(default)]
+    pub meta: Option<RequestMeta>,
     // signature asserting the user's consent
     pub signature: Signature,
 }
 
-pub fn claim_msg(amount: Nat) -> Message {
-    Message::default()
-        .method_name("pump_or_dump_worker_claim".into())
-        .args((amount,))
-        .expect("Claim request should serialize")
+pub fn claim_msg(amount: Nat, meta: Option<RequestMeta>) -> Message {
+    let msg = Message::default();
+    match meta {
+        None => msg
+            .method_name("pump_or_dump_worker_claim".into())
+            .args((amount,)),
+        Some(meta) => msg
+            .method_name("pump_or_dump_worker_claim_v2".into())
+            .args((amount, meta)),
+    }
+    .expect("Claim request should serialize")
 }
 
 impl ClaimReq {
     #[cfg(feature = "client")]
     pub fn new(sender: &impl ic_agent::Identity, amount: Nat) -> yral_identity::Result<Self> {
         use yral_identity::ic_agent::sign_message;
-        let msg = claim_msg(amount.clone());
+        let meta = RequestMeta::now();
+        let msg = claim_msg(amount.clone(), Some(meta));
         let signature = sign_message(sender, msg)?;
 
         Ok(Self {
             sender: sender.sender().expect("signing was succesful"),
             amount,
+            meta: Some(meta),
             signature,
         })
     }
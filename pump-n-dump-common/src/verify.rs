@@ -0,0 +1,129 @@
+//! Server side verification of signed worker requests
+
+use candid::Principal;
+use signed_request::verify_signed_request;
+pub use signed_request::{ReplayGuard, ReplayWindow, VerifyError};
+use yral_identity::Signature;
+
+use crate::{
+    rest::{claim_msg, ClaimReq},
+    ws::identify_message,
+};
+
+/// Verify that `req` was signed by `req.sender`, returns the verified sender
+pub fn verify_claim_request(
+    req: &ClaimReq,
+    window: &ReplayWindow,
+    guard: &impl ReplayGuard,
+) -> Result<Principal, VerifyError> {
+    let msg = claim_msg(req.amount.clone(), req.meta);
+    verify_signed_request(
+        req.sender,
+        msg,
+        req.meta.as_ref(),
+        &req.signature,
+        window,
+        guard,
+    )
+}
+
+/// Verify the identity presented when opening a game websocket,
+/// see [`crate::ws::websocket_connection_url`]
+///
+/// identify signatures are not replay guarded, reconnecting
+/// with the same url is allowed until the signature expires
+pub fn verify_identify(
+    sender: Principal,
+    game_canister: Principal,
+    token_root: Principal,
+    signature: &Signature,
+) -> Result<Principal, VerifyError> {
+    let msg = identify_message(game_canister, token_root);
+    signature.clone().verify_identity(sender, msg)?;
+
+    Ok(sender)
+}
+
+#[cfg(test)]
+mod tests {
+    use candid::Nat;
+    use ic_agent::Identity;
+    use signed_request::test_util::{identity, meta};
+    use yral_identity::ic_agent::sign_message;
+
+    use super::*;
+    use crate::rest::RequestMeta;
+
+    fn claim_req(signer: &impl Identity, sender: Principal, meta: RequestMeta) -> ClaimReq {
+        let amount = Nat::from(10u64);
+        let signature = sign_message(signer, claim_msg(amount.clone(), Some(meta))).unwrap();
+        ClaimReq {
+            sender,
+            amount,
+            meta: Some(meta),
+            signature,
+        }
+    }
+
+    #[test]
+    fn test_claim_request_round_trip() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+        let req = claim_req(&signer, sender, meta(0));
+
+        let verified = verify_claim_request(&req, &ReplayWindow::default(), &());
+        assert_eq!(verified.unwrap(), sender);
+    }
+
+    #[test]
+    fn test_claim_request_wrong_sender() {
+        let other = identity(2).sender().unwrap();
+        let req = claim_req(&identity(1), other, meta(0));
+
+        let res = verify_claim_request(&req, &ReplayWindow::default(), &());
+        assert!(matches!(res, Err(VerifyError::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn test_claim_request_covers_amount_and_meta() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+
+        let mut req = claim_req(&signer, sender, meta(0));
+        req.amount = Nat::from(11u64);
+        let res = verify_claim_request(&req, &ReplayWindow::default(), &());
+        assert!(matches!(res, Err(VerifyError::InvalidSignature(_))));
+
+        let mut req = claim_req(&signer, sender, meta(0));
+        req.meta = Some(meta(-1));
+        let res = verify_claim_request(&req, &ReplayWindow::default(), &());
+        assert!(matches!(res, Err(VerifyError::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn test_identify_round_trip() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+        let game_canister = identity(3).sender().unwrap();
+        let token_root = identity(4).sender().unwrap();
+        let signature = sign_message(&signer, identify_message(game_canister, token_root)).unwrap();
+
+        let verified = verify_identify(sender, game_canister, token_root, &signature);
+        assert_eq!(verified.unwrap(), sender);
+        // signed for another game
+        let res = verify_identify(sender, token_root, game_canister, &signature);
+        assert!(matches!(res, Err(VerifyError::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn test_identify_wrong_sender() {
+        let game_canister = identity(3).sender().unwrap();
+        let token_root = identity(4).sender().unwrap();
+        let signature =
+            sign_message(&identity(1), identify_message(game_canister, token_root)).unwrap();
+        let other = identity(2).sender().unwrap();
+
+        let res = verify_identify(other, game_canister, token_root, &signature);
+        assert!(matches!(res, Err(VerifyError::InvalidSignature(_))));
+    }
+}
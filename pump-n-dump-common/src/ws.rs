@@ -5,6 +5,9 @@ use yral_identity::msg_builder::Message;
 
 use crate::{rest::UserBetsResponse, GameDirection};
 
+#[cfg(feature = "client")]
+pub mod session;
+
 /// Types of request that worker can handle
 #[derive(Serialize, Deserialize)]
 pub enum WsMessage {
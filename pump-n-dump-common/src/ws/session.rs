@@ -0,0 +1,547 @@
+//! Client side of the pump-n-dump game websocket
+//!
+//! [`GameSession::connect`] returns the session along with a [`SessionDriver`]
+//! that owns the socket, the driver must be spawned on the caller's executor
+//! (e.g `tokio::spawn` or `wasm_bindgen_futures::spawn_local`)
+
+#[cfg(feature = "js-ws")]
+mod gloo;
+pub mod local;
+#[cfg(feature = "native-ws")]
+mod tungstenite;
+
+#[cfg(feature = "js-ws")]
+pub use gloo::GlooConnector;
+#[cfg(feature = "native-ws")]
+pub use tungstenite::TungsteniteConnector;
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::{pin, Pin},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use candid::Principal;
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{select, Either},
+    Stream, StreamExt,
+};
+use futures_timer::Delay;
+use thiserror::Error;
+use url::Url;
+use uuid::Uuid;
+
+use super::{websocket_connection_url, WsError, WsMessage, WsRequest, WsResp, WsResponse};
+use crate::GameDirection;
+
+/// A single websocket connection carrying text frames
+pub trait WsConnection {
+    fn send(&mut self, msg: String) -> impl Future<Output = Result<(), String>>;
+
+    /// Next text frame, `None` once the connection is closed
+    ///
+    /// must be cancel safe, the session drops this future
+    /// whenever it has a request to send
+    fn recv(&mut self) -> impl Future<Output = Option<Result<String, String>>>;
+}
+
+/// Opens [`WsConnection`]s, one per (re)connect
+pub trait WsConnector {
+    type Conn: WsConnection;
+
+    fn connect(&self, url: Url) -> impl Future<Output = Result<Self::Conn, String>>;
+}
+
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error("failed to build connection url: {0}")]
+    Identify(String),
+    #[error("failed to connect to worker: {0}")]
+    Connect(String),
+    #[error("bet rejected by worker: {0:?}")]
+    Bet(WsError),
+    #[error("worker responded with something other than a bet result")]
+    UnexpectedResponse,
+    /// The connection dropped before the worker responded,
+    /// the bet may or may not have been placed
+    #[error("connection to worker lost")]
+    Disconnected,
+    #[error("game session is closed")]
+    Closed,
+}
+
+/// Reconnect and buffering behaviour of a [`SessionDriver`]
+#[derive(Clone, Debug)]
+pub struct SessionConfig {
+    /// Consecutive failed reconnects before the session is closed
+    pub max_reconnect_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Game events buffered until the [`GameSession`] stream is polled,
+    /// further events are dropped while the buffer is full
+    pub max_buffered_events: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            max_reconnect_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            max_buffered_events: 64,
+        }
+    }
+}
+
+impl SessionConfig {
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(self.max_backoff)
+    }
+}
+
+struct BetCommand {
+    request_id: Uuid,
+    direction: GameDirection,
+    round: u64,
+    reply: oneshot::Sender<Result<u64, SessionError>>,
+}
+
+/// Cheap handle for placing bets, see [`GameSession::handle`]
+#[derive(Clone)]
+pub struct GameSessionHandle {
+    commands: mpsc::UnboundedSender<BetCommand>,
+}
+
+impl GameSessionHandle {
+    /// Bet on `round`, resolves to the round the bet was placed in
+    ///
+    /// the worker may roll the bet over to the next round in extreme cases
+    pub async fn bet(&self, direction: GameDirection, round: u64) -> Result<u64, SessionError> {
+        let (reply, res) = oneshot::channel();
+        self.commands
+            .unbounded_send(BetCommand {
+                request_id: Uuid::new_v4(),
+                direction,
+                round,
+                reply,
+            })
+            .map_err(|_| SessionError::Closed)?;
+
+        res.await.map_err(|_| SessionError::Closed)?
+    }
+}
+
+/// A live game of a single token
+///
+/// Bets are placed with [`GameSession::bet`] and game events
+/// ([`WsResp::WelcomeEvent`], [`WsResp::WinningPoolEvent`], [`WsResp::GameResultEvent`])
+/// are yielded by the [`Stream`] impl. A fresh [`WsResp::WelcomeEvent`]
+/// is received after every reconnect.
+/// Events are dropped while [`SessionConfig::max_buffered_events`] are
+/// waiting to be polled. The stream ends once the session is closed
+pub struct GameSession {
+    handle: GameSessionHandle,
+    events: mpsc::Receiver<WsResp>,
+}
+
+impl GameSession {
+    /// Connect to the game of `token_root` created by `game_canister`
+    ///
+    /// `base_url` must end with a slash (`/`), see [`websocket_connection_url`]
+    pub async fn connect<C: WsConnector, I: ic_agent::Identity>(
+        connector: C,
+        base_url: Url,
+        identity: I,
+        game_canister: Principal,
+        token_root: Principal,
+        config: SessionConfig,
+    ) -> Result<(Self, SessionDriver<C, I>), SessionError> {
+        let (commands_tx, commands) = mpsc::unbounded();
+        let (events_tx, events) = mpsc::channel(config.max_buffered_events);
+
+        let mut driver = SessionDriver {
+            connector,
+            base_url,
+            identity,
+            game_canister,
+            token_root,
+            config,
+            conn: None,
+            commands,
+            events: events_tx,
+            pending: HashMap::new(),
+        };
+        driver.conn = Some(driver.connect().await?);
+
+        let session = Self {
+            handle: GameSessionHandle {
+                commands: commands_tx,
+            },
+            events,
+        };
+
+        Ok((session, driver))
+    }
+
+    pub async fn bet(&self, direction: GameDirection, round: u64) -> Result<u64, SessionError> {
+        self.handle.bet(direction, round).await
+    }
+
+    /// Handle for placing bets while the session is being polled for events
+    pub fn handle(&self) -> GameSessionHandle {
+        self.handle.clone()
+    }
+}
+
+impl Stream for GameSession {
+    type Item = WsResp;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_next_unpin(cx)
+    }
+}
+
+enum Incoming {
+    Frame(Option<Result<String, String>>),
+    Bet(Option<BetCommand>),
+}
+
+enum Exit {
+    /// All [`GameSession`] handles were dropped
+    Closed,
+    Disconnected,
+}
+
+/// Owns the websocket of a [`GameSession`], reconnecting as needed
+///
+/// must be polled to completion for the session to make progress
+pub struct SessionDriver<C: WsConnector, I> {
+    connector: C,
+    base_url: Url,
+    identity: I,
+    game_canister: Principal,
+    token_root: Principal,
+    config: SessionConfig,
+    conn: Option<C::Conn>,
+    commands: mpsc::UnboundedReceiver<BetCommand>,
+    events: mpsc::Sender<WsResp>,
+    pending: HashMap<Uuid, oneshot::Sender<Result<u64, SessionError>>>,
+}
+
+impl<C: WsConnector, I: ic_agent::Identity> SessionDriver<C, I> {
+    /// Connection urls carry a signature of the identity,
+    /// so each connect re-identifies the user
+    async fn connect(&self) -> Result<C::Conn, SessionError> {
+        let url = websocket_connection_url(
+            self.base_url.clone(),
+            &self.identity,
+            self.game_canister,
+            self.token_root,
+        )
+        .map_err(SessionError::Identify)?;
+
+        self.connector
+            .connect(url)
+            .await
+            .map_err(SessionError::Connect)
+    }
+
+    async fn reconnect(&self) -> Option<C::Conn> {
+        for attempt in 1..=self.config.max_reconnect_attempts {
+            match self.connect().await {
+                Ok(conn) => return Some(conn),
+                Err(SessionError::Identify(_)) => return None,
+                Err(_) => Delay::new(self.config.backoff(attempt)).await,
+            }
+        }
+        None
+    }
+
+    fn fail_pending(&mut self, err: impl Fn() -> SessionError) {
+        for (_, reply) in self.pending.drain() {
+            _ = reply.send(Err(err()));
+        }
+    }
+
+    fn handle_frame(&mut self, frame: &str) {
+        let Ok(res) = serde_json::from_str::<WsResponse>(frame) else {
+            return;
+        };
+        if res.is_event() {
+            // a full buffer means the events aren't being polled,
+            // the next welcome event restates the game anyway
+            _ = self.events.try_send(res.response);
+            return;
+        }
+
+        let Some(reply) = self.pending.remove(&res.request_id) else {
+            return;
+        };
+        let res = match res.response {
+            WsResp::BetSuccesful { round } => Ok(round),
+            WsResp::Error(e) => Err(SessionError::Bet(e)),
+            _ => Err(SessionError::UnexpectedResponse),
+        };
+        _ = reply.send(res);
+    }
+
+    async fn drive(&mut self, mut conn: C::Conn) -> Exit {
+        loop {
+            let incoming = {
+                let frame = pin!(conn.recv());
+                match select(frame, self.commands.next()).await {
+                    Either::Left((frame, _)) => Incoming::Frame(frame),
+                    Either::Right((cmd, _)) => Incoming::Bet(cmd),
+                }
+            };
+
+            match incoming {
+                Incoming::Frame(Some(Ok(frame))) => self.handle_frame(&frame),
+                Incoming::Frame(_) => return Exit::Disconnected,
+                Incoming::Bet(None) => return Exit::Closed,
+                Incoming::Bet(Some(cmd)) => {
+                    let req = WsRequest {
+                        request_id: cmd.request_id,
+                        msg: WsMessage::Bet {
+                            direction: cmd.direction,
+                            round: cmd.round,
+                        },
+                    };
+                    let req = serde_json::to_string(&req).expect("ws request should serialize");
+                    if conn.send(req).await.is_err() {
+                        _ = cmd.reply.send(Err(SessionError::Disconnected));
+                        return Exit::Disconnected;
+                    }
+                    self.pending.insert(cmd.request_id, cmd.reply);
+                }
+            }
+        }
+    }
+
+    /// Drive the session until it is closed, either because all handles
+    /// were dropped or reconnecting failed
+    pub async fn run(mut self) {
+        let mut conn = self.conn.take();
+        while let Some(current) = conn {
+            if let Exit::Closed = self.drive(current).await {
+                break;
+            }
+            self.fail_pending(|| SessionError::Disconnected);
+            conn = self.reconnect().await;
+        }
+        self.fail_pending(|| SessionError::Closed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future::join, FutureExt};
+    use ic_agent::identity::Secp256k1Identity;
+    use k256::SecretKey;
+
+    use super::{local::LocalGameServer, *};
+
+    fn identity() -> Secp256k1Identity {
+        Secp256k1Identity::from_private_key(SecretKey::from_slice(&[7; 32]).unwrap())
+    }
+
+    fn config() -> SessionConfig {
+        SessionConfig {
+            max_reconnect_attempts: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    async fn connect(
+        server: &LocalGameServer,
+        config: SessionConfig,
+    ) -> (
+        GameSession,
+        SessionDriver<LocalGameServer, Secp256k1Identity>,
+    ) {
+        GameSession::connect(
+            server.clone(),
+            Url::parse("ws://localhost/").unwrap(),
+            identity(),
+            Principal::from_slice(&[1]),
+            Principal::from_slice(&[2]),
+            config,
+        )
+        .await
+        .unwrap()
+    }
+
+    fn pool_event(round: u64) -> WsResp {
+        WsResp::WinningPoolEvent { new_pool: 0, round }
+    }
+
+    #[tokio::test]
+    async fn test_bets_correlated_by_request_id() {
+        let server = LocalGameServer::new();
+        let (session, driver) = connect(&server, config()).await;
+        let mut conn = server.accept().await.unwrap();
+
+        join(driver.run(), async move {
+            let bets = join(
+                session.bet(GameDirection::Pump, 1),
+                session.bet(GameDirection::Dump, 1),
+            );
+            let worker = async {
+                let first = conn.recv_request().await.unwrap();
+                let second = conn.recv_request().await.unwrap();
+                let round_of = |req: &WsRequest| match req.msg {
+                    WsMessage::Bet {
+                        direction: GameDirection::Pump,
+                        ..
+                    } => 10,
+                    WsMessage::Bet {
+                        direction: GameDirection::Dump,
+                        ..
+                    } => 20,
+                };
+                conn.respond(Uuid::new_v4(), WsResp::BetSuccesful { round: 99 });
+                conn.respond(
+                    second.request_id,
+                    WsResp::BetSuccesful {
+                        round: round_of(&second),
+                    },
+                );
+                conn.respond(
+                    first.request_id,
+                    WsResp::BetSuccesful {
+                        round: round_of(&first),
+                    },
+                );
+            };
+            let ((pump, dump), _) = join(bets, worker).await;
+
+            assert_eq!(pump.unwrap(), 10);
+            assert_eq!(dump.unwrap(), 20);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_bet_error_reply() {
+        let server = LocalGameServer::new();
+        let (session, driver) = connect(&server, config()).await;
+        let mut conn = server.accept().await.unwrap();
+
+        join(driver.run(), async move {
+            let worker = async {
+                let req = conn.recv_request().await.unwrap();
+                conn.respond(
+                    req.request_id,
+                    WsResp::bet_failure("round over", GameDirection::Pump),
+                );
+                let req = conn.recv_request().await.unwrap();
+                conn.respond(req.request_id, pool_event(1));
+            };
+            let bets = async {
+                (
+                    session.bet(GameDirection::Pump, 1).await,
+                    session.bet(GameDirection::Pump, 1).await,
+                )
+            };
+            let ((rejected, unexpected), _) = join(bets, worker).await;
+
+            assert!(matches!(
+                rejected,
+                Err(SessionError::Bet(WsError::BetFailure { message, .. })) if message == "round over"
+            ));
+            assert!(matches!(unexpected, Err(SessionError::UnexpectedResponse)));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_after_disconnect() {
+        let server = LocalGameServer::new();
+        let (mut session, driver) = connect(&server, config()).await;
+        let mut conn = server.accept().await.unwrap();
+
+        join(driver.run(), async move {
+            let worker = async {
+                conn.recv_request().await.unwrap();
+                server.refuse_connections(1);
+                drop(conn);
+            };
+            let (lost, _) = join(session.bet(GameDirection::Pump, 1), worker).await;
+            assert!(matches!(lost, Err(SessionError::Disconnected)));
+
+            let mut conn = server.accept().await.unwrap();
+            conn.send_event(pool_event(2));
+            assert!(matches!(
+                session.next().await,
+                Some(WsResp::WinningPoolEvent { round: 2, .. })
+            ));
+
+            let worker = async {
+                let req = conn.recv_request().await.unwrap();
+                conn.respond(req.request_id, WsResp::BetSuccesful { round: 2 });
+            };
+            let (placed, _) = join(session.bet(GameDirection::Pump, 2), worker).await;
+            assert_eq!(placed.unwrap(), 2);
+            // initial connect, the refused attempt and the reconnect
+            assert_eq!(server.connect_urls().len(), 3);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_closed_after_failed_reconnects() {
+        let server = LocalGameServer::new();
+        let (mut session, driver) = connect(&server, config()).await;
+        let conn = server.accept().await.unwrap();
+        server.refuse_connections(2);
+        drop(conn);
+
+        driver.run().await;
+
+        assert!(session.next().await.is_none());
+        assert!(matches!(
+            session.bet(GameDirection::Pump, 1).await,
+            Err(SessionError::Closed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_events_bounded_when_not_polled() {
+        let server = LocalGameServer::new();
+        let config = SessionConfig {
+            max_buffered_events: 1,
+            ..config()
+        };
+        let (mut session, driver) = connect(&server, config).await;
+        let mut conn = server.accept().await.unwrap();
+
+        join(driver.run(), async move {
+            let worker = async {
+                for round in 0..5 {
+                    conn.send_event(pool_event(round));
+                }
+                let req = conn.recv_request().await.unwrap();
+                conn.respond(req.request_id, WsResp::BetSuccesful { round: 5 });
+            };
+            let (placed, _) = join(session.bet(GameDirection::Pump, 5), worker).await;
+            assert_eq!(placed.unwrap(), 5);
+
+            // one slot for the buffer and one for the driver's sender
+            for round in 0..2 {
+                assert!(matches!(
+                    session.next().await,
+                    Some(WsResp::WinningPoolEvent { round: r, .. }) if r == round
+                ));
+            }
+            assert!(session.next().now_or_never().is_none());
+        })
+        .await;
+    }
+}
@@ -0,0 +1,35 @@
+use futures::{SinkExt, StreamExt};
+use gloo_net::websocket::{futures::WebSocket, Message};
+use url::Url;
+
+use super::{WsConnection, WsConnector};
+
+/// Connects with the browser's `WebSocket`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GlooConnector;
+
+impl WsConnector for GlooConnector {
+    type Conn = WebSocket;
+
+    async fn connect(&self, url: Url) -> Result<WebSocket, String> {
+        WebSocket::open(url.as_str()).map_err(|e| e.to_string())
+    }
+}
+
+impl WsConnection for WebSocket {
+    async fn send(&mut self, msg: String) -> Result<(), String> {
+        SinkExt::send(self, Message::Text(msg))
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn recv(&mut self) -> Option<Result<String, String>> {
+        loop {
+            match self.next().await? {
+                Ok(Message::Text(text)) => return Some(Ok(text)),
+                Ok(Message::Bytes(_)) => continue,
+                Err(e) => return Some(Err(e.to_string())),
+            }
+        }
+    }
+}
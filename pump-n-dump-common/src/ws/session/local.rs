@@ -0,0 +1,141 @@
+use std::sync::{Arc, Mutex};
+
+use futures::{channel::mpsc, lock::Mutex as AsyncMutex, StreamExt};
+use url::Url;
+use uuid::Uuid;
+
+use super::{WsConnection, WsConnector};
+use crate::ws::{WsRequest, WsResp, WsResponse};
+
+/// In-process stand-in for the pump-n-dump worker, for tests
+///
+/// Connections made through this connector are handed to the test
+/// by [`LocalGameServer::accept`]. Clones share the same state
+#[derive(Clone)]
+pub struct LocalGameServer {
+    incoming: mpsc::UnboundedSender<ServerConnection>,
+    accepted: Arc<AsyncMutex<mpsc::UnboundedReceiver<ServerConnection>>>,
+    refuse: Arc<Mutex<u32>>,
+    connect_urls: Arc<Mutex<Vec<Url>>>,
+}
+
+impl Default for LocalGameServer {
+    fn default() -> Self {
+        let (incoming, accepted) = mpsc::unbounded();
+        Self {
+            incoming,
+            accepted: Arc::new(AsyncMutex::new(accepted)),
+            refuse: Arc::default(),
+            connect_urls: Arc::default(),
+        }
+    }
+}
+
+impl LocalGameServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait for the next client connection
+    pub async fn accept(&self) -> Option<ServerConnection> {
+        self.accepted.lock().await.next().await
+    }
+
+    /// Fail the next `count` connection attempts
+    pub fn refuse_connections(&self, count: u32) {
+        *self.refuse.lock().unwrap() = count;
+    }
+
+    /// Urls of all connection attempts so far, in order
+    pub fn connect_urls(&self) -> Vec<Url> {
+        self.connect_urls.lock().unwrap().clone()
+    }
+}
+
+impl WsConnector for LocalGameServer {
+    type Conn = LocalConnection;
+
+    async fn connect(&self, url: Url) -> Result<LocalConnection, String> {
+        self.connect_urls.lock().unwrap().push(url.clone());
+        {
+            let mut refuse = self.refuse.lock().unwrap();
+            if *refuse > 0 {
+                *refuse -= 1;
+                return Err("connection refused".into());
+            }
+        }
+
+        let (client_tx, server_rx) = mpsc::unbounded();
+        let (server_tx, client_rx) = mpsc::unbounded();
+        self.incoming
+            .unbounded_send(ServerConnection {
+                url,
+                tx: server_tx,
+                rx: server_rx,
+            })
+            .map_err(|e| e.to_string())?;
+
+        Ok(LocalConnection {
+            tx: client_tx,
+            rx: client_rx,
+        })
+    }
+}
+
+/// Client end of a [`LocalGameServer`] connection
+pub struct LocalConnection {
+    tx: mpsc::UnboundedSender<String>,
+    rx: mpsc::UnboundedReceiver<String>,
+}
+
+impl WsConnection for LocalConnection {
+    async fn send(&mut self, msg: String) -> Result<(), String> {
+        self.tx.unbounded_send(msg).map_err(|e| e.to_string())
+    }
+
+    async fn recv(&mut self) -> Option<Result<String, String>> {
+        self.rx.next().await.map(Ok)
+    }
+}
+
+/// Server end of a [`LocalGameServer`] connection,
+/// dropping it closes the connection
+pub struct ServerConnection {
+    url: Url,
+    tx: mpsc::UnboundedSender<String>,
+    rx: mpsc::UnboundedReceiver<String>,
+}
+
+impl ServerConnection {
+    /// Url the client connected with
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Next request from the client, `None` once the client disconnects
+    ///
+    /// # Panics
+    /// if the client sends something other than a [`WsRequest`]
+    pub async fn recv_request(&mut self) -> Option<WsRequest> {
+        let frame = self.rx.next().await?;
+        Some(serde_json::from_str(&frame).expect("client should send a WsRequest"))
+    }
+
+    pub fn send(&self, res: WsResponse) {
+        let frame = serde_json::to_string(&res).expect("ws response should serialize");
+        _ = self.tx.unbounded_send(frame);
+    }
+
+    /// Respond to the request with `request_id`
+    pub fn respond(&self, request_id: Uuid, response: WsResp) {
+        self.send(WsResponse {
+            request_id,
+            response,
+        });
+    }
+
+    /// Broadcast an event to the client
+    pub fn send_event(&self, event: WsResp) {
+        self.respond(Uuid::max(), event);
+    }
+}
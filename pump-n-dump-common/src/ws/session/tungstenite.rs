@@ -0,0 +1,40 @@
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use url::Url;
+
+use super::{WsConnection, WsConnector};
+
+/// Connects with `tokio-tungstenite`, requires a tokio runtime
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TungsteniteConnector;
+
+impl WsConnector for TungsteniteConnector {
+    type Conn = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn connect(&self, url: Url) -> Result<Self::Conn, String> {
+        let (conn, _) = connect_async(url.as_str())
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(conn)
+    }
+}
+
+impl WsConnection for WebSocketStream<MaybeTlsStream<TcpStream>> {
+    async fn send(&mut self, msg: String) -> Result<(), String> {
+        SinkExt::send(self, Message::text(msg))
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn recv(&mut self) -> Option<Result<String, String>> {
+        loop {
+            match self.next().await? {
+                Ok(Message::Text(text)) => return Some(Ok(text.to_string())),
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => continue,
+                Err(e) => return Some(Err(e.to_string())),
+            }
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! Replay protection shared by the signed requests of Yral's workers
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+mod verify;
+
+pub use verify::*;
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+/// Replay protection covered by request signatures
+///
+/// Requests signed by clients predating it carry no meta (wire format v1),
+/// their signed message only covers the payload
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, CandidType)]
+pub struct RequestMeta {
+    /// Random, unique per request
+    pub nonce: u64,
+    pub issued_at_secs: u64,
+}
+
+impl RequestMeta {
+    /// Fresh meta issued now
+    #[cfg(feature = "client")]
+    pub fn now() -> Self {
+        use web_time::{SystemTime, UNIX_EPOCH};
+
+        let mut nonce = [0u8; 8];
+        getrandom::getrandom(&mut nonce).expect("system randomness should be available");
+        let issued_at_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time should be after unix epoch")
+            .as_secs();
+
+        Self {
+            nonce: u64::from_le_bytes(nonce),
+            issued_at_secs,
+        }
+    }
+}
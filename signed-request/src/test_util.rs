@@ -0,0 +1,46 @@
+//! Fixtures for testing workers' signed requests
+
+use std::{collections::HashMap, sync::Mutex};
+
+use candid::Principal;
+use ic_agent::identity::Secp256k1Identity;
+use web_time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{ReplayGuard, RequestMeta};
+
+/// Identity derived from `seed`, the same seed gives the same principal
+pub fn identity(seed: u8) -> Secp256k1Identity {
+    Secp256k1Identity::from_private_key(
+        k256::SecretKey::from_slice(&[seed; 32]).expect("seed should give a valid key"),
+    )
+}
+
+/// Meta issued `offset_secs` from now
+pub fn meta(offset_secs: i64) -> RequestMeta {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time should be after unix epoch");
+    RequestMeta {
+        // unique enough within a test
+        nonce: now.as_nanos() as u64,
+        issued_at_secs: now.as_secs().saturating_add_signed(offset_secs),
+    }
+}
+
+/// In memory [`ReplayGuard`]
+#[derive(Default)]
+pub struct SeenKeys(Mutex<HashMap<(Principal, String), Duration>>);
+
+impl ReplayGuard for SeenKeys {
+    fn record(&self, sender: Principal, key: &str, expires_at: Duration) -> bool {
+        let mut seen = self.0.lock().unwrap();
+        seen.insert((sender, key.to_string()), expires_at).is_none()
+    }
+}
+
+impl SeenKeys {
+    /// Expiries of the recorded keys
+    pub fn expiries(&self) -> Vec<Duration> {
+        self.0.lock().unwrap().values().copied().collect()
+    }
+}
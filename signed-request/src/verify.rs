@@ -0,0 +1,278 @@
+//! Server side verification of signed worker requests
+
+use candid::Principal;
+use thiserror::Error;
+use web_time::{Duration, SystemTime, UNIX_EPOCH};
+use yral_identity::{msg_builder::Message, Signature};
+
+use crate::RequestMeta;
+
+#[derive(Debug, Error)]
+pub enum VerifyError {
+    /// Signature doesn't match the sender or the message,
+    /// or the signature has expired
+    #[error("invalid signature: {0}")]
+    InvalidSignature(#[from] yral_identity::Error),
+    #[error("request was already processed")]
+    Replayed,
+    #[error("request was issued outside the accepted window")]
+    Stale,
+    #[error("request has no replay protection")]
+    MissingMeta,
+    /// Legacy signature without a readable ingress expiry
+    #[error("malformed signature: {0}")]
+    MalformedSignature(String),
+}
+
+/// Remembers signed requests that were already accepted
+pub trait ReplayGuard {
+    /// Record `key` for `sender`, returns `false` if it was already recorded
+    ///
+    /// `expires_at` is a duration since the unix epoch, the request no longer
+    /// verifies after it so the key can be dropped then. It is the end of the
+    /// [`ReplayWindow`] for requests with [`RequestMeta`] and the signature's
+    /// own expiry for legacy requests
+    fn record(&self, sender: Principal, key: &str, expires_at: Duration) -> bool;
+}
+
+/// Accept every request, for workers with their own replay protection
+impl ReplayGuard for () {
+    fn record(&self, _sender: Principal, _key: &str, _expires_at: Duration) -> bool {
+        true
+    }
+}
+
+/// Bounds on [`RequestMeta::issued_at_secs`] accepted by [`verify_signed_request`]
+#[derive(Clone, Debug)]
+pub struct ReplayWindow {
+    pub max_age: Duration,
+    /// Tolerance for clients with clocks ahead of the worker
+    pub max_clock_skew: Duration,
+    /// Accept requests without [`RequestMeta`] from older clients,
+    /// off by default
+    pub allow_legacy: bool,
+}
+
+impl Default for ReplayWindow {
+    fn default() -> Self {
+        Self {
+            max_age: Duration::from_secs(5 * 60),
+            max_clock_skew: Duration::from_secs(30),
+            allow_legacy: false,
+        }
+    }
+}
+
+impl ReplayWindow {
+    /// Returns when `meta` leaves the window
+    fn check(&self, meta: &RequestMeta) -> Result<Duration, VerifyError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time should be after unix epoch");
+        let issued_at = Duration::from_secs(meta.issued_at_secs);
+        if issued_at > now + self.max_clock_skew || now.saturating_sub(issued_at) > self.max_age {
+            return Err(VerifyError::Stale);
+        }
+
+        Ok(issued_at + self.max_age)
+    }
+}
+
+/// Key and expiry of a legacy signature
+///
+/// the expiry is only exposed through the serialized signature,
+/// as nanoseconds since the unix epoch
+fn legacy_key(signature: &Signature) -> Result<(String, Duration), VerifyError> {
+    let value = serde_json::to_value(signature)
+        .map_err(|e| VerifyError::MalformedSignature(e.to_string()))?;
+    let expiry = value["ingress_expiry"].as_u64().ok_or_else(|| {
+        VerifyError::MalformedSignature("ingress expiry is missing or not a number".into())
+    })?;
+    Ok((value.to_string(), Duration::from_nanos(expiry)))
+}
+
+/// Verify that `msg` was signed by `sender` and was not processed before,
+/// returns the verified sender
+///
+/// `msg` must cover `meta`, workers use the `verify_*` functions of their
+/// request types rather than calling this directly
+pub fn verify_signed_request(
+    sender: Principal,
+    msg: Message,
+    meta: Option<&RequestMeta>,
+    signature: &Signature,
+    window: &ReplayWindow,
+    guard: &impl ReplayGuard,
+) -> Result<Principal, VerifyError> {
+    signature.clone().verify_identity(sender, msg)?;
+
+    let (key, expires_at) = match meta {
+        Some(meta) => (meta.nonce.to_string(), window.check(meta)?),
+        // legacy signatures still carry their own ingress expiry,
+        // so an unexpired signature is unique for the window it can be replayed in
+        None if window.allow_legacy => legacy_key(signature)?,
+        None => return Err(VerifyError::MissingMeta),
+    };
+    if !guard.record(sender, &key, expires_at) {
+        return Err(VerifyError::Replayed);
+    }
+
+    Ok(sender)
+}
+
+#[cfg(test)]
+mod tests {
+    use ic_agent::Identity;
+    use yral_identity::ic_agent::sign_message;
+
+    use super::*;
+    use crate::test_util::{identity, meta, SeenKeys};
+
+    fn msg(meta: Option<RequestMeta>) -> Message {
+        let msg = Message::default().method_name("test_request".into());
+        match meta {
+            None => msg.args((10u64,)),
+            Some(meta) => msg.args((10u64, meta)),
+        }
+        .unwrap()
+    }
+
+    fn verify(
+        sender: Principal,
+        meta: Option<RequestMeta>,
+        signature: &Signature,
+        window: &ReplayWindow,
+        guard: &impl ReplayGuard,
+    ) -> Result<Principal, VerifyError> {
+        verify_signed_request(sender, msg(meta), meta.as_ref(), signature, window, guard)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+        let meta = meta(0);
+        let signature = sign_message(&signer, msg(Some(meta))).unwrap();
+
+        let verified = verify(
+            sender,
+            Some(meta),
+            &signature,
+            &ReplayWindow::default(),
+            &(),
+        );
+        assert_eq!(verified.unwrap(), sender);
+    }
+
+    #[test]
+    fn test_meta_is_signed() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+        let signed = meta(0);
+        let signature = sign_message(&signer, msg(Some(signed))).unwrap();
+        let other = RequestMeta {
+            nonce: signed.nonce + 1,
+            ..signed
+        };
+
+        let res = verify(
+            sender,
+            Some(other),
+            &signature,
+            &ReplayWindow::default(),
+            &(),
+        );
+        assert!(matches!(res, Err(VerifyError::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn test_outside_window() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+        let window = ReplayWindow::default();
+
+        for offset in [-(window.max_age.as_secs() as i64) - 60, 10 * 60] {
+            let meta = meta(offset);
+            let signature = sign_message(&signer, msg(Some(meta))).unwrap();
+            let res = verify(
+                sender,
+                Some(meta),
+                &signature,
+                &window,
+                &SeenKeys::default(),
+            );
+            assert!(matches!(res, Err(VerifyError::Stale)), "offset {offset}");
+        }
+    }
+
+    #[test]
+    fn test_clock_skew_tolerated() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+        let meta = meta(10);
+        let signature = sign_message(&signer, msg(Some(meta))).unwrap();
+
+        let res = verify(
+            sender,
+            Some(meta),
+            &signature,
+            &ReplayWindow::default(),
+            &(),
+        );
+        assert_eq!(res.unwrap(), sender);
+    }
+
+    #[test]
+    fn test_replayed() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+        let meta = meta(0);
+        let signature = sign_message(&signer, msg(Some(meta))).unwrap();
+        let window = ReplayWindow::default();
+        let guard = SeenKeys::default();
+
+        verify(sender, Some(meta), &signature, &window, &guard).unwrap();
+        let res = verify(sender, Some(meta), &signature, &window, &guard);
+        assert!(matches!(res, Err(VerifyError::Replayed)));
+    }
+
+    #[test]
+    fn test_kept_until_window_ends() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+        let meta = meta(0);
+        let signature = sign_message(&signer, msg(Some(meta))).unwrap();
+        let window = ReplayWindow::default();
+        let guard = SeenKeys::default();
+
+        verify(sender, Some(meta), &signature, &window, &guard).unwrap();
+        let issued_at = Duration::from_secs(meta.issued_at_secs);
+        assert_eq!(guard.expiries(), [issued_at + window.max_age]);
+    }
+
+    #[test]
+    fn test_legacy_request() {
+        let signer = identity(1);
+        let sender = signer.sender().unwrap();
+        let signature = sign_message(&signer, msg(None)).unwrap();
+
+        let res = verify(sender, None, &signature, &ReplayWindow::default(), &());
+        assert!(matches!(res, Err(VerifyError::MissingMeta)));
+
+        let window = ReplayWindow {
+            allow_legacy: true,
+            ..Default::default()
+        };
+        let guard = SeenKeys::default();
+        verify(sender, None, &signature, &window, &guard).unwrap();
+        let res = verify(sender, None, &signature, &window, &guard);
+        assert!(matches!(res, Err(VerifyError::Replayed)));
+
+        // kept until the signature expires, not for the replay window
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let [expires_at] = guard.expiries()[..] else {
+            panic!("one key should be recorded");
+        };
+        assert!(expires_at > now);
+    }
+}